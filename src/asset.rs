@@ -11,6 +11,7 @@ type Decimals = u8;
 #[derive(Debug, Clone, Copy, std::cmp::PartialEq, std::cmp::Eq, std::hash::Hash, FromPrimitive)]
 pub enum Domain {
     Binance = -1,
+    BinanceFutures = -2, // USDⓈ-M perpetuals
     Arbitrum = 42161,
 }
 
@@ -52,6 +53,7 @@ impl From<Decentralised> for Domains2 {
     }
 }
 
+#[derive(Debug, Clone, Copy, std::cmp::PartialEq, std::cmp::Eq, std::hash::Hash)]
 pub enum EVM {
     Arbitrum = 42161,
     Optimism = 10,
@@ -60,6 +62,21 @@ pub enum EVM {
     Ethereum = 1,
 }
 
+impl EVM {
+
+    // Target block (or slot) time; Arbitrum's sequencer produces blocks continuously
+    pub fn block_time_ms(&self) -> u64 {
+        match self {
+            EVM::Arbitrum => 250,
+            EVM::Optimism => 2000,
+            EVM::Polygon => 2000,
+            EVM::BSC => 3000,
+            EVM::Ethereum => 12000,
+        }
+    }
+
+}
+
 impl From<EVM> for Domains2 {
     fn from(value: EVM) -> Self {
         Domains2::Decentralised(Decentralised::EVM(value))
@@ -183,15 +200,19 @@ pub mod supported_assets {
     lazy_static::lazy_static! {
         pub static ref ETH: Asset = Asset::new("eth")
             .add_domain(Domain::Binance, "ETH", 0)
+            .add_domain(Domain::BinanceFutures, "ETH", 0)
             .add_domain(Domain::Arbitrum, "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE", 18);
         pub static ref WETH: Asset = Asset::new("eth")
             .add_domain(Domain::Binance, "ETH", 0)
+            .add_domain(Domain::BinanceFutures, "ETH", 0)
             .add_domain(Domain::Arbitrum, "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1", 18);
         pub static ref USDT: Asset = Asset::new("usdt")
             .add_domain(Domain::Binance, "USDT", 0)
+            .add_domain(Domain::BinanceFutures, "USDT", 0)
             .add_domain(Domain::Arbitrum, "0xFd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9", 6);
         pub static ref ARB: Asset = Asset::new("arb")
             .add_domain(Domain::Binance, "ARB", 0)
+            .add_domain(Domain::BinanceFutures, "ARB", 0)
             .add_domain(Domain::Arbitrum, "0x912CE59144191C1204E64559FE8253a0e49E6548", 18);
     }
 }
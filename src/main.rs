@@ -1,11 +1,45 @@
 mod quoters;
 mod asset;
+mod simulation;
 
-use quoters::binance::{BinanceQuoter, self};
+use quoters::binance::{BinanceQuoter, BinanceFuturesQuoter, BinanceEndpoints, BinanceFees, FeeTier, Liquidity, Market, self};
 use quoters::oneinch::OneInchQuoter;
 use quoters::crypto::UniV3Quoter;
-use quoters::Quoter;
-use asset::{Domain, supported_assets};
+use quoters::{Quoter, BPS};
+use asset::{Domain, Asset, EVM, supported_assets};
+use simulation::{
+    Simulator,
+    SimulationResult,
+    OrderOutcome,
+    UserOrder,
+    OrderKind,
+    SolverModel,
+    SolverAgent,
+    EscalationSchedule,
+    FeeUnit,
+    LinearSchedule,
+    ExponentialSchedule,
+    StepSchedule,
+    DutchAuctionSchedule,
+    Backtest,
+    DexQuote,
+    MarketSnapshot,
+    RecordedVenue,
+    SnapshotRecorder,
+    PathParams,
+    PricePathGenerator,
+    BookShape,
+    SyntheticDex,
+    SyntheticMarket,
+    Objective,
+    ParamGrid,
+    ScheduleOptimizer,
+    ScheduleParams,
+    OrderFlowGenerator,
+    PairFlow,
+    SizeDistribution,
+};
+use std::sync::Arc;
 
 
 #[tokio::main]
@@ -14,50 +48,49 @@ async fn main() -> eyre::Result<()> {
 
     // gen
     let loop_wait_ms = 2000;
-    const BPS: f64 = 10000.;
 
     // binance
     let book_depth = 200;
     let refresh_rate_ms = 100;
-    let binance_fee_bps = 7.5;
-
-    let binance_quoter = BinanceQuoter::create(
-        vec![
-            binance::suppported_markets::ETHUSDT, 
-            // binance::suppported_markets::BTCUSDT,
-            // binance::suppported_markets::ARBUSDT,
-        ],
-        book_depth,
-        refresh_rate_ms,
-    ).await?;
-
-    // 1inch
-    let domain = Domain::Arbitrum;
-    let connector_tokens = None; 
-    let complexity_level = Some(1);
-    let main_route_parts = None;
-    let parts = Some(10);
-
-    let oneinch_quoter = OneInchQuoter::create(
-        domain as u32,
-        connector_tokens,
-        complexity_level,
-        main_route_parts,
-        parts
-    ).await?;
-
-    // UniV3
-
-    let rpc_url = std::env::var("ARB_RPC_URL").unwrap();
-    let arb_eden_static_quoter = "0xc80f61d1bdAbD8f5285117e1558fDDf8C64870FE";
-    let chain_id = Domain::Arbitrum as u32;
-
-    let univ3_quoter = UniV3Quoter::create(
-        &rpc_url, 
-        &arb_eden_static_quoter.to_string(), 
-        chain_id
-    ).unwrap();
+    // e.g. the spot testnet, the live exchange otherwise
+    let default_endpoints = BinanceEndpoints::default();
+    let binance_endpoints = BinanceEndpoints {
+        stream: std::env::var("BINANCE_STREAM_ENDPOINT").unwrap_or(default_endpoints.stream),
+        api: std::env::var("BINANCE_API_ENDPOINT").unwrap_or(default_endpoints.api),
+    };
+    // comma separated tickers of markets on a zero-fee promotion
+    let zero_fee_markets = std::env::var("BINANCE_ZERO_FEE_MARKETS")
+        .map(|tickers| tickers.split(',').map(|ticker| ticker.trim().to_lowercase()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    // the account's VIP level, 0 for the regular tier
+    let fee_tier = match std::env::var("BINANCE_VIP_LEVEL") {
+        Ok(level) => FeeTier::from_vip_level(level.parse()?)?,
+        Err(_) => FeeTier::Regular,
+    };
+    // quotes cross the spread and pay taker fees
+    let binance_fees = BinanceFees::new(fee_tier, true)
+        .with_zero_fee_markets(zero_fee_markets.clone());
+    let binance_vip3_fees = BinanceFees::new(FeeTier::Vip3, true)
+        .with_zero_fee_markets(zero_fee_markets);
+    // perp hedges pay the account's futures rates, so they compare with spot
+    let binance_futures_fees = BinanceFees::futures(fee_tier, true);
 
+    // solver
+    let fill_gas_units = 400_000;
+    let gas_price_gwei = 0.1;
+    let risk_premium_bps = 2.;
+
+    // venue quotes are net of trading fees
+    let solver = SolverModel::new(
+        0.,
+        fill_gas_units,
+        gas_price_gwei,
+        &supported_assets::ETH,
+        risk_premium_bps,
+    );
+    // perp hedges pay or earn funding until they're unwound
+    let perp_holding_ms = 60 * 60_000;
+    let perp_solver = solver.clone().with_holding_ms(perp_holding_ms);
 
     // todo: make this in command-line args
     // trade
@@ -65,15 +98,554 @@ async fn main() -> eyre::Result<()> {
     // let sell_asset = &supported_assets::ARB;
     let buy_asset = &supported_assets::USDT;
     let sell_amount_fixed = 10.;
+    // DEX quotes are recorded at sizes around the order's. Backtests interpolate
+    // between them and won't price sizes far outside
+    let dex_quote_sizes = [sell_amount_fixed / 4., sell_amount_fixed, sell_amount_fixed * 4.];
+    let settlement_chain = EVM::Arbitrum;
 
-    let apply_binance_fee = |x: f64| {
-        x * (1. - binance_fee_bps/BPS)
+    // every market the order and its gas can be routed through, e.g. ETHBTC
+    // and BTCUSDT next to ETHUSDT
+    let order_assets: [&Asset; 3] = [sell_asset, buy_asset, &supported_assets::ETH];
+    let binance_assets = order_assets.iter()
+        .map(|asset| asset.get_domain_id(Domain::Binance))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let binance_markets = binance::route_markets(&binance::suppported_markets::ALL, &binance_assets);
+    // trading filters from a cached exchangeInfo response, live modes fetch them
+    let binance_markets = match std::env::var("BINANCE_EXCHANGE_INFO") {
+        Ok(path) => {
+            let tickers = binance_markets.iter().map(|market| market.ticker()).collect::<Vec<_>>();
+            binance::load_markets(&path, &tickers)?
+        },
+        Err(_) => binance_markets,
+    };
+
+    // escalation
+    let schedules: Vec<Arc<dyn EscalationSchedule>> = vec![
+        Arc::new(LinearSchedule::new(-5., 0.5, FeeUnit::Bps)),
+        Arc::new(ExponentialSchedule::new(1., 0.05, FeeUnit::Bps)),
+        Arc::new(StepSchedule::new(vec![(0, 0.), (20, 5.), (40, 10.), (80, 20.)], FeeUnit::Bps)?),
+        Arc::new(DutchAuctionSchedule::new(-5., 30., 120, FeeUnit::Bps)),
+        Arc::new(LinearSchedule::new(0., 0.25, FeeUnit::Absolute)),
+    ];
+    let order = UserOrder::new(
+        settlement_chain,
+        sell_asset,
+        buy_asset,
+        sell_amount_fixed,
+        schedules[0].clone(),
+    );
+    let orders = schedules.iter()
+        .map(|schedule| order.with_schedule(schedule.clone()))
+        .collect::<Vec<_>>();
+
+    let mut args = std::env::args().skip(1);
+    let mode = args.next().unwrap_or(String::from("monitor"));
+    match mode.as_str() {
+        "monitor" => {
+            let recorder = args.next()
+                .map(|path| SnapshotRecorder::create(&path))
+                .transpose()?;
+            // what resting at the touch would save, quotes aren't priced that way
+            for market in binance_markets.iter() {
+                println!("{} fees: {:.2} bps taker, {:.2} bps maker",
+                    market.ticker(), binance_fees.taker_fee_bps(market), binance_fees.fee_bps(market, Liquidity::Maker)
+                );
+            }
+            let quoters = LiveQuoters::create(&binance_endpoints, binance_markets, book_depth, refresh_rate_ms, &binance_fees, &binance_vip3_fees, &binance_futures_fees).await?;
+            monitor(
+                &quoters,
+                &order,
+                &solver,
+                &perp_solver,
+                &dex_quote_sizes,
+                loop_wait_ms,
+                recorder,
+            ).await
+        },
+        "simulate" => {
+            let max_blocks = 240;
+
+            let quoters = LiveQuoters::create(&binance_endpoints, binance_markets, book_depth, refresh_rate_ms, &binance_fees, &binance_vip3_fees, &binance_futures_fees).await?;
+            let solvers = make_solvers::<&(dyn Quoter + Sync)>(
+                &quoters.binance,
+                &quoters.binance_vip3,
+                &quoters.univ3,
+                &quoters.oneinch,
+                &solver,
+            );
+            let benchmark_quoters: [(&str, &(dyn Quoter + Sync)); 2] = [
+                ("oneinch", &quoters.oneinch),
+                ("univ3", &quoters.univ3),
+            ];
+            let simulator = Simulator::new(max_blocks);
+            let (oneinch_outcomes, univ3_outcomes) = futures::future::join(
+                simulator.run(&orders, "oneinch", &benchmark_quoters, &solvers),
+                simulator.run(&orders, "univ3", &benchmark_quoters, &solvers),
+            ).await;
+            for (reference, outcomes) in [("OneInch", oneinch_outcomes?), ("UniV3", univ3_outcomes?)] {
+                println!("Reference: {reference}");
+                for (order, outcome) in orders.iter().zip(outcomes) {
+                    print_simulation_result(order, &outcome);
+                }
+                println!();
+            }
+            Ok(())
+        },
+        "backtest" => {
+            let snapshots_path = args.next()
+                .ok_or(eyre::eyre!("Usage: backtest <snapshots.ndjson> [sell|buy]"))?;
+            let kind = args.next().unwrap_or(String::from("sell"));
+            let max_blocks = 240;
+            let order_spacing = 1;
+            // buys receive exact amounts of the buy asset, priced from the same recorded quotes
+            let (order_sizes, orders) = match kind.as_str() {
+                "sell" => (vec![2.5, 5., 10., 20.], orders),
+                "buy" => {
+                    let orders = orders.iter()
+                        .map(|order| UserOrder::new_buy(
+                            order.chain,
+                            &order.sell_asset,
+                            &order.buy_asset,
+                            order.amount,
+                            order.schedule.clone(),
+                        ))
+                        .collect::<Vec<_>>();
+                    (vec![5_000., 10_000., 20_000., 40_000.], orders)
+                },
+                _ => return Err(eyre::eyre!(format!("Unknown order kind {kind}"))),
+            };
+
+            let snapshots = simulation::load_snapshots(&snapshots_path)?;
+            let backtest = Backtest::new(snapshots, binance_markets.into(), "oneinch", max_blocks);
+            println!("Loaded {} snapshots", backtest.snapshot_count());
+            let solvers = make_solvers(
+                RecordedVenue::Binance(binance_fees.clone()),
+                RecordedVenue::Binance(binance_vip3_fees.clone()),
+                RecordedVenue::Dex(String::from("univ3")),
+                RecordedVenue::Dex(String::from("oneinch")),
+                &solver,
+            );
+            for order in orders.iter() {
+                let backtest_orders = backtest.make_orders(order, &order_sizes, order_spacing);
+                let report = backtest.run(&backtest_orders, &solvers).await?;
+                println!("{}", order.schedule.name());
+                println!("{report}");
+                println!();
+            }
+            Ok(())
+        },
+        "flow" => {
+            let snapshots_path = args.next()
+                .ok_or(eyre::eyre!("Usage: flow <snapshots.ndjson> [seed] [sizes.csv] [fit|resample]"))?;
+            let seed = args.next().map(|seed| seed.parse::<u64>()).transpose()?.unwrap_or(0);
+            let sizes_path = args.next();
+            let size_model = args.next().unwrap_or(String::from("fit"));
+            let max_blocks = 240;
+            let arrival_rate = 0.5; // orders per second
+            let sizes = match sizes_path {
+                Some(path) => {
+                    let sizes = simulation::load_sizes_csv(&path, 0)?;
+                    match size_model.as_str() {
+                        "fit" => SizeDistribution::fit_log_normal(&sizes)?,
+                        "resample" => SizeDistribution::Empirical(sizes),
+                        _ => return Err(eyre::eyre!(format!("Unknown size model {size_model}"))),
+                    }
+                },
+                None => SizeDistribution::log_normal(sell_amount_fixed, 1.),
+            };
+            println!("Order sizes: {sizes:?}");
+            let pairs = vec![
+                PairFlow {
+                    sell_asset: Asset::clone(sell_asset),
+                    buy_asset: Asset::clone(buy_asset),
+                    weight: 1.,
+                    sizes,
+                },
+                // ARB -> USDT needs ARBUSDT books and DEX quotes in the snapshots
+            ];
+
+            let snapshots = simulation::load_snapshots(&snapshots_path)?;
+            let backtest = Backtest::new(snapshots, binance_markets.into(), "oneinch", max_blocks);
+            let (start_ms, end_ms) = backtest.time_range()
+                .ok_or(eyre::eyre!("No snapshots to run the order flow on"))?;
+            let solvers = make_solvers(
+                RecordedVenue::Binance(binance_fees.clone()),
+                RecordedVenue::Binance(binance_vip3_fees.clone()),
+                RecordedVenue::Dex(String::from("univ3")),
+                RecordedVenue::Dex(String::from("oneinch")),
+                &solver,
+            );
+            for schedule in schedules.iter() {
+                let flow = OrderFlowGenerator::new(arrival_rate, pairs.clone(), settlement_chain, schedule.clone(), seed)
+                    .generate(start_ms, end_ms - start_ms)?;
+                let report = backtest.run_flow(&flow, &solvers).await?;
+                println!("{}", schedule.name());
+                println!("{report}");
+                println!();
+            }
+            Ok(())
+        },
+        "optimize" => {
+            let snapshots_path = args.next()
+                .ok_or(eyre::eyre!("Usage: optimize <snapshots.ndjson> [target_fill_rate] [within_blocks]"))?;
+            let target_fill_rate = args.next().map(|rate| rate.parse::<f64>()).transpose()?.unwrap_or(0.95);
+            let within_blocks = args.next().map(|blocks| blocks.parse::<u32>()).transpose()?.unwrap_or(120);
+            let order_sizes = [2.5, 5., 10., 20.];
+            let order_spacing = 10;
+            let grid = ParamGrid {
+                start: vec![-10., -5., -2., 0., 2.],
+                rate: vec![0.05, 0.1, 0.25, 0.5, 1.],
+                cap: vec![5., 10., 20., 40.],
+            };
+            let search_step = ScheduleParams { start: 1., rate: 0.05, cap: 2.5 };
+            let search_rounds = 5;
+
+            let snapshots = simulation::load_snapshots(&snapshots_path)?;
+            let backtest = Backtest::new(snapshots, binance_markets.into(), "oneinch", within_blocks);
+            println!("Loaded {} snapshots", backtest.snapshot_count());
+            let solvers = make_solvers(
+                RecordedVenue::Binance(binance_fees.clone()),
+                RecordedVenue::Binance(binance_vip3_fees.clone()),
+                RecordedVenue::Dex(String::from("univ3")),
+                RecordedVenue::Dex(String::from("oneinch")),
+                &solver,
+            );
+            let objective = Objective { target_fill_rate, within_blocks };
+            let backtest_orders = backtest.make_orders(&order, &order_sizes, order_spacing);
+            let optimizer = ScheduleOptimizer::new(&backtest, backtest_orders, &solvers, objective);
+
+            let evaluations = optimizer.grid_search(&grid).await?;
+            println!("Grid search ({} candidates), top 5:", evaluations.len());
+            for evaluation in evaluations.iter().take(5) {
+                println!("\t{evaluation}");
+            }
+            let best = optimizer.coordinate_search(evaluations[0].clone(), search_step, search_rounds).await?;
+            let feasibility = if best.is_feasible(&objective) { "meets" } else { "misses" };
+            println!("Best ({feasibility} {:.2}% within {within_blocks} blocks):", target_fill_rate * 100.);
+            println!("\t{best}");
+            Ok(())
+        },
+        "replay" => {
+            let capture_path = args.next()
+                .ok_or(eyre::eyre!("Usage: replay <capture.ndjson.gz> [until_ms] [speed]"))?;
+            let until_ms = args.next().map(|ms| ms.parse::<u64>()).transpose()?.unwrap_or(u64::MAX);
+            // times the original pace, as fast as possible by default
+            let speed = args.next().map(|speed| speed.parse::<f64>()).transpose()?.unwrap_or(f64::INFINITY);
+            let mut replay = binance::BinanceReplay::open(&capture_path)?;
+            replay.play(until_ms, speed, |_| ()).await?;
+            let snapshot = replay.snapshot();
+            let end = if replay.is_done() { " (end of capture)" } else { "" };
+            println!("Books at {}ms{end}", replay.now_ms());
+            for ticker in replay.tickers() {
+                let book = replay.get_book(&ticker)?;
+                let best_bid = book.bids().next().map(|level| level.price);
+                let best_ask = book.asks().next().map(|level| level.price);
+                println!("\t{ticker}: bid {best_bid:?}, ask {best_ask:?}");
+            }
+            let markets = binance_markets.into();
+            let quoter = binance::BinanceSnapshotQuoter::new(&snapshot, &markets).with_fees(binance_fees.clone());
+            match quoter.get_amount_out(sell_asset, buy_asset, sell_amount_fixed).await {
+                Ok(amount_out) => println!("\tBinance: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id),
+                Err(e) => println!("Error: {}", e),
+            }
+            Ok(())
+        },
+        "synthetic" => {
+            let out_path = args.next()
+                .ok_or(eyre::eyre!("Usage: synthetic <out.ndjson> [recorded.ndjson] [seed]"))?;
+            let recorded_path = args.next();
+            let seed = args.next().map(|seed| seed.parse::<u64>()).transpose()?.unwrap_or(0);
+            let synthetic_steps = 5000;
+            let jump_threshold = 5.;
+            let volatility_multiplier = 2.;
+            let jump_multiplier = 2.;
+            let market = binance::Markets::from(binance_markets.clone())
+                .get(sell_asset.get_domain_id(Domain::Binance)?, buy_asset.get_domain_id(Domain::Binance)?)
+                .cloned()
+                .ok_or(eyre::eyre!("No Binance market between the order's assets"))?;
+
+            let (params, start_price) = match recorded_path {
+                Some(path) => {
+                    let mids = simulation::mid_prices(&simulation::load_snapshots(&path)?, &market);
+                    let params = PathParams::calibrate(&mids, jump_threshold)?;
+                    (params, mids.last().unwrap().1)
+                },
+                None => {
+                    let params = PathParams {
+                        drift: 0.,
+                        volatility: 0.0002,
+                        jump_intensity: 0.001,
+                        jump_mean: 0.,
+                        jump_std: 0.003,
+                    };
+                    (params, 2000.)
+                },
+            };
+            let params = params.stressed(volatility_multiplier, jump_multiplier);
+            println!("Path params: {params:?}");
+            let path = PricePathGenerator::new(params, seed)
+                .generate(start_price, loop_wait_ms, synthetic_steps)?;
+
+            let synthetic_market = SyntheticMarket {
+                market,
+                sell_asset: Asset::clone(sell_asset),
+                buy_asset: Asset::clone(buy_asset),
+                quote_sizes: dex_quote_sizes.to_vec(),
+                book: BookShape {
+                    half_spread_bps: 0.05,
+                    level_step_bps: 0.1,
+                    levels: book_depth as usize,
+                    level_qty: 2.,
+                    qty_growth: 0.02,
+                },
+                dexes: vec![
+                    SyntheticDex { venue: String::from("oneinch"), cost_bps: 6., lag_steps: 1 },
+                    SyntheticDex { venue: String::from("univ3"), cost_bps: 8., lag_steps: 1 },
+                ],
+            };
+            let snapshots = synthetic_market.snapshots(&path, 0, loop_wait_ms);
+            let mut recorder = SnapshotRecorder::create(&out_path)?;
+            for snapshot in snapshots.iter() {
+                recorder.record(snapshot)?;
+            }
+            println!("Wrote {} snapshots to {out_path}", snapshots.len());
+            Ok(())
+        },
+        _ => Err(eyre::eyre!(format!("Unknown mode {mode}"))),
+    }
+}
+
+struct LiveQuoters {
+    binance: BinanceQuoter,
+    binance_vip3: BinanceQuoter, // shares the books
+    binance_futures: Option<BinanceFuturesQuoter>, // spot quoting works without it
+    oneinch: OneInchQuoter,
+    univ3: UniV3Quoter,
+}
+
+impl LiveQuoters {
+
+    async fn create(
+        binance_endpoints: &BinanceEndpoints,
+        binance_markets: Vec<Market>,
+        book_depth: u32,
+        refresh_rate_ms: u32,
+        binance_fees: &BinanceFees,
+        binance_vip3_fees: &BinanceFees,
+        binance_futures_fees: &BinanceFees,
+    ) -> eyre::Result<Self> {
+        let tickers = binance_markets.iter().map(|market| market.ticker()).collect::<Vec<_>>();
+        let binance_markets = BinanceQuoter::fetch_markets(binance_endpoints, &tickers).await?;
+        // 5, 10 or 20 to follow only the top of the books from partial depth streams,
+        // and a path to capture the stream to, for `replay`
+        let binance = match (std::env::var("BINANCE_PARTIAL_DEPTH"), std::env::var("BINANCE_CAPTURE")) {
+            (Ok(book_size), Ok(capture_path)) => {
+                BinanceQuoter::create_partial_recording(binance_endpoints, binance_markets, book_size.parse()?, refresh_rate_ms, &capture_path).await?
+            },
+            (Ok(book_size), _) => BinanceQuoter::create_partial(binance_endpoints, binance_markets, book_size.parse()?, refresh_rate_ms).await?,
+            (_, Ok(capture_path)) => BinanceQuoter::create_recording(binance_endpoints, binance_markets, book_depth, refresh_rate_ms, &capture_path).await?,
+            _ => BinanceQuoter::create(binance_endpoints, binance_markets, book_depth, refresh_rate_ms).await?,
+        }.with_fees(binance_fees.clone());
+        let binance_vip3 = binance.with_fees(binance_vip3_fees.clone());
+
+        // Binance USDⓈ-M perpetuals
+        let default_endpoints = BinanceEndpoints::futures();
+        let binance_futures_endpoints = BinanceEndpoints {
+            stream: std::env::var("BINANCE_FUTURES_STREAM_ENDPOINT").unwrap_or(default_endpoints.stream),
+            api: std::env::var("BINANCE_FUTURES_API_ENDPOINT").unwrap_or(default_endpoints.api),
+        };
+        // perp hedges have to pass the same lot and notional checks as spot ones
+        let binance_futures_tickers = vec![binance::supported_futures_markets::ETHUSDT.ticker()];
+        let binance_futures = match async {
+            let markets = BinanceFuturesQuoter::fetch_markets(&binance_futures_endpoints, &binance_futures_tickers).await?;
+            BinanceFuturesQuoter::create(&binance_futures_endpoints, markets, book_depth, refresh_rate_ms).await
+        }.await {
+            Ok(quoter) => Some(quoter.with_fees(binance_futures_fees.clone())),
+            Err(e) => {
+                println!("Error creating the Binance futures quoter, quoting spot only: {e}");
+                None
+            },
+        };
+
+        // 1inch
+        let domain = Domain::Arbitrum;
+        let connector_tokens = None;
+        let complexity_level = Some(1);
+        let main_route_parts = None;
+        let parts = Some(10);
+
+        let oneinch = OneInchQuoter::create(
+            domain as u32,
+            connector_tokens,
+            complexity_level,
+            main_route_parts,
+            parts
+        ).await?;
+
+        // UniV3
+        let rpc_url = std::env::var("ARB_RPC_URL")
+            .map_err(|_| eyre::eyre!("ARB_RPC_URL is not set"))?;
+        let arb_eden_static_quoter = "0xc80f61d1bdAbD8f5285117e1558fDDf8C64870FE";
+        let chain_id = Domain::Arbitrum as u32;
+
+        let univ3 = UniV3Quoter::create(
+            &rpc_url,
+            arb_eden_static_quoter,
+            chain_id
+        )?;
+
+        Ok(Self { binance, binance_vip3, binance_futures, oneinch, univ3 })
+    }
+
+}
+
+// Fast CEX hedgers on different fee tiers competing with slower on-chain hedgers
+fn make_solvers<V: Clone>(
+    binance: V,
+    binance_vip3: V,
+    univ3: V,
+    oneinch: V,
+    base_model: &SolverModel,
+) -> Vec<SolverAgent<V>> {
+    let with_costs = |venue_fee_bps: f64, gas_multiplier: u64, risk_premium_bps: f64| {
+        SolverModel {
+            venue_fee_bps,
+            gas_units: base_model.gas_units * gas_multiplier,
+            risk_premium_bps,
+            ..base_model.clone()
+        }
+    };
+    vec![
+        SolverAgent::new("binance-vip0", binance, base_model.clone(), 50, 1.),
+        SolverAgent::new("binance-vip3", binance_vip3, base_model.clone(), 20, 2.),
+        SolverAgent::new("univ3", univ3, with_costs(0., 2, 0.), 250, 0.5),
+        SolverAgent::new("oneinch", oneinch, with_costs(0., 3, 0.), 500, 0.5),
+    ]
+}
+
+fn print_simulation_result(order: &UserOrder, outcome: &OrderOutcome) {
+    let quoted_asset = order.quoted_asset();
+    println!("\t{}", order.schedule.name());
+    match &outcome.result {
+        SimulationResult::Filled { solver, bidders, fill } => {
+            println!("\t\tFilled by {} after {} ms (block {}, {} bidders)", solver, fill.elapsed_ms, fill.step, bidders);
+            println!("\t\tFee paid: {:.2} bps ({:.4} {})", fill.fee_bps, fill.fee_amount, quoted_asset.id);
+            match order.kind {
+                OrderKind::Sell => println!("\t\tUser received: {:.4} {}", fill.user_amount, quoted_asset.id),
+                OrderKind::Buy => println!("\t\tUser paid: {:.4} {}", fill.user_amount, quoted_asset.id),
+            }
+            println!("\t\tSolver profit: {:.4} {}", fill.solver_profit(), quoted_asset.id);
+            println!("\t\tSolver breakeven fee: {:.2} bps", fill.breakeven_fee_bps());
+            for benchmark in outcome.benchmarks.iter() {
+                println!("\t\tPrice improvement vs {}: {:.2} bps",
+                    benchmark.venue, benchmark.price_improvement_bps(fill.user_amount)
+                );
+            }
+        },
+        SimulationResult::Expired { steps } => {
+            println!("\t\tExpired unfilled after {steps} blocks");
+        },
+        SimulationResult::Censored { steps } => {
+            println!("\t\tUnsettled after {steps} blocks when the data ran out");
+        },
+    }
+}
+
+// DEX quotes are recorded for every size in `dex_quote_sizes`
+async fn monitor(
+    quoters: &LiveQuoters,
+    order: &UserOrder,
+    solver: &SolverModel,
+    perp_solver: &SolverModel,
+    dex_quote_sizes: &[f64],
+    loop_wait_ms: u64,
+    mut recorder: Option<SnapshotRecorder>,
+) -> eyre::Result<()> {
+    if order.kind != OrderKind::Sell {
+        return Err(eyre::eyre!("The monitor quotes sell orders"));
+    }
+    let (sell_asset, buy_asset, sell_amount_fixed) = (&order.sell_asset, &order.buy_asset, order.amount);
+
+    let make_dex_quote = |venue: &str, sell_amount: f64, amount_out: f64| DexQuote {
+        venue: venue.to_string(),
+        sell_asset: sell_asset.id.clone(),
+        buy_asset: buy_asset.id.clone(),
+        sell_amount,
+        amount_out,
     };
 
     loop {
-        let oneinch_amount_out = match oneinch_quoter.get_amount_out(&sell_asset, &buy_asset, sell_amount_fixed).await {
+        match solver.quote(&quoters.binance, order).await {
+            Ok(solver_quote) => {
+                println!("\tBinance: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, solver_quote.hedge_amount, buy_asset.id);
+                println!("\t\tSolver net: {:.2} {} (fee {:.2}, gas {:.2}, risk {:.2})",
+                    solver_quote.net_amount(), buy_asset.id, solver_quote.venue_fee, solver_quote.gas_cost, solver_quote.risk_premium
+                );
+            },
+            Err(e) => {
+                println!("Error: {}", e);
+            },
+        };
+        // the same order hedged on perps, with the funding it pays or earns
+        if let Some(binance_futures) = quoters.binance_futures.as_ref() {
+            match perp_solver.quote(binance_futures, order).await {
+                Ok(solver_quote) => {
+                    println!("\tBinance perp: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, solver_quote.hedge_amount, buy_asset.id);
+                    println!("\t\tSolver net: {:.2} {} (fee {:.2}, gas {:.2}, risk {:.2}, funding {:.2})",
+                        solver_quote.net_amount(), buy_asset.id, solver_quote.venue_fee, solver_quote.gas_cost, solver_quote.risk_premium, solver_quote.funding_cost
+                    );
+                },
+                Err(e) => {
+                    println!("Error: {}", e);
+                },
+            };
+            for ticker in binance_futures.tickers() {
+                match binance_futures.health(ticker) {
+                    Ok(binance::BookHealth::Live) => (),
+                    Ok(health) => println!("\t\t{ticker} perp book is {health}"),
+                    Err(e) => println!("Error: {}", e),
+                }
+                if let Ok(Some(funding)) = binance_futures.funding(ticker) {
+                    println!("\t\t{ticker} perp mark: {:.2}, funding rate: {:.4}%", funding.mark_price, funding.rate * 100.);
+                }
+            }
+        }
+        for ticker in quoters.binance.markets.tickers.iter() {
+            if let (Ok(Some(bbo)), Ok(Some(last_trade)), Ok(flow)) = (
+                quoters.binance.bbo(ticker),
+                quoters.binance.last_trade(ticker),
+                quoters.binance.trade_flow(ticker, 10_000),
+            ) {
+                println!("\t\t{ticker} bbo: {:.2}/{:.2}, last trade: {:.2}, 10s vwap: {:.2}, 10s signed volume: {:.4}",
+                    bbo.bid_price, bbo.ask_price, last_trade.price, flow.vwap.unwrap_or(last_trade.price), flow.signed_volume
+                );
+            }
+            if let Ok(stats) = quoters.binance.book_stats(ticker, 10.) {
+                println!("\t\t{ticker} book: {stats}");
+            }
+            if let Ok(curve) = quoters.binance.impact_curve(ticker, binance::SwapType::Sell, &[1., 10., 100.]) {
+                let curve = curve.iter()
+                    .map(|point| format!("{} @ {:.2} bps", point.base_amount, point.impact_bps))
+                    .collect::<Vec<_>>();
+                println!("\t\t{ticker} sell impact: {}", curve.join(", "));
+            }
+        }
+        // the pools are read at the latest block and the books are taken as of
+        // when the RPC returned it, on the local clock the books are kept by
+        let (univ3_block, univ3_quoted_ms) = match quoters.univ3.latest_block().await {
+            Ok(block) => block,
+            Err(e) => {
+                println!("Error reading the latest block, not recording: {e}");
+                tokio::time::sleep(std::time::Duration::from_millis(loop_wait_ms)).await;
+                continue;
+            },
+        };
+        // 1inch has no block to pin its quote to, so it's taken right after the block is read
+        let mut dex_quotes = Vec::new();
+        let oneinch_amount_out = match quoters.oneinch.get_amount_out(sell_asset, buy_asset, sell_amount_fixed).await {
             Ok(amount_out) => {
                 println!("\tOneInch: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id);
+                dex_quotes.push(make_dex_quote("oneinch", sell_amount_fixed, amount_out));
                 amount_out
             },
             Err(e) => {
@@ -81,19 +653,38 @@ async fn main() -> eyre::Result<()> {
                 0.
             },
         };
-        let binance_amount_out = match binance_quoter.get_amount_out(&sell_asset, &buy_asset, sell_amount_fixed).await {
+        let univ3_amount_out = match quoters.univ3.get_amount_out_at(sell_asset, buy_asset, sell_amount_fixed, univ3_block).await {
             Ok(amount_out) => {
-                println!("\tBinance: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id);
-                apply_binance_fee(amount_out)
+                println!("\tUniV3: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id);
+                dex_quotes.push(make_dex_quote("univ3", sell_amount_fixed, amount_out));
+                amount_out
             },
             Err(e) => {
                 println!("Error: {}", e);
                 0.
             },
         };
-        let univ3_amount_out = match univ3_quoter.get_amount_out(&sell_asset, &buy_asset, sell_amount_fixed).await {
+        // the other sizes are only recorded
+        for sell_amount in dex_quote_sizes.iter().copied().filter(|sell_amount| *sell_amount != sell_amount_fixed) {
+            match quoters.oneinch.get_amount_out(sell_asset, buy_asset, sell_amount).await {
+                Ok(amount_out) => dex_quotes.push(make_dex_quote("oneinch", sell_amount, amount_out)),
+                Err(e) => println!("Error: {}", e),
+            }
+            match quoters.univ3.get_amount_out_at(sell_asset, buy_asset, sell_amount, univ3_block).await {
+                Ok(amount_out) => dex_quotes.push(make_dex_quote("univ3", sell_amount, amount_out)),
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+        let binance_snapshot = quoters.binance.snapshot_at(univ3_quoted_ms);
+        let binance_at_univ3 = quoters.binance.query_at(
+            sell_asset.get_domain_id(Domain::Binance)?,
+            buy_asset.get_domain_id(Domain::Binance)?,
+            sell_asset.convert_from_zero(Domain::Binance, sell_amount_fixed)?,
+            univ3_quoted_ms,
+        ).await.and_then(|amount_out| buy_asset.convert_to_zero(Domain::Binance, amount_out));
+        let binance_amount_out = match binance_at_univ3 {
             Ok(amount_out) => {
-                println!("\tUniV3: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id);
+                println!("\tBinance at the UniV3 quote: {:.2} {} -> {:.2} {}", sell_amount_fixed, sell_asset.id, amount_out, buy_asset.id);
                 amount_out
             },
             Err(e) => {
@@ -101,11 +692,41 @@ async fn main() -> eyre::Result<()> {
                 0.
             },
         };
+        // what quoting the latest books instead would have biased the comparison by
+        let latest_snapshot = quoters.binance.snapshot();
+        for (ticker, book) in binance_snapshot.books.iter() {
+            let mids = (book.mid_price(), latest_snapshot.books.get(ticker).and_then(|book| book.mid_price()));
+            if let (Some(mid_then), Some(mid_now)) = mids {
+                println!("\t\t{ticker} mid moved {:.2} bps since the UniV3 quote", (mid_now / mid_then - 1.) * BPS);
+            }
+        }
         println!("binance_amount_out/one_inch_amount_out: {:.2} bps", (1.-binance_amount_out/oneinch_amount_out)*BPS);
         println!("binance_amount_out/univ3_amount_out: {:.2} bps", (1.-binance_amount_out/univ3_amount_out)*BPS);
         println!();
+
+        // books that aren't live would record prices the market has moved away from
+        let unhealthy_books = quoters.binance.markets.tickers.iter()
+            .filter_map(|ticker| match quoters.binance.health(ticker) {
+                Ok(binance::BookHealth::Live) if !binance_snapshot.books.contains_key(ticker) => {
+                    Some(format!("{ticker} has no book at {univ3_quoted_ms}ms"))
+                },
+                Ok(binance::BookHealth::Live) => None,
+                Ok(health) => Some(format!("{ticker} {health}")),
+                Err(e) => Some(format!("{ticker}: {e}")),
+            })
+            .collect::<Vec<_>>();
+        if !unhealthy_books.is_empty() {
+            println!("Not recording: {}", unhealthy_books.join(", "));
+        } else if let Some(recorder) = recorder.as_mut() {
+            let snapshot = MarketSnapshot {
+                timestamp_ms: binance_snapshot.timestamp_ms,
+                binance: binance_snapshot,
+                dex_quotes,
+            };
+            if let Err(e) = recorder.record(&snapshot) {
+                println!("Error recording snapshot: {e}");
+            }
+        }
         tokio::time::sleep(std::time::Duration::from_millis(loop_wait_ms)).await;
     }
-
-    Ok(())
 }
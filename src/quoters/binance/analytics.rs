@@ -0,0 +1,189 @@
+use super::*;
+use order_book::SwapType;
+
+
+// Cost of taking a size from one side of the book, relative to the mid price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpactPoint {
+    pub base_amount: f64,
+    pub avg_price: f64,
+    pub impact_bps: f64, // always positive, the taker pays it
+}
+
+// Top of book and depth around the mid price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookStats {
+    pub mid_price: f64,
+    pub spread_bps: f64,
+    pub microprice: f64,
+    pub bid_depth: f64, // base within `within_bps` of the mid price
+    pub ask_depth: f64,
+    pub imbalance: Option<f64>, // -1 (only asks) to 1 (only bids), none without depth in the band
+}
+
+impl std::fmt::Display for BookStats {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let imbalance = self.imbalance
+            .map(|imbalance| format!("{imbalance:.2}"))
+            .unwrap_or(String::from("-"));
+        write!(
+            f,
+            "mid {:.2}, spread {:.2} bps, microprice {:.2}, depth {:.4}/{:.4}, imbalance {}",
+            self.mid_price, self.spread_bps, self.microprice, self.bid_depth, self.ask_depth, imbalance
+        )
+    }
+
+}
+
+impl BinanceOrderBook {
+
+    pub fn spread(&self) -> Option<f64> {
+        let best_bid = self.bids().next()?.price;
+        let best_ask = self.asks().next()?.price;
+        Some(best_ask - best_bid)
+    }
+
+    pub fn spread_bps(&self) -> Option<f64> {
+        Some(self.spread()? / self.mid_price()? * BPS)
+    }
+
+    // Mid price weighted towards the side with less size at the top, where the
+    // next trade is more likely to move the price
+    pub fn microprice(&self) -> Option<f64> {
+        let best_bid = self.bids().next()?;
+        let best_ask = self.asks().next()?;
+        let total_qty = best_bid.qty + best_ask.qty;
+        if total_qty <= 0. {
+            return self.mid_price();
+        }
+        Some((best_bid.price * best_ask.qty + best_ask.price * best_bid.qty) / total_qty)
+    }
+
+    // Base and quote resting within `within_bps` of the mid price on the side
+    // a swap takes from
+    pub fn depth_within_bps(&self, swap_type: SwapType, within_bps: f64) -> (f64, f64) {
+        let Some(mid_price) = self.mid_price() else {
+            return (0., 0.);
+        };
+        let levels: Box<dyn Iterator<Item = _>> = match swap_type {
+            SwapType::Sell => Box::new(self.bids()),
+            SwapType::Buy => Box::new(self.asks()),
+        };
+        // levels exactly at the limit are included despite float noise
+        levels
+            .take_while(|level| (level.price - mid_price).abs() / mid_price * BPS <= within_bps + 1e-9)
+            .fold((0., 0.), |(base, quote), level| (base + level.qty, quote + level.qty * level.price))
+    }
+
+    // Positive when more base is bid than offered within `within_bps`
+    pub fn imbalance(&self, within_bps: f64) -> Option<f64> {
+        let (bid_depth, _) = self.depth_within_bps(SwapType::Sell, within_bps);
+        let (ask_depth, _) = self.depth_within_bps(SwapType::Buy, within_bps);
+        let total_depth = bid_depth + ask_depth;
+        if total_depth <= 0. {
+            return None;
+        }
+        Some((bid_depth - ask_depth) / total_depth)
+    }
+
+    // Impact of taking each size; stops at the first size the book can't fill
+    pub fn impact_curve(&self, swap_type: SwapType, base_amounts: &[f64]) -> Vec<ImpactPoint> {
+        let Some(mid_price) = self.mid_price() else {
+            return Vec::new();
+        };
+        base_amounts.iter()
+            .map(|base_amount| (*base_amount, self.query_exact_base(swap_type, *base_amount)))
+            .take_while(|(base_amount, (base_used, _))| base_used == base_amount && *base_amount > 0.)
+            .map(|(base_amount, (_, quote))| {
+                let avg_price = quote / base_amount;
+                let impact = match swap_type {
+                    SwapType::Sell => mid_price - avg_price,
+                    SwapType::Buy => avg_price - mid_price,
+                };
+                ImpactPoint { base_amount, avg_price, impact_bps: impact / mid_price * BPS }
+            })
+            .collect()
+    }
+
+    pub fn stats(&self, within_bps: f64) -> Option<BookStats> {
+        let (bid_depth, _) = self.depth_within_bps(SwapType::Sell, within_bps);
+        let (ask_depth, _) = self.depth_within_bps(SwapType::Buy, within_bps);
+        Some(BookStats {
+            mid_price: self.mid_price()?,
+            spread_bps: self.spread_bps()?,
+            microprice: self.microprice()?,
+            bid_depth,
+            ask_depth,
+            imbalance: self.imbalance(within_bps),
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1999/2001 with more size bid than offered at the top
+    fn make_book() -> BinanceOrderBook {
+        BinanceOrderBook::from_levels(
+            10,
+            0.01,
+            0,
+            vec![(1999., 3.), (1998., 2.), (1990., 10.)],
+            vec![(2001., 1.), (2002., 2.), (2010., 10.)],
+        )
+    }
+
+    #[test]
+    fn test_top_of_book() {
+        let book = make_book();
+        assert_eq!(book.spread(), Some(2.));
+        assert_eq!(book.spread_bps(), Some(10.));
+        // closer to the ask, which has less size
+        assert_eq!(book.microprice(), Some((1999. * 1. + 2001. * 3.) / 4.));
+        assert_eq!(BinanceOrderBook::default().spread(), None);
+        assert_eq!(BinanceOrderBook::default().stats(10.), None);
+    }
+
+    #[test]
+    fn test_depth_and_imbalance() {
+        let book = make_book();
+        // 10 bps of 2000 reaches 1998 and 2002
+        assert_eq!(book.depth_within_bps(SwapType::Sell, 10.), (5., 1999. * 3. + 1998. * 2.));
+        assert_eq!(book.depth_within_bps(SwapType::Buy, 10.), (3., 2001. + 2002. * 2.));
+        assert_eq!(book.depth_within_bps(SwapType::Buy, 1.), (0., 0.));
+        assert_eq!(book.imbalance(10.), Some(0.25));
+        assert!((book.imbalance(100.).unwrap() - 2. / 28.).abs() < 1e-12);
+        assert_eq!(book.imbalance(1.), None);
+    }
+
+    #[test]
+    fn test_stats_with_spread_wider_than_band() {
+        let book = make_book();
+        // the touch is 5 bps from the mid, outside a 1 bps band
+        let stats = book.stats(1.).unwrap();
+        assert_eq!(stats.mid_price, 2000.);
+        assert_eq!(stats.spread_bps, 10.);
+        assert_eq!((stats.bid_depth, stats.ask_depth), (0., 0.));
+        assert_eq!(stats.imbalance, None);
+        assert!(stats.to_string().ends_with("imbalance -"));
+        assert_eq!(book.stats(10.).unwrap().imbalance, Some(0.25));
+    }
+
+    #[test]
+    fn test_impact_curve() {
+        let book = make_book();
+        let curve = book.impact_curve(SwapType::Buy, &[1., 3., 100.]);
+        assert_eq!(curve.len(), 2);
+        assert_eq!(curve[0].avg_price, 2001.);
+        assert!((curve[0].impact_bps - 5.).abs() < 1e-9);
+        assert!((curve[1].avg_price - (2001. + 2002. * 2.) / 3.).abs() < 1e-9);
+        assert!(curve[1].impact_bps > curve[0].impact_bps);
+
+        let curve = book.impact_curve(SwapType::Sell, &[5.]);
+        assert!((curve[0].impact_bps - (2000. - (1999. * 3. + 1998. * 2.) / 5.) / 2000. * BPS).abs() < 1e-9);
+    }
+
+}
@@ -0,0 +1,417 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+use flate2::{Compression, write::GzEncoder, read::GzDecoder};
+
+use super::*;
+use snapshot::BinanceSnapshot;
+use sync::BookSync;
+use tape::MarketTape;
+
+
+// One line of a capture, times are local receive times. Externally tagged,
+// internally tagged enums can't read floats with arbitrary precision numbers
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureRecord {
+    Start { received_ms: u64, book_depth: u32, tick_sizes: Vec<(MarketTicker, f64)> },
+    Connected { received_ms: u64 },
+    Disconnected { received_ms: u64 },
+    Snapshot { received_ms: u64, ticker: MarketTicker, depth: u32, body: String }, // REST response
+    Message { received_ms: u64, raw: String }, // websocket frame
+    Broken { received_ms: u64, error: String }, // recording stopped after a failed write
+}
+
+impl CaptureRecord {
+
+    pub fn received_ms(&self) -> u64 {
+        match self {
+            Self::Start { received_ms, .. }
+                | Self::Connected { received_ms }
+                | Self::Disconnected { received_ms }
+                | Self::Snapshot { received_ms, .. }
+                | Self::Message { received_ms, .. }
+                | Self::Broken { received_ms, .. } => *received_ms,
+        }
+    }
+
+}
+
+// Writes everything the books are built from to gzipped NDJSON. Records are
+// flushed as they're written, so a capture cut short by a crash still replays
+pub struct CaptureRecorder {
+    writer: GzEncoder<BufWriter<File>>,
+    broken: bool, // a write failed, nothing is recorded after it
+}
+
+impl CaptureRecorder {
+
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { writer: GzEncoder::new(BufWriter::new(file), Compression::default()), broken: false })
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    // A capture missing a record would replay into the wrong books, so the
+    // first failed write ends it with a `Broken` record, if that can be written
+    pub fn record(&mut self, record: &CaptureRecord) -> Result<()> {
+        if self.broken {
+            return Err(eyre::eyre!("Capture is broken"));
+        }
+        if let Err(e) = self.write(record) {
+            self.broken = true;
+            let broken = CaptureRecord::Broken { received_ms: record.received_ms(), error: e.to_string() };
+            // on a new line, after whatever part of the record was written
+            let _ = self.writer.write_all(b"\n");
+            let _ = self.write(&broken);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &CaptureRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+}
+
+pub(super) type CaptureShared = Arc<Mutex<CaptureRecorder>>;
+
+// Records under the capture lock, which the caller holds while applying the
+// record, so the capture is in the order the books saw
+pub(super) fn record_with<T>(
+    capture: Option<&CaptureShared>,
+    record: impl FnOnce() -> CaptureRecord,
+    apply: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(capture) = capture else {
+        return apply();
+    };
+    let mut capture = capture.lock().expect("Could not lock capture");
+    if !capture.is_broken() {
+        if let Err(e) = capture.record(&record()) {
+            println!("Error writing capture, recording stopped: {e}");
+        }
+    }
+    apply()
+}
+
+pub fn read_capture(path: &str) -> Result<impl Iterator<Item = Result<CaptureRecord>>> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    Ok(reader.lines()
+        // the gzip stream of a capture that's still being written, or was cut
+        // short, ends without a trailer after the last flushed record
+        .take_while(|line| !matches!(
+            line,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidInput)
+        ))
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?)))
+}
+
+// Rebuilds the books of a capture by feeding its records through the same
+// handlers as the live stream
+pub struct BinanceReplay {
+    records: Box<dyn Iterator<Item = Result<CaptureRecord>>>,
+    next: Option<CaptureRecord>,
+    books: OrderBooksShared,
+    tapes: MarketTapesShared,
+    now_ms: u64, // receive time of the last record applied
+}
+
+impl BinanceReplay {
+
+    const TAPE_RETENTION_MS: u64 = 5 * 60_000;
+
+    pub fn open(path: &str) -> Result<Self> {
+        let mut records = read_capture(path)?;
+        let Some(CaptureRecord::Start { received_ms, book_depth, tick_sizes }) = records.next().transpose()? else {
+            return Err(eyre::eyre!(format!("{path} doesn't start with a capture header")));
+        };
+        let books = tick_sizes.iter()
+            .map(|(ticker, tick_size)| (
+                ticker.clone(),
+                Arc::new(Mutex::new((BinanceOrderBook::empty(book_depth, *tick_size), BookSync::new()))),
+            ))
+            .collect::<HashMap<_, _>>();
+        let tapes = tick_sizes.iter()
+            .map(|(ticker, _)| (ticker.clone(), Arc::new(Mutex::new(MarketTape::new(Self::TAPE_RETENTION_MS)))))
+            .collect::<HashMap<_, _>>();
+        let mut replay = Self {
+            records: Box::new(records),
+            next: None,
+            books: Arc::new(books),
+            tapes: Arc::new(tapes),
+            now_ms: received_ms,
+        };
+        replay.next = replay.records.next().transpose()?;
+        Ok(replay)
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next.is_none()
+    }
+
+    // Applies the next record, none at the end of the capture
+    pub fn step(&mut self) -> Result<Option<u64>> {
+        let Some(record) = self.next.take() else {
+            return Ok(None);
+        };
+        self.next = self.records.next().transpose()?;
+        self.now_ms = record.received_ms();
+        self.apply(record)?;
+        Ok(Some(self.now_ms))
+    }
+
+    // Replays the records received up to `until_ms` at `speed` times the
+    // original pace, calling `on_record` after each record
+    pub async fn play<F>(&mut self, until_ms: u64, speed: f64, mut on_record: F) -> Result<()>
+        where F: FnMut(&Self)
+    {
+        if speed.is_nan() || speed <= 0. {
+            return Err(eyre::eyre!(format!("Replay speed must be positive, got {speed}")));
+        }
+        while let Some(record) = self.next.as_ref().filter(|record| record.received_ms() <= until_ms) {
+            let wait_ms = record.received_ms().saturating_sub(self.now_ms) as f64 / speed;
+            if wait_ms >= 1. {
+                tokio::time::sleep(std::time::Duration::from_millis(wait_ms as u64)).await;
+            }
+            self.step()?;
+            on_record(self);
+        }
+        Ok(())
+    }
+
+    pub fn tickers(&self) -> Vec<MarketTicker> {
+        self.books.keys().cloned().collect()
+    }
+
+    pub fn get_book(&self, market: &MarketTicker) -> Result<BinanceOrderBook> {
+        let book = self.books.get(market)
+            .ok_or(eyre::eyre!(format!("No book for {market}")))?;
+        let book = book.lock().unwrap().0.clone();
+        Ok(book)
+    }
+
+    // Books as of the last record, for `BinanceSnapshotQuoter`
+    pub fn snapshot(&self) -> BinanceSnapshot {
+        let books = self.books.keys()
+            .map(|ticker| (ticker.clone(), self.get_book(ticker).unwrap()))
+            .collect();
+        BinanceSnapshot { timestamp_ms: self.now_ms, books }
+    }
+
+    fn apply(&mut self, record: CaptureRecord) -> Result<()> {
+        match record {
+            CaptureRecord::Start { .. } => return Err(eyre::eyre!("Capture header in the middle of a capture")),
+            CaptureRecord::Broken { received_ms, error } => {
+                return Err(eyre::eyre!(format!("Capture stopped recording at {received_ms}ms: {error}")));
+            },
+            CaptureRecord::Connected { .. } => {
+                for book in self.books.values() {
+                    book.lock().unwrap().1.on_connect();
+                }
+            },
+            CaptureRecord::Disconnected { .. } => {
+                for book in self.books.values() {
+                    book.lock().unwrap().1.on_disconnect();
+                }
+                for tape in self.tapes.values() {
+                    tape.lock().unwrap().on_disconnect();
+                }
+            },
+            CaptureRecord::Snapshot { received_ms, ticker, depth, body } => {
+                let book = self.books.get(&ticker)
+                    .ok_or(eyre::eyre!(format!("No book for {ticker}")))?;
+                connector::apply_snapshot(book, depth, &body, received_ms)?;
+            },
+            // a gap is followed by the snapshot that was fetched for it, and
+            // an error by the disconnect it caused
+            CaptureRecord::Message { received_ms, raw } => {
+                if let Err(e) = connector::handle_update(self.books.clone(), &self.tapes, &raw, received_ms) {
+                    println!("Error replaying stream message: {e}");
+                }
+            },
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quoter::BinanceQuoter;
+    use mock::{MockBinance, MockEvent, diff_event, snapshot, wait_for};
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{name}-{}.ndjson.gz", std::process::id()));
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_read_unfinished_capture() {
+        let path = temp_path("unfinished-capture");
+        let mut recorder = CaptureRecorder::create(&path).unwrap();
+        let records = vec![
+            CaptureRecord::Start { received_ms: 1, book_depth: 5, tick_sizes: vec![(String::from("ethusdt"), 0.01)] },
+            CaptureRecord::Message { received_ms: 2, raw: String::from("not json") },
+        ];
+        for record in records.iter() {
+            recorder.record(record).unwrap();
+        }
+        // as if the process was killed
+        std::mem::forget(recorder);
+
+        let read = read_capture(&path).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(read, records);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_matches_live_books() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_snapshot("ethusdt", snapshot(105, &[("1995.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            diff_event("ethusdt", 101, 102, &[("1999.50", "1.0")], &[]),
+            MockEvent::Pause(50),
+            // 103 and 104 are missing, the book is resynced
+            diff_event("ethusdt", 105, 106, &[("1996.00", "1.0")], &[("2002.00", "2.0")]),
+            MockEvent::Frame(String::from("not json")),
+            diff_event("ethusdt", 107, 107, &[("1996.00", "0.5")], &[]),
+        ]);
+        let path = temp_path("live-capture");
+        let quoter = BinanceQuoter::create_recording(
+            &mock.endpoints,
+            vec![suppported_markets::ETHUSDT],
+            50,
+            100,
+            &path,
+        ).await.unwrap();
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.get_book(&ticker).unwrap().bids().next().map(|level| level.qty) == Some(0.5)).await;
+        let live_book = quoter.get_book(&ticker).unwrap();
+
+        let mut replay = BinanceReplay::open(&path).unwrap();
+        replay.play(u64::MAX, f64::INFINITY, |_| ()).await.unwrap();
+        let replayed_book = replay.get_book(&ticker).unwrap();
+        assert_eq!(replayed_book.bids().collect::<Vec<_>>(), live_book.bids().collect::<Vec<_>>());
+        assert_eq!(replayed_book.asks().collect::<Vec<_>>(), live_book.asks().collect::<Vec<_>>());
+        assert_eq!(replay.snapshot().books.len(), 1);
+
+        // the book before the gap
+        let mut replay = BinanceReplay::open(&path).unwrap();
+        while replay.get_book(&ticker).unwrap().bids().next().map(|level| level.price) != Some(1999.5) {
+            replay.step().unwrap().unwrap();
+        }
+        let gap_ms = replay.now_ms();
+        replay.play(gap_ms, f64::INFINITY, |_| ()).await.unwrap();
+        assert_eq!(replay.get_book(&ticker).unwrap().bids().next().map(|level| level.price), Some(1999.5));
+        assert!(!replay.is_done());
+        assert!(replay.play(u64::MAX, 0., |_| ()).await.is_err());
+        assert!(replay.play(u64::MAX, f64::NAN, |_| ()).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_partial_books() {
+        let mock = MockBinance::start().await.unwrap();
+        let partial_event = |last_update_id: u64, bid_price: &str| MockEvent::Frame(format!(
+            r#"{{"stream":"ethusdt@depth5@100ms","data":{{"lastUpdateId":{last_update_id},"bids":[["{bid_price}","2.0"]],"asks":[["2001.00","1.0"]]}}}}"#
+        ));
+        mock.add_connection(vec![
+            partial_event(10, "1999.00"),
+            MockEvent::Pause(50),
+            partial_event(12, "1998.50"),
+        ]);
+        let path = temp_path("partial-capture");
+        let quoter = BinanceQuoter::create_partial_recording(
+            &mock.endpoints,
+            vec![suppported_markets::ETHUSDT],
+            5,
+            100,
+            &path,
+        ).await.unwrap();
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.get_book(&ticker).unwrap().bids().next().map(|level| level.price) == Some(1998.5)).await;
+
+        let mut replay = BinanceReplay::open(&path).unwrap();
+        replay.play(u64::MAX, f64::INFINITY, |_| ()).await.unwrap();
+        let replayed_book = replay.get_book(&ticker).unwrap();
+        assert_eq!(replayed_book.bids().collect::<Vec<_>>(), quoter.get_book(&ticker).unwrap().bids().collect::<Vec<_>>());
+        assert_eq!(mock.depth_requests(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_bbo_receive_times() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            MockEvent::Frame(String::from(
+                r#"{"stream":"ethusdt@bookTicker","data":{"u":101,"s":"ETHUSDT","b":"1999.50","B":"3.5","a":"2000.50","A":"1.2"}}"#
+            )),
+        ]);
+        let path = temp_path("bbo-capture");
+        let quoter = BinanceQuoter::create_recording(
+            &mock.endpoints,
+            vec![suppported_markets::ETHUSDT],
+            50,
+            100,
+            &path,
+        ).await.unwrap();
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.bbo(&ticker).unwrap().is_some()).await;
+        let live_bbo = quoter.bbo(&ticker).unwrap().unwrap();
+        let message_ms = read_capture(&path).unwrap()
+            .filter_map(|record| match record.unwrap() {
+                CaptureRecord::Message { received_ms, .. } => Some(received_ms),
+                _ => None,
+            })
+            .next().unwrap();
+        assert_eq!(live_bbo.received_ms, message_ms);
+
+        // replayed later, the BBO keeps the time it was received at
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let mut replay = BinanceReplay::open(&path).unwrap();
+        replay.play(u64::MAX, f64::INFINITY, |_| ()).await.unwrap();
+        let replayed_bbo = replay.tapes[&ticker].lock().unwrap().bbo;
+        assert_eq!(replayed_bbo, Some(live_bbo));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_broken_capture_is_not_replayed() {
+        let path = temp_path("broken-capture");
+        let mut recorder = CaptureRecorder::create(&path).unwrap();
+        let records = [
+            CaptureRecord::Start { received_ms: 1, book_depth: 5, tick_sizes: vec![(String::from("ethusdt"), 0.01)] },
+            CaptureRecord::Connected { received_ms: 2 },
+            CaptureRecord::Broken { received_ms: 3, error: String::from("No space left on device") },
+            CaptureRecord::Connected { received_ms: 4 },
+        ];
+        for record in records.iter() {
+            recorder.record(record).unwrap();
+        }
+        drop(recorder);
+
+        let mut replay = BinanceReplay::open(&path).unwrap();
+        assert_eq!(replay.step().unwrap(), Some(2));
+        assert!(replay.play(u64::MAX, f64::INFINITY, |_| ()).await.is_err());
+        assert_eq!(replay.now_ms(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+}
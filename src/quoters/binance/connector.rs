@@ -9,32 +9,111 @@ use tokio_tungstenite::{
     connect_async, 
 };
 
-// todo: panic if there is no update for X seconds
-
 use super::*;
+use sync::SyncStatus;
+use capture::{CaptureRecord, CaptureShared, record_with};
+use tape::{Bbo, Trade, Funding};
+
+
+const BINANCE_STREAM_ENDPOINT: &str = "wss://stream.binance.com:9443";
+const BINANCE_API_ENDPOINT: &str = "https://api.binance.com";
+const BINANCE_FUTURES_STREAM_ENDPOINT: &str = "wss://fstream.binance.com";
+const BINANCE_FUTURES_API_ENDPOINT: &str = "https://fapi.binance.com";
+const RECONNECT_MIN_WAIT_MS: u64 = 500;
+const RECONNECT_MAX_WAIT_MS: u64 = 30_000;
+const SYNC_MIN_WAIT_MS: u64 = 500;
+const SYNC_MAX_WAIT_MS: u64 = 60_000; // keeps an outage or rate limit from getting the IP banned
 
 
 lazy_static! {
-    pub static ref BOOK_STREAM_KEY_REGEX: Regex = Regex::new(r"[a-z]+@depth[0-9]+@[0-9]*ms").unwrap();
+    pub static ref BOOK_STREAM_KEY_REGEX: Regex = Regex::new(r"[a-z]+@depth([0-9]+)@[0-9]*ms").unwrap();
+}
+
+// Base URLs of the combined stream and the REST API
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinanceEndpoints {
+    pub stream: String,
+    pub api: String,
+}
+
+impl BinanceEndpoints {
+
+    // USDⓈ-M futures
+    pub fn futures() -> Self {
+        Self {
+            stream: BINANCE_FUTURES_STREAM_ENDPOINT.to_string(),
+            api: BINANCE_FUTURES_API_ENDPOINT.to_string(),
+        }
+    }
+
+}
+
+impl Default for BinanceEndpoints {
+    fn default() -> Self {
+        Self {
+            stream: BINANCE_STREAM_ENDPOINT.to_string(),
+            api: BINANCE_API_ENDPOINT.to_string(),
+        }
+    }
 }
 
+// Combined stream wrapper, `data` depends on the stream
 #[derive(serde::Deserialize, Debug)]
-pub struct BinanceAPIOrderBookUpdate {
+pub struct BinanceAPIStreamMessage {
     stream: String,
-    data: BinanceAPIOrderBookUpdateData,
+    data: serde_json::Value,
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct BinanceAPIOrderBookUpdateData {
-    pub e: String, // event type
-    pub E: u64, // event time
     pub s: String, // market ticker
     pub U: u64, // first update ID in event
     pub u: u64, // final update ID in event
+    pub pu: Option<u64>, // final update ID of the previous event, futures only
     pub b: Vec<Vec<String>>, // bids to be updated (sorted descending)
     pub a: Vec<Vec<String>>, // asks to be updated (sorted ascending)
 }
 
+#[derive(serde::Deserialize, Debug)]
+pub struct BinanceAPIBookTicker {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "b")]
+    pub bid_price: String,
+    #[serde(rename = "B")]
+    pub bid_qty: String,
+    #[serde(rename = "a")]
+    pub ask_price: String,
+    #[serde(rename = "A")]
+    pub ask_qty: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct BinanceAPIAggTrade {
+    #[serde(rename = "p")]
+    pub price: String,
+    #[serde(rename = "q")]
+    pub qty: String,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    #[serde(rename = "m")]
+    pub buyer_is_maker: bool, // the taker sold
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct BinanceAPIMarkPrice {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "p")]
+    pub mark_price: String,
+    #[serde(rename = "i")]
+    pub index_price: String,
+    #[serde(rename = "r")]
+    pub funding_rate: String,
+    #[serde(rename = "T")]
+    pub next_funding_time: u64,
+}
+
 #[derive(serde::Deserialize, Debug)]
 pub struct BinanceAPIOrderBook {
     stream: String,
@@ -44,69 +123,309 @@ pub struct BinanceAPIOrderBook {
 #[derive(serde::Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BinanceAPIOrderBookData {
-    last_update_id: u64,
+    pub last_update_id: u64,
     pub bids: Vec<Vec<String>>, // sorted desc
     pub asks: Vec<Vec<String>>, // sorted asc
 }
 
-pub(super) async fn fetch_book(
+#[derive(serde::Deserialize, Debug)]
+pub struct BinanceAPIExchangeInfo {
+    pub symbols: Vec<BinanceAPISymbol>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BinanceAPISymbol {
+    pub symbol: String,
+    pub status: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub filters: Vec<BinanceAPIFilter>,
+}
+
+// Values are decimal strings
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BinanceAPIFilter {
+    #[serde(rename_all = "camelCase")]
+    PriceFilter { tick_size: String },
+    #[serde(rename_all = "camelCase")]
+    LotSize { min_qty: String, max_qty: String, step_size: String },
+    // futures name it `notional` and apply it to every order
+    #[serde(rename_all = "camelCase")]
+    MinNotional {
+        #[serde(alias = "notional")]
+        min_notional: String,
+        #[serde(default = "applies_to_market")]
+        apply_to_market: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Notional { min_notional: String, apply_min_to_market: bool },
+    #[serde(other)]
+    Other,
+}
+
+fn applies_to_market() -> bool {
+    true
+}
+
+// Futures exchangeInfo takes no symbols and lists every market
+pub(super) async fn fetch_exchange_info(
     endpoint: &str,
+    market_type: MarketType,
+    market_tickers: &[MarketTicker],
+) -> Result<BinanceAPIExchangeInfo> {
+    let symbols = market_tickers.iter()
+        .map(|ticker| ticker.to_uppercase())
+        .collect::<Vec<_>>();
+    let request = reqwest::Client::new()
+        .get(market_type.exchange_info_endpoint(endpoint));
+    let request = match market_type {
+        MarketType::Spot => request.query(&[("symbols", serde_json::to_string(&symbols)?)]),
+        MarketType::UsdFutures => request,
+    };
+    let resp = request.send().await?;
+    let resp = resp.text().await?;
+    let exchange_info: BinanceAPIExchangeInfo = serde_json::from_str(&resp)?;
+    Ok(exchange_info)
+}
+
+// Raw body, so it can be captured as received
+pub(super) async fn fetch_book(
+    depth_endpoint: &str,
     market_ticker: &MarketTicker,
     depth: u32,
-) -> Result<BinanceAPIOrderBookData> {
+) -> Result<String> {
     let endpoint = format!(
-        "{}/api/v3/depth?symbol={}&limit={}",
-        endpoint,
+        "{}?symbol={}&limit={}",
+        depth_endpoint,
         market_ticker.to_uppercase(),
         depth,
     );
     let resp = reqwest::get(&endpoint).await?;
     let resp = resp.text().await?;
-    let book: BinanceAPIOrderBookData = serde_json::from_str(&resp)?;
-    Ok(book.into())
+    Ok(resp)
 }
 
+#[derive(Clone, Copy)]
+pub(super) struct StreamConfig {
+    pub market_type: MarketType,
+    pub mode: StreamMode,
+    pub refresh_rate_ms: RefreshRate,
+    pub book_depth: u32, // of REST snapshots
+    pub stale_after_ms: u64, // reconnect after this long without a message
+}
+
+// Keeps the stream connected, reconnecting with exponential backoff when it
+// closes, errors or goes quiet for longer than the staleness timeout. With a
+// capture, every message and snapshot the books are built from is recorded
 pub(super) async fn start_stream(
-    stream_base_endpoint: &str, 
+    endpoints: BinanceEndpoints,
     market_tickers: Vec<MarketTicker>,
-    refresh_rate_ms: RefreshRate,
-    books: OrderBooksShared
+    config: StreamConfig,
+    books: OrderBooksShared,
+    tapes: MarketTapesShared,
+    capture: Option<CaptureShared>,
 ) -> Result<()> {
-    let mut stream = connect(
-        stream_base_endpoint, 
-        market_tickers.clone(), 
-        refresh_rate_ms,
-    ).await?;
+    let StreamConfig { market_type, mode, refresh_rate_ms, book_depth, .. } = config;
+    let depth_endpoint = market_type.depth_endpoint(&endpoints.api);
+    let mut backoff = Backoff::new(RECONNECT_MIN_WAIT_MS, RECONNECT_MAX_WAIT_MS);
+    let mut reconnecting = false;
+    loop {
+        match connect(&endpoints.stream, market_tickers.clone(), market_type, mode, refresh_rate_ms).await {
+            Ok(stream) => {
+                let connected = || CaptureRecord::Connected { received_ms: utils::get_epoch_ms() };
+                record_with(capture.as_ref(), connected, || {
+                    for book in books.values() {
+                        book.lock().expect("Could not lock book").1.on_connect();
+                    }
+                    Ok(())
+                })?;
+                // the first snapshots are fetched by the quoter, partial books
+                // arrive whole on the stream
+                if reconnecting && matches!(mode, StreamMode::Diff) {
+                    for ticker in market_tickers.iter() {
+                        tokio::spawn(sync_book(
+                            depth_endpoint.clone(),
+                            ticker.clone(),
+                            book_depth,
+                            books.clone(),
+                            capture.clone(),
+                            u32::MAX,
+                        ));
+                    }
+                }
+                let closed = read_stream(
+                    stream, 
+                    &depth_endpoint, 
+                    &config, 
+                    books.clone(), 
+                    tapes.clone(),
+                    capture.clone(),
+                    &mut backoff,
+                ).await;
+                if let Err(e) = closed {
+                    println!("Stream error: {e}");
+                }
+                let disconnected = || CaptureRecord::Disconnected { received_ms: utils::get_epoch_ms() };
+                record_with(capture.as_ref(), disconnected, || {
+                    for book in books.values() {
+                        book.lock().expect("Could not lock book").1.on_disconnect();
+                    }
+                    for tape in tapes.values() {
+                        tape.lock().expect("Could not lock tape").on_disconnect();
+                    }
+                    Ok(())
+                })?;
+            },
+            Err(e) => {
+                println!("Error connecting to stream: {e}");
+            },
+        }
+        reconnecting = true;
+        let wait_ms = backoff.next_wait_ms();
+        println!("Reconnecting in {wait_ms}ms");
+        tokio::time::sleep(std::time::Duration::from_millis(wait_ms)).await;
+    }
+}
 
-    while let Some(msg) = stream.next().await {
+// Returns when the connection closes
+async fn read_stream(
+    mut stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    depth_endpoint: &str,
+    config: &StreamConfig,
+    books: OrderBooksShared,
+    tapes: MarketTapesShared,
+    capture: Option<CaptureShared>,
+    backoff: &mut Backoff,
+) -> Result<()> {
+    let StreamConfig { book_depth, stale_after_ms, .. } = *config;
+    let idle_timeout = std::time::Duration::from_millis(stale_after_ms);
+    loop {
+        let msg = match tokio::time::timeout(idle_timeout, stream.next()).await {
+            Ok(Some(msg)) => msg?,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(eyre::eyre!(format!("No message for {stale_after_ms}ms"))),
+        };
+        backoff.reset();
         match msg {
-            Ok(msg) => {
-                if let Message::Ping(ping) = msg {
-                    stream.send(Message::Pong(ping)).await?;
-                    continue;
-                }
-                if msg.is_binary() || msg.is_text() {
-                    handle_update(books.clone(), msg.to_text().unwrap())?;
-                    continue;
+            Message::Ping(ping) => {
+                stream.send(Message::Pong(ping)).await?;
+            },
+            Message::Close(frame) => {
+                println!("Stream closed: {frame:?}");
+                return Ok(());
+            },
+            Message::Binary(_) | Message::Text(_) => {
+                let msg = msg.to_text()?;
+                let received_ms = utils::get_epoch_ms();
+                let received = || CaptureRecord::Message { received_ms, raw: msg.to_string() };
+                let gap = record_with(capture.as_ref(), received, || handle_update(books.clone(), &tapes, msg, received_ms))?;
+                if let Some(ticker) = gap {
+                    tokio::spawn(sync_book(
+                        depth_endpoint.to_string(),
+                        ticker,
+                        book_depth,
+                        books.clone(),
+                        capture.clone(),
+                        u32::MAX,
+                    ));
                 }
+            },
+            msg => {
                 println!("Unhandled message: {msg:?}");
             },
+        }
+    }
+}
+
+// Fetches snapshots until one lines up with the buffered stream events, backing
+// off between attempts. Only one sync runs per book, a gap found while it's
+// running is closed by the snapshot it fetches next
+pub(super) async fn sync_book(
+    depth_endpoint: String,
+    market_ticker: MarketTicker,
+    book_depth: u32,
+    books: OrderBooksShared,
+    capture: Option<CaptureShared>,
+    max_attempts: u32,
+) -> Result<()> {
+    let book = books.get(&market_ticker)
+        .ok_or(eyre::eyre!(format!("No book for {market_ticker}")))?;
+    if !book.lock().expect("Could not lock book").1.start_sync() {
+        return Ok(());
+    }
+    let synced = fetch_until_synced(&depth_endpoint, &market_ticker, book_depth, book, capture, max_attempts).await;
+    book.lock().expect("Could not lock book").1.end_sync();
+    synced
+}
+
+async fn fetch_until_synced(
+    depth_endpoint: &str,
+    market_ticker: &MarketTicker,
+    book_depth: u32,
+    book: &Mutex<(BinanceOrderBook, sync::BookSync)>,
+    capture: Option<CaptureShared>,
+    max_attempts: u32,
+) -> Result<()> {
+    let mut backoff = Backoff::new(SYNC_MIN_WAIT_MS, SYNC_MAX_WAIT_MS);
+    for _ in 0..max_attempts {
+        let snapshot = fetch_book(depth_endpoint, market_ticker, book_depth).await;
+        let status = match snapshot {
+            Ok(body) => {
+                let received_ms = utils::get_epoch_ms();
+                let received = || CaptureRecord::Snapshot {
+                    received_ms,
+                    ticker: market_ticker.clone(),
+                    depth: book_depth,
+                    body: body.clone(),
+                };
+                record_with(capture.as_ref(), received, || apply_snapshot(book, book_depth, &body, received_ms))?
+            },
             Err(e) => {
-                println!("Error receiving message: {e}");
-            }
+                println!("Error fetching {market_ticker} book: {e}");
+                SyncStatus::Gap
+            },
+        };
+        if let SyncStatus::Synced = status {
+            return Ok(());
         }
+        tokio::time::sleep(std::time::Duration::from_millis(backoff.next_wait_ms())).await;
     }
-    Ok(())
+    Err(eyre::eyre!(format!("Could not sync {market_ticker} book after {max_attempts} attempts")))
+}
+
+// A body that isn't a snapshot, e.g. an error response, leaves the gap open
+pub(super) fn apply_snapshot(
+    book: &Mutex<(BinanceOrderBook, sync::BookSync)>,
+    book_depth: u32,
+    body: &str,
+    received_ms: u64,
+) -> Result<SyncStatus> {
+    let snapshot = match serde_json::from_str::<BinanceAPIOrderBookData>(body) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("Error parsing book snapshot: {e}");
+            return Ok(SyncStatus::Gap);
+        },
+    };
+    let mut book = book.lock().expect("Could not lock book");
+    let (book, sync) = &mut *book;
+    sync.on_snapshot(book, book_depth, snapshot, received_ms)
 }
 
 async fn connect(
     stream_base_endpoint: &str,
     market_tickers: Vec<String>,
+    market_type: MarketType,
+    mode: StreamMode,
     interval_ms: RefreshRate,
 ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
     let stream_endpoint = make_stream_endpoint(
         stream_base_endpoint, 
         market_tickers, 
+        market_type,
+        mode,
         interval_ms
     );
     println!("Connecting to {}", stream_endpoint);
@@ -120,54 +439,226 @@ async fn connect(
 fn make_stream_endpoint(
     stream_base_endpoint: &str,
     market_tickers: Vec<String>,
+    market_type: MarketType,
+    mode: StreamMode,
     interval_ms: RefreshRate,
 ) -> String {
-    let stream_keys = make_stream_keys(market_tickers, interval_ms);
+    let stream_keys = make_stream_keys(market_tickers, market_type, mode, interval_ms);
     format!("{}/stream?streams={}", stream_base_endpoint, stream_keys.join("/"))
 }
 
+// Perpetuals add the mark price stream, which carries the funding rate
 fn make_stream_keys(
     market_tickers: Vec<String>,
+    market_type: MarketType,
+    mode: StreamMode,
     interval_ms: RefreshRate,
 ) -> Vec<String> {
+    let depth_levels = match mode {
+        StreamMode::Diff => String::new(),
+        StreamMode::Partial(book_size) => (book_size as u8).to_string(),
+    };
     market_tickers.iter()
-        .map(|market_ticker| format!("{}@depth@{}ms", 
-            market_ticker, interval_ms as u32
-        ))
+        .flat_map(|market_ticker| {
+            let mut keys = vec![
+                format!("{}@depth{}@{}ms", market_ticker, depth_levels, interval_ms as u32),
+                format!("{}@bookTicker", market_ticker),
+                format!("{}@aggTrade", market_ticker),
+            ];
+            if market_type == MarketType::UsdFutures {
+                keys.push(format!("{}@markPrice@1s", market_ticker));
+            }
+            keys
+        })
         .collect()
 }
 
-fn handle_update(books: OrderBooksShared, msg: &str) -> Result<()> {
-    match serde_json::from_str::<BinanceAPIOrderBookUpdate>(msg) {
-        Ok(order_book_update) => {
-            let ref ticker = order_book_update.data.s;
-            let mut book = books.get(&ticker.to_lowercase())
-                .unwrap()
-                .lock().expect("Could not lock book");
-            book.0.update_from_stream(order_book_update.data)?;
-        }
+// Doubles the wait after each failed attempt, up to a maximum
+struct Backoff {
+    min_wait_ms: u64,
+    max_wait_ms: u64,
+    wait_ms: u64,
+}
+
+impl Backoff {
+
+    fn new(min_wait_ms: u64, max_wait_ms: u64) -> Self {
+        Self { min_wait_ms, max_wait_ms, wait_ms: min_wait_ms }
+    }
+
+    fn next_wait_ms(&mut self) -> u64 {
+        let wait_ms = self.wait_ms;
+        self.wait_ms = (self.wait_ms * 2).min(self.max_wait_ms);
+        wait_ms
+    }
+
+    fn reset(&mut self) {
+        self.wait_ms = self.min_wait_ms;
+    }
+
+}
+
+// Returns the ticker of a book that needs a new snapshot
+pub(super) fn handle_update(
+    books: OrderBooksShared,
+    tapes: &MarketTapesShared,
+    msg: &str,
+    received_ms: u64,
+) -> Result<Option<MarketTicker>> {
+    let message = match serde_json::from_str::<BinanceAPIStreamMessage>(msg) {
+        Ok(message) => message,
         Err(e) => {
-            println!("Error parsing order book update: {e}");
+            println!("Error parsing stream message: {e}");
+            return Ok(None);
         }
     };
+    if let Some(captures) = BOOK_STREAM_KEY_REGEX.captures(&message.stream) {
+        let depth = captures[1].parse::<u32>()?;
+        let partial = match serde_json::from_value::<BinanceAPIOrderBookData>(message.data) {
+            Ok(partial) => partial,
+            Err(e) => {
+                println!("Error parsing partial order book: {e}");
+                return Ok(None);
+            },
+        };
+        let ticker = message.stream.split('@').next().unwrap_or_default();
+        let Some(book) = books.get(ticker) else {
+            println!("No book for {ticker}");
+            return Ok(None);
+        };
+        let (book, sync) = &mut *book.lock().expect("Could not lock book");
+        // the next message replaces the book anyway
+        if let Err(e) = sync.on_partial_book(book, depth, partial, received_ms) {
+            println!("Error handling {} event: {e}", message.stream);
+        }
+        return Ok(None);
+    }
+    let (ticker, stream_type) = message.stream.split_once('@').unwrap_or((&message.stream, ""));
+    match stream_type {
+        "bookTicker" | "aggTrade" | "markPrice@1s" => {
+            // a bad tape event isn't worth a reconnect
+            if let Err(e) = handle_tape_update(tapes, ticker, stream_type, message.data, received_ms) {
+                println!("Error handling {} event: {e}", message.stream);
+            }
+        },
+        _ => match serde_json::from_value::<BinanceAPIOrderBookUpdateData>(message.data) {
+            Ok(order_book_update) => {
+                let ticker = order_book_update.s.to_lowercase();
+                // one market's bad event doesn't reconnect the others
+                let Some(book) = books.get(&ticker) else {
+                    println!("No book for {ticker}");
+                    return Ok(None);
+                };
+                let (book, sync) = &mut *book.lock().expect("Could not lock book");
+                if let SyncStatus::Gap = sync.on_update(book, order_book_update, received_ms)? {
+                    return Ok(Some(ticker));
+                }
+            }
+            Err(e) => {
+                println!("Error parsing order book update: {e}");
+            }
+        },
+    };
+    Ok(None)
+}
+
+fn handle_tape_update(
+    tapes: &MarketTapesShared,
+    ticker: &str,
+    stream_type: &str,
+    data: serde_json::Value,
+    received_ms: u64,
+) -> Result<()> {
+    let mut tape = tapes.get(ticker)
+        .ok_or(eyre::eyre!(format!("No tape for {ticker}")))?
+        .lock().expect("Could not lock tape");
+    match stream_type {
+        "bookTicker" => {
+            let book_ticker = serde_json::from_value::<BinanceAPIBookTicker>(data)?;
+            tape.on_book_ticker(Bbo::try_from((book_ticker, received_ms))?);
+        },
+        "aggTrade" => {
+            let trade = serde_json::from_value::<BinanceAPIAggTrade>(data)?;
+            tape.on_trade(Trade::try_from(trade)?);
+        },
+        _ => {
+            let mark_price = serde_json::from_value::<BinanceAPIMarkPrice>(data)?;
+            tape.on_mark_price(Funding::try_from(mark_price)?);
+        },
+    }
     Ok(())
 }
 
-// fn handle_update(books: OrderBooksShared, msg: &str) -> Result<()> {
-//     match serde_json::from_str::<BinanceAPIOrderBookUpdate>(msg) {
-//         Ok(order_book_update) => {
-//             if BOOK_STREAM_KEY_REGEX.is_match(&order_book_update.stream) {
-//                 let ticker = order_book_update.stream.split("@").next().unwrap();
-//                 let mut book = books
-//                     .get(&ticker.to_string()).unwrap()
-//                     .lock().unwrap();
-//                 book.0 = BinanceOrderBook::try_from(order_book_update.data)?;
-//                 // println!("Refreshed {ticker} book"); 
-//             }
-//         }
-//         Err(e) => {
-//             println!("Error parsing order book update: {e}");
-//         }
-//     };
-//     Ok(())
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(500, 3_000);
+        let waits = (0..5).map(|_| backoff.next_wait_ms()).collect::<Vec<_>>();
+        assert_eq!(waits, vec![500, 1_000, 2_000, 3_000, 3_000]);
+        backoff.reset();
+        assert_eq!(backoff.next_wait_ms(), 500);
+    }
+
+    #[test]
+    fn test_handle_tape_updates() {
+        let books: OrderBooksShared = Arc::new(HashMap::new());
+        let tape = Arc::new(Mutex::new(tape::MarketTape::new(60_000)));
+        let tapes: MarketTapesShared = Arc::new(HashMap::from([(String::from("ethusdt"), tape.clone())]));
+
+        let book_ticker = r#"{"stream":"ethusdt@bookTicker","data":{"u":400900217,"s":"ETHUSDT","b":"1999.99","B":"3.5","a":"2000.00","A":"1.2"}}"#;
+        assert_eq!(handle_update(books.clone(), &tapes, book_ticker, 0).unwrap(), None);
+        let agg_trade = r#"{"stream":"ethusdt@aggTrade","data":{"e":"aggTrade","E":1700000000001,"s":"ETHUSDT","a":1,"p":"2000.00","q":"0.5","f":1,"l":2,"T":1700000000000,"m":true,"M":true}}"#;
+        handle_update(books.clone(), &tapes, agg_trade, 0).unwrap();
+
+        let tape = tape.lock().unwrap();
+        let bbo = tape.bbo.unwrap();
+        assert_eq!((bbo.bid_price, bbo.bid_qty, bbo.ask_price, bbo.ask_qty), (1999.99, 3.5, 2000., 1.2));
+        let flow = tape.trades.flow(1700000000000, 1_000);
+        assert_eq!(flow.signed_volume, -0.5);
+        assert_eq!(flow.vwap, Some(2000.));
+    }
+
+    #[test]
+    fn test_update_without_book() {
+        let books: OrderBooksShared = Arc::new(HashMap::new());
+        let tapes: MarketTapesShared = Arc::new(HashMap::new());
+        let update = r#"{"stream":"ethusdt@depth@100ms","data":{"e":"depthUpdate","E":1,"s":"ETHUSDT","U":1,"u":2,"b":[],"a":[]}}"#;
+        assert_eq!(handle_update(books, &tapes, update, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_handle_partial_book() {
+        let book = Arc::new(Mutex::new((BinanceOrderBook::empty(5, 0.01), sync::BookSync::new())));
+        let books: OrderBooksShared = Arc::new(HashMap::from([(String::from("ethusdt"), book.clone())]));
+        let tapes: MarketTapesShared = Arc::new(HashMap::new());
+        book.lock().unwrap().1.on_connect();
+
+        let make_message = |last_update_id: u64, bid_price: &str| format!(
+            r#"{{"stream":"ethusdt@depth5@100ms","data":{{"lastUpdateId":{last_update_id},"bids":[["{bid_price}","2.0"]],"asks":[["2001.00","1.0"]]}}}}"#
+        );
+        let now_ms = utils::get_epoch_ms();
+        assert_eq!(handle_update(books.clone(), &tapes, &make_message(10, "1999.00"), now_ms).unwrap(), None);
+        assert_eq!(book.lock().unwrap().1.health(now_ms, 1_000), BookHealth::Live);
+        // a newer message replaces the book and an older one is ignored
+        handle_update(books.clone(), &tapes, &make_message(12, "1998.00"), now_ms).unwrap();
+        handle_update(books.clone(), &tapes, &make_message(11, "1997.00"), now_ms).unwrap();
+        let book = &book.lock().unwrap().0;
+        assert_eq!(book.bids().collect::<Vec<_>>().len(), 1);
+        assert_eq!(book.query_exact_base(order_book::SwapType::Sell, 1.).1, 1998.);
+    }
+
+    #[test]
+    fn test_stream_keys() {
+        let keys = make_stream_keys(vec![String::from("ethusdt")], MarketType::Spot, StreamMode::Diff, RefreshRate::Fast);
+        assert_eq!(keys, vec!["ethusdt@depth@100ms", "ethusdt@bookTicker", "ethusdt@aggTrade"]);
+        let keys = make_stream_keys(vec![String::from("ethusdt")], MarketType::UsdFutures, StreamMode::Diff, RefreshRate::Fast);
+        assert_eq!(keys[3], "ethusdt@markPrice@1s");
+        let keys = make_stream_keys(vec![String::from("ethusdt")], MarketType::Spot, StreamMode::Partial(BookSize::Ten), RefreshRate::Fast);
+        assert_eq!(keys[0], "ethusdt@depth10@100ms");
+        assert!(BOOK_STREAM_KEY_REGEX.is_match(&keys[0]));
+    }
+
+}
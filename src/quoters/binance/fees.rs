@@ -0,0 +1,196 @@
+use super::*;
+use market::Market;
+
+
+// VIP levels by 30 day volume and BNB balance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeTier {
+    Regular,
+    Vip1,
+    Vip2,
+    Vip3,
+    Vip4,
+    Vip5,
+    Vip6,
+    Vip7,
+    Vip8,
+    Vip9,
+}
+
+impl FeeTier {
+
+    // 0 is the regular tier
+    pub fn from_vip_level(level: u8) -> Result<Self> {
+        match level {
+            0 => Ok(Self::Regular),
+            1 => Ok(Self::Vip1),
+            2 => Ok(Self::Vip2),
+            3 => Ok(Self::Vip3),
+            4 => Ok(Self::Vip4),
+            5 => Ok(Self::Vip5),
+            6 => Ok(Self::Vip6),
+            7 => Ok(Self::Vip7),
+            8 => Ok(Self::Vip8),
+            9 => Ok(Self::Vip9),
+            _ => Err(eyre::eyre!(format!("No VIP level {level}"))),
+        }
+    }
+
+    // (maker, taker) in bps, before the BNB discount
+    fn rates_bps(&self, market_type: MarketType) -> (f64, f64) {
+        match market_type {
+            MarketType::Spot => self.spot_rates_bps(),
+            MarketType::UsdFutures => self.futures_rates_bps(),
+        }
+    }
+
+    pub fn spot_rates_bps(&self) -> (f64, f64) {
+        match self {
+            Self::Regular => (10., 10.),
+            Self::Vip1 => (9., 10.),
+            Self::Vip2 => (8., 10.),
+            Self::Vip3 => (4.2, 6.),
+            Self::Vip4 => (4.2, 5.4),
+            Self::Vip5 => (3.6, 4.8),
+            Self::Vip6 => (3., 4.2),
+            Self::Vip7 => (2.4, 3.6),
+            Self::Vip8 => (1.8, 3.),
+            Self::Vip9 => (1.2, 2.4),
+        }
+    }
+
+    // USDⓈ-M futures
+    pub fn futures_rates_bps(&self) -> (f64, f64) {
+        match self {
+            Self::Regular => (2., 5.),
+            Self::Vip1 => (1.6, 4.),
+            Self::Vip2 => (1.4, 3.5),
+            Self::Vip3 => (1.2, 3.2),
+            Self::Vip4 => (1., 3.),
+            Self::Vip5 => (0.8, 2.7),
+            Self::Vip6 => (0.6, 2.5),
+            Self::Vip7 => (0.4, 2.2),
+            Self::Vip8 => (0.2, 2.),
+            Self::Vip9 => (0., 1.7),
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+// Fees an account pays on each market
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinanceFees {
+    market_type: MarketType,
+    tier: FeeTier,
+    bnb_discount: bool, // fees paid in BNB
+    zero_fee_markets: Vec<MarketTicker>, // promotions without maker or taker fees
+    waived: bool, // no fees on any market
+}
+
+impl BinanceFees {
+
+    const BNB_DISCOUNT: f64 = 0.25;
+    const FUTURES_BNB_DISCOUNT: f64 = 0.1;
+
+    // Spot
+    pub fn new(tier: FeeTier, bnb_discount: bool) -> Self {
+        Self {
+            market_type: MarketType::Spot,
+            tier,
+            bnb_discount,
+            zero_fee_markets: Vec::new(),
+            waived: false,
+        }
+    }
+
+    // USDⓈ-M futures
+    pub fn futures(tier: FeeTier, bnb_discount: bool) -> Self {
+        Self { market_type: MarketType::UsdFutures, ..Self::new(tier, bnb_discount) }
+    }
+
+    // No fees anywhere, e.g. to price an amount at the book without trading it
+    pub fn zero() -> Self {
+        Self { waived: true, ..Self::default() }
+    }
+
+    pub fn with_zero_fee_markets(mut self, market_tickers: Vec<MarketTicker>) -> Self {
+        self.zero_fee_markets = market_tickers;
+        self
+    }
+
+    // Quotes walk the book, so they take liquidity. Maker rates are only
+    // reported, a quote priced at the touch would need to rest and get filled
+    pub fn taker_fee_bps(&self, market: &Market) -> f64 {
+        self.fee_bps(market, Liquidity::Taker)
+    }
+
+    pub fn fee_bps(&self, market: &Market, liquidity: Liquidity) -> f64 {
+        if self.waived || self.zero_fee_markets.contains(&market.ticker()) {
+            return 0.;
+        }
+        let (maker_bps, taker_bps) = self.tier.rates_bps(self.market_type);
+        let fee_bps = match liquidity {
+            Liquidity::Maker => maker_bps,
+            Liquidity::Taker => taker_bps,
+        };
+        let bnb_discount = match self.market_type {
+            MarketType::Spot => Self::BNB_DISCOUNT,
+            MarketType::UsdFutures => Self::FUTURES_BNB_DISCOUNT,
+        };
+        if self.bnb_discount {
+            fee_bps * (1. - bnb_discount)
+        } else {
+            fee_bps
+        }
+    }
+
+}
+
+impl Default for BinanceFees {
+    fn default() -> Self {
+        Self::new(FeeTier::Regular, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_bps() {
+        let market = suppported_markets::ETHUSDT;
+        assert_eq!(BinanceFees::default().taker_fee_bps(&market), 10.);
+        assert_eq!(BinanceFees::new(FeeTier::Regular, true).taker_fee_bps(&market), 7.5);
+        assert_eq!(BinanceFees::new(FeeTier::Vip3, false).taker_fee_bps(&market), 6.);
+        assert_eq!(BinanceFees::new(FeeTier::Vip3, false).fee_bps(&market, Liquidity::Maker), 4.2);
+        assert_eq!(BinanceFees::new(FeeTier::Vip3, true).taker_fee_bps(&market), 4.5);
+
+        let promo = BinanceFees::new(FeeTier::Vip3, true)
+            .with_zero_fee_markets(vec![suppported_markets::BTCUSDT.ticker()]);
+        assert_eq!(promo.taker_fee_bps(&suppported_markets::BTCUSDT), 0.);
+        assert_eq!(promo.taker_fee_bps(&market), 4.5);
+        assert_eq!(BinanceFees::zero().taker_fee_bps(&market), 0.);
+
+        let futures = BinanceFees::futures(FeeTier::Regular, true);
+        assert_eq!(futures.taker_fee_bps(&supported_futures_markets::ETHUSDT), 4.5);
+        assert_eq!(BinanceFees::futures(FeeTier::Vip3, false).taker_fee_bps(&supported_futures_markets::ETHUSDT), 3.2);
+        assert_eq!(futures.fee_bps(&supported_futures_markets::ETHUSDT, Liquidity::Maker), 1.8);
+    }
+
+    #[test]
+    fn test_vip_level() {
+        let market = suppported_markets::ETHUSDT;
+        let fees = BinanceFees::new(FeeTier::from_vip_level(1).unwrap(), false);
+        assert_eq!(fees.taker_fee_bps(&market), 10.);
+        assert_eq!(fees.fee_bps(&market, Liquidity::Maker), 9.);
+        assert_eq!(FeeTier::from_vip_level(9).unwrap(), FeeTier::Vip9);
+        assert!(FeeTier::from_vip_level(10).is_err());
+    }
+
+}
@@ -0,0 +1,204 @@
+use super::*;
+use quoter::BinanceQuoter;
+use connector::BinanceEndpoints;
+use fees::{BinanceFees, FeeTier};
+use sync::BookHealth;
+use tape::Funding;
+use super::super::Quoter;
+use crate::asset::Domain;
+
+
+// USDⓈ-M perpetuals, quoted from books kept like the spot ones. Selling the
+// base opens a short and buying it a long, which pays or receives funding for
+// as long as the hedge stays open
+pub struct BinanceFuturesQuoter {
+    quoter: BinanceQuoter,
+}
+
+impl BinanceFuturesQuoter {
+
+    // Books are synced from depth snapshots once the stream is buffering updates
+    pub async fn create(
+        endpoints: &BinanceEndpoints,
+        markets: Vec<Market>,
+        book_depth: u32,
+        refresh_rate_ms: u32,
+    ) -> Result<Self> {
+        let quoter = BinanceQuoter::create_with_mode(
+            endpoints,
+            MarketType::UsdFutures,
+            markets,
+            book_depth,
+            refresh_rate_ms,
+            StreamMode::Diff,
+            None,
+        ).await?;
+        Ok(Self { quoter: quoter.with_fees(BinanceFees::futures(FeeTier::Regular, false)) })
+    }
+
+    // Perpetuals with the trading filters currently listed by the exchange
+    pub async fn fetch_markets(
+        endpoints: &BinanceEndpoints,
+        market_tickers: &[MarketTicker],
+    ) -> Result<Vec<Market>> {
+        BinanceQuoter::fetch_markets_of(endpoints, MarketType::UsdFutures, market_tickers).await
+    }
+
+    // Fees should be futures fees, see `BinanceFees::futures`
+    pub fn with_fees(&self, fees: BinanceFees) -> Self {
+        Self { quoter: self.quoter.with_fees(fees) }
+    }
+
+    pub fn tickers(&self) -> &[MarketTicker] {
+        &self.quoter.markets.tickers
+    }
+
+    pub fn health(&self, market: &MarketTicker) -> Result<BookHealth> {
+        self.quoter.health(market)
+    }
+
+    // Mark price and funding as of the last markPrice event
+    pub fn funding(&self, market: &MarketTicker) -> Result<Option<Funding>> {
+        Ok(self.quoter.tape(market)?.lock().unwrap().funding)
+    }
+
+    // Funding paid over `holding_ms` by the position a swap between the two
+    // assets opens, as a fraction of its notional. Negative when it's received
+    pub fn expected_funding_rate(
+        &self,
+        sell_token: &str,
+        buy_token: &str,
+        holding_ms: u64,
+    ) -> Result<f64> {
+        if holding_ms == 0 {
+            return Ok(0.);
+        }
+        let market = self.quoter.markets.get(sell_token, buy_token)
+            .ok_or(eyre::eyre!(format!("No perpetual between {sell_token} and {buy_token}")))?;
+        let ticker = market.ticker();
+        let funding = self.funding(&ticker)?
+            .ok_or(eyre::eyre!(format!("No funding rate for {ticker} yet")))?;
+        let long_rate = funding.expected_rate(utils::get_epoch_ms(), holding_ms);
+        if sell_token == market.base() {
+            Ok(-long_rate)
+        } else {
+            Ok(long_rate)
+        }
+    }
+
+    // Amounts are net of taker fees
+    pub async fn query(
+        &self,
+        sell_token: String,
+        buy_token: String,
+        sell_amount: f64,
+    ) -> Result<f64> {
+        self.quoter.query(sell_token, buy_token, sell_amount).await
+    }
+
+    pub async fn query_exact_out(
+        &self,
+        sell_token: String,
+        buy_token: String,
+        buy_amount: f64,
+    ) -> Result<f64> {
+        self.quoter.query_exact_out(sell_token, buy_token, buy_amount).await
+    }
+
+    pub async fn query_value(
+        &self,
+        sell_token: String,
+        buy_token: String,
+        sell_amount: f64,
+    ) -> Result<f64> {
+        self.quoter.query_value(sell_token, buy_token, sell_amount).await
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Quoter for BinanceFuturesQuoter {
+
+    async fn query(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        self.query(domain_sell_asset_id, domain_buy_asset_id, domain_sell_amount).await
+    }
+
+    async fn query_exact_out(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_buy_amount: f64,
+    ) -> Result<f64> {
+        self.query_exact_out(domain_sell_asset_id, domain_buy_asset_id, domain_buy_amount).await
+    }
+
+    async fn query_value(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        self.query_value(domain_sell_asset_id, domain_buy_asset_id, domain_sell_amount).await
+    }
+
+    async fn holding_cost(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        holding_ms: u64,
+    ) -> Result<f64> {
+        self.expected_funding_rate(&domain_sell_asset_id, &domain_buy_asset_id, holding_ms)
+    }
+
+    fn get_domain_id(&self) -> Domain {
+        Domain::BinanceFutures
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{MockBinance, futures_diff_event, mark_price_event, snapshot, wait_for};
+    use crate::asset::supported_assets;
+
+    #[tokio::test]
+    async fn test_futures_stream() {
+        let mock = MockBinance::start().await.unwrap();
+        let next_funding_ms = utils::get_epoch_ms() + 60_000;
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "2.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            futures_diff_event("ethusdt", 95, 102, 94, &[("1999.50", "1.0")], &[]),
+            futures_diff_event("ethusdt", 106, 108, 102, &[], &[("2001.00", "0"), ("2002.00", "3.0")]),
+            mark_price_event("ethusdt", "2000.10", "0.0001", next_funding_ms),
+        ]);
+        let quoter = BinanceFuturesQuoter::create(
+            &mock.endpoints,
+            vec![supported_futures_markets::ETHUSDT],
+            50,
+            100,
+        ).await.unwrap().with_fees(BinanceFees::zero());
+        let ticker = supported_futures_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.funding(&ticker).unwrap().is_some()).await;
+
+        assert_eq!(quoter.health(&ticker).unwrap(), BookHealth::Live);
+        let amount_out = quoter.query(String::from("USDT"), String::from("ETH"), 2002.).await.unwrap();
+        assert_eq!(amount_out, 1.);
+        assert_eq!(quoter.funding(&ticker).unwrap().unwrap().mark_price, 2000.1);
+
+        // shorts receive positive funding, longs pay it
+        let hour_ms = 60 * 60_000;
+        assert_eq!(quoter.expected_funding_rate("ETH", "USDT", hour_ms).unwrap(), -0.0001);
+        assert_eq!(quoter.expected_funding_rate("USDT", "ETH", hour_ms).unwrap(), 0.0001);
+        assert_eq!(quoter.expected_funding_rate("USDT", "ETH", 1_000).unwrap(), 0.);
+        let holding_cost = quoter.get_holding_cost(&supported_assets::ETH, &supported_assets::USDT, hour_ms).await;
+        assert_eq!(holding_cost.unwrap(), -0.0001);
+        assert!(quoter.expected_funding_rate("ARB", "USDT", hour_ms).is_err());
+    }
+
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+
+use super::*;
+use order_book::BookDelta;
+
+
+const DEFAULT_RETENTION_MS: u64 = 5 * 60_000;
+const DEFAULT_CHECKPOINT_EVERY: usize = 100; // deltas, about 10s of a 100ms stream
+
+#[derive(Debug, Clone)]
+enum HistoryEntry {
+    Checkpoint(BinanceOrderBook),
+    Delta(BookDelta),
+    Gap, // no book until the next checkpoint
+}
+
+// Past states of a book, kept as the deltas between full copies of the book
+// taken every `checkpoint_every` deltas and on every snapshot. Entries older
+// than `retention_ms` are dropped, except the checkpoint later deltas need
+#[derive(Debug)]
+pub(super) struct BookHistory {
+    retention_ms: u64,
+    checkpoint_every: usize,
+    entries: VecDeque<(u64, HistoryEntry)>, // by time
+    deltas_since_checkpoint: usize,
+}
+
+impl BookHistory {
+
+    pub fn new(retention_ms: u64, checkpoint_every: usize) -> Self {
+        Self {
+            retention_ms,
+            checkpoint_every: checkpoint_every.max(1),
+            entries: VecDeque::new(),
+            deltas_since_checkpoint: 0,
+        }
+    }
+
+    // The book as it was at `time_ms`
+    pub fn book_at(&self, time_ms: u64) -> Result<BinanceOrderBook> {
+        let end = self.entries.partition_point(|(entry_ms, _)| *entry_ms <= time_ms);
+        let start = self.entries.range(..end)
+            .rposition(|(_, entry)| !matches!(entry, HistoryEntry::Delta(_)))
+            .ok_or(eyre::eyre!(format!("No book history at {time_ms}ms")))?;
+        let HistoryEntry::Checkpoint(book) = &self.entries[start].1 else {
+            return Err(eyre::eyre!(format!("Book was out of sync at {time_ms}ms")));
+        };
+        let mut book = book.clone();
+        for (_, entry) in self.entries.range(start + 1..end) {
+            if let HistoryEntry::Delta(delta) = entry {
+                book.apply_delta(delta);
+            }
+        }
+        Ok(book)
+    }
+
+    // The book was replaced, e.g. by a snapshot
+    pub fn on_book(&mut self, book: &BinanceOrderBook) {
+        self.deltas_since_checkpoint = 0;
+        self.push(book.last_update_time(), HistoryEntry::Checkpoint(book.clone()));
+    }
+
+    // `book` is the book after the delta was applied
+    pub fn on_delta(&mut self, book: &BinanceOrderBook, delta: BookDelta) {
+        self.deltas_since_checkpoint += 1;
+        if self.deltas_since_checkpoint >= self.checkpoint_every {
+            self.on_book(book);
+        } else {
+            self.push(delta.time_ms, HistoryEntry::Delta(delta));
+        }
+    }
+
+    // Without a time the book is unknown from its last change on
+    pub fn on_gap(&mut self, time_ms: Option<u64>) {
+        self.deltas_since_checkpoint = 0;
+        self.push(time_ms.unwrap_or(0), HistoryEntry::Gap);
+    }
+
+    // Times only move forward, so a snapshot timed before it was applied
+    // doesn't end up before the events applied while it was on its way
+    fn push(&mut self, time_ms: u64, entry: HistoryEntry) {
+        let time_ms = match self.entries.back() {
+            Some((last_ms, _)) => time_ms.max(*last_ms),
+            None => time_ms,
+        };
+        let is_base = !matches!(entry, HistoryEntry::Delta(_));
+        self.entries.push_back((time_ms, entry));
+        if is_base {
+            self.evict(time_ms.saturating_sub(self.retention_ms));
+        }
+    }
+
+    // Keeps the last checkpoint or gap before `cutoff_ms`, so the book can
+    // still be rebuilt at the cutoff
+    fn evict(&mut self, cutoff_ms: u64) {
+        let keep_from = self.entries.iter()
+            .take_while(|(entry_ms, _)| *entry_ms <= cutoff_ms)
+            .enumerate()
+            .filter(|(_, (_, entry))| !matches!(entry, HistoryEntry::Delta(_)))
+            .map(|(i, _)| i)
+            .last();
+        if let Some(keep_from) = keep_from {
+            self.entries.drain(..keep_from);
+        }
+    }
+
+}
+
+impl Default for BookHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION_MS, DEFAULT_CHECKPOINT_EVERY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use order_book::Tick;
+
+    fn make_delta(time_ms: u64, bid_price: f64, bid_qty: f64) -> BookDelta {
+        BookDelta { time_ms, bids: vec![Tick { price: bid_price, qty: bid_qty }], asks: vec![] }
+    }
+
+    fn best_bid(history: &BookHistory, time_ms: u64) -> Option<(f64, f64)> {
+        let book = history.book_at(time_ms).unwrap();
+        let best_bid = book.bids().next().map(|level| (level.price, level.qty));
+        best_bid
+    }
+
+    fn replay(history: &mut BookHistory, book: &mut BinanceOrderBook, deltas: Vec<BookDelta>) {
+        for delta in deltas {
+            book.apply_delta(&delta);
+            history.on_delta(book, delta);
+        }
+    }
+
+    #[test]
+    fn test_book_at() {
+        let mut history = BookHistory::new(60_000, 3);
+        let mut book = BinanceOrderBook::from_levels(10, 0.01, 1_000, vec![(1999., 1.)], vec![(2001., 1.)]);
+        history.on_book(&book);
+        // the third delta is stored as a checkpoint
+        replay(&mut history, &mut book, (1..=5).map(|i| make_delta(1_000 + i * 100, 1999. + i as f64 * 0.01, 1.)).collect());
+
+        assert!(history.book_at(999).is_err());
+        assert_eq!(best_bid(&history, 1_000), Some((1999., 1.)));
+        assert_eq!(best_bid(&history, 1_150), Some((1999.01, 1.)));
+        assert_eq!(best_bid(&history, 1_300), Some((1999.03, 1.)));
+        assert_eq!(best_bid(&history, 1_499), Some((1999.04, 1.)));
+        // the latest book after the last event
+        assert_eq!(best_bid(&history, 10_000), Some((1999.05, 1.)));
+        assert_eq!(history.book_at(1_250).unwrap().bids().count(), 3);
+    }
+
+    #[test]
+    fn test_gaps_and_retention() {
+        let mut history = BookHistory::new(1_000, 2);
+        let mut book = BinanceOrderBook::from_levels(10, 0.01, 1_000, vec![(1999., 1.)], vec![(2001., 1.)]);
+        history.on_book(&book);
+        replay(&mut history, &mut book, vec![make_delta(1_100, 1999., 2.)]);
+        history.on_gap(Some(1_200));
+        assert_eq!(best_bid(&history, 1_199), Some((1999., 2.)));
+        assert!(history.book_at(1_200).is_err());
+
+        // a snapshot timestamped before the gap is kept after it
+        let mut book = BinanceOrderBook::from_levels(10, 0.01, 1_150, vec![(1998., 1.)], vec![(2001., 1.)]);
+        history.on_book(&book);
+        assert_eq!(best_bid(&history, 1_200), Some((1998., 1.)));
+
+        replay(&mut history, &mut book, (1..=6).map(|i| make_delta(1_200 + i * 500, 1998., 1. + i as f64)).collect());
+        // checkpoints before the cutoff are dropped, but the one at it is kept
+        assert!(history.book_at(1_200).is_err());
+        assert_eq!(best_bid(&history, 3_200), Some((1998., 5.)));
+        assert_eq!(best_bid(&history, 4_200), Some((1998., 7.)));
+    }
+
+}
@@ -1,30 +1,175 @@
-use std::collections::{HashMap, HashSet};
+use std::{borrow::Cow, collections::HashMap};
+use eyre::Result;
+
+use super::connector::{BinanceAPIExchangeInfo, BinanceAPISymbol, BinanceAPIFilter};
 
 type Asset = String;
-type Base = &'static str;
-type Quote = &'static str;
+type Base = Cow<'static, str>;
+type Quote = Cow<'static, str>;
+
+// Trading rules a market order has to respect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketFilters {
+    pub tick_size: f64, // PRICE_FILTER
+    pub step_size: f64, // LOT_SIZE, zero when sizes are not rounded
+    pub min_qty: f64,
+    pub max_qty: f64,
+    pub min_notional: f64, // NOTIONAL or MIN_NOTIONAL in the quote asset
+}
 
-#[derive(Clone, Copy)]
-pub struct Market(pub Base, pub Quote);
+impl MarketFilters {
+
+    // Only a price filter, sizes are unrestricted
+    pub const fn with_tick_size(tick_size: f64) -> Self {
+        Self {
+            tick_size,
+            step_size: 0.,
+            min_qty: 0.,
+            max_qty: f64::INFINITY,
+            min_notional: 0.,
+        }
+    }
+
+    // Largest multiple of the lot step not above `qty`
+    pub fn floor_lot(&self, qty: f64) -> f64 {
+        self.round_lot(qty, f64::floor)
+    }
+
+    // Smallest multiple of the lot step not below `qty`
+    pub fn ceil_lot(&self, qty: f64) -> f64 {
+        self.round_lot(qty, f64::ceil)
+    }
+
+    fn round_lot(&self, qty: f64, round: fn(f64) -> f64) -> f64 {
+        if self.step_size <= 0. {
+            return qty;
+        }
+        let steps = qty / self.step_size;
+        // sizes already on the step must not move because of float noise
+        let steps = if (steps - steps.round()).abs() < 1e-9 { steps.round() } else { round(steps) };
+        steps * self.step_size
+    }
+
+    // Rejects orders the exchange would refuse
+    pub fn check_order(&self, base_qty: f64, quote_qty: f64) -> Result<()> {
+        if base_qty <= 0. || base_qty < self.min_qty {
+            return Err(eyre::eyre!(format!("Order size {base_qty} is below the minimum of {}", self.min_qty)));
+        }
+        if base_qty > self.max_qty {
+            return Err(eyre::eyre!(format!("Order size {base_qty} is above the maximum of {}", self.max_qty)));
+        }
+        if quote_qty < self.min_notional {
+            return Err(eyre::eyre!(format!("Order notional {quote_qty} is below the minimum of {}", self.min_notional)));
+        }
+        Ok(())
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct Market {
+    base: Base,
+    quote: Quote,
+    filters: MarketFilters,
+}
 
 impl Market {
+
+    pub const fn new(base: &'static str, quote: &'static str, tick_size: f64) -> Self {
+        Self {
+            base: Cow::Borrowed(base),
+            quote: Cow::Borrowed(quote),
+            filters: MarketFilters::with_tick_size(tick_size),
+        }
+    }
+
+    pub fn with_filters<B, Q>(base: B, quote: Q, filters: MarketFilters) -> Self
+        where B: Into<Base>, Q: Into<Quote>
+    {
+        Self { base: base.into(), quote: quote.into(), filters }
+    }
     
     pub fn ticker(&self) -> String {
-        format!("{}{}", self.0, self.1).to_lowercase()
+        format!("{}{}", self.base, self.quote).to_lowercase()
     }
 
     pub fn base(&self) -> Asset {
-        self.0.to_string()
+        self.base.to_string()
     }
 
 
     pub fn quote(&self) -> Asset {
-        self.1.to_string()
+        self.quote.to_string()
+    }
+
+    pub fn tick_size(&self) -> f64 {
+        self.filters.tick_size
     }
+
+    pub fn filters(&self) -> &MarketFilters {
+        &self.filters
+    }
+}
+
+impl TryFrom<&BinanceAPISymbol> for Market {
+    type Error = eyre::Report;
+
+    fn try_from(symbol: &BinanceAPISymbol) -> Result<Self> {
+        let mut filters = MarketFilters::with_tick_size(0.);
+        for filter in &symbol.filters {
+            match filter {
+                BinanceAPIFilter::PriceFilter { tick_size } => {
+                    filters.tick_size = tick_size.parse()?;
+                },
+                BinanceAPIFilter::LotSize { min_qty, max_qty, step_size } => {
+                    filters.min_qty = min_qty.parse()?;
+                    filters.max_qty = max_qty.parse()?;
+                    filters.step_size = step_size.parse()?;
+                },
+                BinanceAPIFilter::MinNotional { min_notional, apply_to_market } if *apply_to_market => {
+                    filters.min_notional = filters.min_notional.max(min_notional.parse()?);
+                },
+                BinanceAPIFilter::Notional { min_notional, apply_min_to_market } if *apply_min_to_market => {
+                    filters.min_notional = filters.min_notional.max(min_notional.parse()?);
+                },
+                _ => {},
+            }
+        }
+        if filters.tick_size <= 0. {
+            return Err(eyre::eyre!(format!("No price filter for {}", symbol.symbol)));
+        }
+        Ok(Self::with_filters(symbol.base_asset.clone(), symbol.quote_asset.clone(), filters))
+    }
+}
+
+// Markets for the requested tickers, in the order they were requested
+pub fn markets_from_exchange_info(
+    exchange_info: &BinanceAPIExchangeInfo,
+    tickers: &[String],
+) -> Result<Vec<Market>> {
+    tickers.iter()
+        .map(|ticker| {
+            let symbol = exchange_info.symbols.iter()
+                .find(|symbol| symbol.symbol.eq_ignore_ascii_case(ticker))
+                .ok_or(eyre::eyre!(format!("No exchange info for {ticker}")))?;
+            if symbol.status != "TRADING" {
+                return Err(eyre::eyre!(format!("{} is not trading ({})", symbol.symbol, symbol.status)));
+            }
+            Market::try_from(symbol)
+        })
+        .collect()
+}
+
+// Reads a cached `/api/v3/exchangeInfo` response
+pub fn load_markets(path: &str, tickers: &[String]) -> Result<Vec<Market>> {
+    let exchange_info = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    markets_from_exchange_info(&exchange_info, tickers)
 }
 
+#[derive(Clone)]
 pub struct Markets {
     markets: HashMap<(String, String), Market>,
+    neighbours: HashMap<Asset, Vec<Asset>>, // assets with a market against the key
     pub tickers: Vec<String>,
 }
 
@@ -33,18 +178,55 @@ impl Markets {
     fn new() -> Self {
         Self {
             markets: HashMap::new(), 
+            neighbours: HashMap::new(),
             tickers: Vec::new(),
         }
     }
 
     fn add(mut self, market: Market) -> Self {
-        let (base, quote) = (String::from(market.0), String::from(market.1));
-        self.markets.insert((base.clone(), quote.clone()), market);
-        self.markets.insert((quote, base), market);
+        let (base, quote) = (market.base(), market.quote());
         self.tickers.push(market.ticker());
+        self.markets.insert((base.clone(), quote.clone()), market.clone());
+        self.markets.insert((quote.clone(), base.clone()), market);
+        self.neighbours.entry(base.clone()).or_default().push(quote.clone());
+        self.neighbours.entry(quote).or_default().push(base);
         self
     }
 
+    // Asset paths from `from` to `to` through at most `max_hops` markets,
+    // without visiting an asset twice
+    pub fn paths(&self, from: &str, to: &str, max_hops: usize) -> Vec<Vec<Asset>> {
+        let mut paths = Vec::new();
+        let mut path = vec![from.to_string()];
+        self.extend_paths(&mut path, to, max_hops, &mut paths);
+        paths
+    }
+
+    fn extend_paths(
+        &self, 
+        path: &mut Vec<Asset>, 
+        to: &str, 
+        hops_left: usize, 
+        paths: &mut Vec<Vec<Asset>>,
+    ) {
+        if hops_left == 0 {
+            return;
+        }
+        let last = path.last().unwrap().clone();
+        for next in self.neighbours.get(&last).into_iter().flatten() {
+            if path.contains(next) {
+                continue;
+            }
+            path.push(next.clone());
+            if next == to {
+                paths.push(path.clone());
+            } else {
+                self.extend_paths(path, to, hops_left - 1, paths);
+            }
+            path.pop();
+        }
+    }
+
     pub fn get_ticker<T, D>(&self, asset_a: T, asset_b: D) -> Option<String>
         where T: Into<Asset>, D: Into<Asset>
     {
@@ -65,3 +247,120 @@ impl From<Vec<Market>> for Markets {
             .fold(Self::new(), |markets, market| markets.add(market))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quoters::binance::suppported_markets;
+
+    #[test]
+    fn test_paths() {
+        let markets: Markets = vec![
+            suppported_markets::ETHUSDT,
+            suppported_markets::BTCUSDT,
+            suppported_markets::ARBUSDT,
+            suppported_markets::ETHBTC,
+        ].into();
+        let mut paths = markets.paths("ARB", "ETH", 3);
+        paths.sort();
+        assert_eq!(paths, vec![
+            vec!["ARB", "USDT", "BTC", "ETH"],
+            vec!["ARB", "USDT", "ETH"],
+        ]);
+        assert_eq!(markets.paths("ARB", "ETH", 1), Vec::<Vec<String>>::new());
+        assert_eq!(markets.paths("ETH", "USDT", 2).len(), 2);
+        assert!(markets.paths("ETH", "SOL", 3).is_empty());
+    }
+
+
+    #[test]
+    fn test_markets_from_exchange_info() {
+        let exchange_info = r#"{
+            "timezone": "UTC",
+            "symbols": [{
+                "symbol": "ETHUSDT",
+                "status": "TRADING",
+                "baseAsset": "ETH",
+                "quoteAsset": "USDT",
+                "filters": [
+                    {"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000000.00", "tickSize": "0.01"},
+                    {"filterType": "LOT_SIZE", "minQty": "0.00010000", "maxQty": "9000.00000000", "stepSize": "0.00010000"},
+                    {"filterType": "ICEBERG_PARTS", "limit": 10},
+                    {"filterType": "NOTIONAL", "minNotional": "5.00", "applyMinToMarket": true, "maxNotional": "9000000.00", "applyMaxToMarket": false, "avgPriceMins": 5}
+                ]
+            }, {
+                "symbol": "LUNAUSDT",
+                "status": "BREAK",
+                "baseAsset": "LUNA",
+                "quoteAsset": "USDT",
+                "filters": [{"filterType": "PRICE_FILTER", "minPrice": "0.0001", "maxPrice": "1000.00", "tickSize": "0.0001"}]
+            }]
+        }"#;
+        let exchange_info = serde_json::from_str(exchange_info).unwrap();
+
+        let markets = markets_from_exchange_info(&exchange_info, &[String::from("ethusdt")]).unwrap();
+        assert_eq!(markets[0].ticker(), "ethusdt");
+        assert_eq!(markets[0].filters(), &MarketFilters {
+            tick_size: 0.01,
+            step_size: 0.0001,
+            min_qty: 0.0001,
+            max_qty: 9000.,
+            min_notional: 5.,
+        });
+        assert!(markets_from_exchange_info(&exchange_info, &[String::from("lunausdt")]).is_err());
+        assert!(markets_from_exchange_info(&exchange_info, &[String::from("arbusdt")]).is_err());
+    }
+
+    #[test]
+    fn test_markets_from_futures_exchange_info() {
+        let exchange_info = r#"{
+            "timezone": "UTC",
+            "symbols": [{
+                "symbol": "ETHUSDT",
+                "pair": "ETHUSDT",
+                "contractType": "PERPETUAL",
+                "status": "TRADING",
+                "baseAsset": "ETH",
+                "quoteAsset": "USDT",
+                "filters": [
+                    {"filterType": "PRICE_FILTER", "minPrice": "39.86", "maxPrice": "306177", "tickSize": "0.01"},
+                    {"filterType": "LOT_SIZE", "minQty": "0.001", "maxQty": "10000", "stepSize": "0.001"},
+                    {"filterType": "MARKET_LOT_SIZE", "minQty": "0.001", "maxQty": "2000", "stepSize": "0.001"},
+                    {"filterType": "MIN_NOTIONAL", "notional": "20"}
+                ]
+            }]
+        }"#;
+        let exchange_info = serde_json::from_str(exchange_info).unwrap();
+
+        let markets = markets_from_exchange_info(&exchange_info, &[String::from("ethusdt")]).unwrap();
+        assert_eq!(markets[0].filters(), &MarketFilters {
+            tick_size: 0.01,
+            step_size: 0.001,
+            min_qty: 0.001,
+            max_qty: 10000.,
+            min_notional: 20.,
+        });
+    }
+
+    #[test]
+    fn test_filters() {
+        let filters = MarketFilters {
+            step_size: 0.001,
+            min_qty: 0.001,
+            max_qty: 100.,
+            min_notional: 5.,
+            ..MarketFilters::with_tick_size(0.01)
+        };
+        assert!((filters.floor_lot(1.23456) - 1.234).abs() < 1e-12);
+        assert!((filters.ceil_lot(1.23456) - 1.235).abs() < 1e-12);
+        // already on the step
+        assert_eq!(filters.floor_lot(0.3), filters.ceil_lot(0.3));
+        assert_eq!(MarketFilters::with_tick_size(0.01).floor_lot(1.23456), 1.23456);
+
+        assert!(filters.check_order(1., 2000.).is_ok());
+        assert!(filters.check_order(0.0005, 1.).is_err());
+        assert!(filters.check_order(101., 202_000.).is_err());
+        assert!(filters.check_order(0.002, 4.).is_err());
+    }
+
+}
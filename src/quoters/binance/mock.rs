@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use futures::{stream::StreamExt, sink::SinkExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::{tungstenite::protocol::Message, accept_async};
+
+use super::*;
+use connector::BinanceEndpoints;
+
+
+// What the mock stream does on a connection, in order
+#[derive(Debug, Clone)]
+pub(super) enum MockEvent {
+    Frame(String), // sent as is, so it can be malformed
+    Pause(u64), // ms
+    Disconnect, // closes the connection and the quoter reconnects
+}
+
+// Diff-depth event on the combined stream, levels are (price, qty)
+pub(super) fn diff_event(
+    ticker: &str,
+    first_update_id: u64,
+    final_update_id: u64,
+    bids: &[(&str, &str)],
+    asks: &[(&str, &str)],
+) -> MockEvent {
+    let data = depth_update(ticker, first_update_id, final_update_id, bids, asks);
+    stream_event(&format!("{ticker}@depth@100ms"), data)
+}
+
+// Futures events also name the final update ID of the event before them
+pub(super) fn futures_diff_event(
+    ticker: &str,
+    first_update_id: u64,
+    final_update_id: u64,
+    previous_update_id: u64,
+    bids: &[(&str, &str)],
+    asks: &[(&str, &str)],
+) -> MockEvent {
+    let mut data = depth_update(ticker, first_update_id, final_update_id, bids, asks);
+    data["pu"] = previous_update_id.into();
+    stream_event(&format!("{ticker}@depth@100ms"), data)
+}
+
+pub(super) fn mark_price_event(ticker: &str, mark_price: &str, funding_rate: &str, next_funding_ms: u64) -> MockEvent {
+    let data = serde_json::json!({
+        "e": "markPriceUpdate",
+        "E": utils::get_epoch_ms(),
+        "s": ticker.to_uppercase(),
+        "p": mark_price,
+        "i": mark_price,
+        "P": mark_price,
+        "r": funding_rate,
+        "T": next_funding_ms,
+    });
+    stream_event(&format!("{ticker}@markPrice@1s"), data)
+}
+
+fn depth_update(
+    ticker: &str,
+    first_update_id: u64,
+    final_update_id: u64,
+    bids: &[(&str, &str)],
+    asks: &[(&str, &str)],
+) -> serde_json::Value {
+    serde_json::json!({
+        "e": "depthUpdate",
+        "E": utils::get_epoch_ms(),
+        "s": ticker.to_uppercase(),
+        "U": first_update_id,
+        "u": final_update_id,
+        "b": bids,
+        "a": asks,
+    })
+}
+
+fn stream_event(stream: &str, data: serde_json::Value) -> MockEvent {
+    MockEvent::Frame(serde_json::json!({ "stream": stream, "data": data }).to_string())
+}
+
+// Response of /api/v3/depth and /fapi/v1/depth
+pub(super) fn snapshot(
+    last_update_id: u64,
+    bids: &[(&str, &str)],
+    asks: &[(&str, &str)],
+) -> serde_json::Value {
+    serde_json::json!({ "lastUpdateId": last_update_id, "bids": bids, "asks": asks })
+}
+
+#[derive(Default)]
+struct MockState {
+    snapshots: HashMap<String, VecDeque<serde_json::Value>>, // the last one is served again
+    connections: VecDeque<Vec<MockEvent>>, // later connections stay open and quiet
+    depth_requests: usize,
+    connection_count: usize,
+}
+
+// Polls until the quoter has caught up with the scripted stream
+pub(super) async fn wait_for(condition: impl Fn() -> bool) {
+    let start = std::time::Instant::now();
+    while !condition() {
+        assert!(start.elapsed().as_millis() < 5_000, "Timed out waiting for the stream");
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+// Local stand-in for the REST API and the combined stream, serving scripted
+// snapshots and events so the quoter can be tested without the exchange
+pub(super) struct MockBinance {
+    pub endpoints: BinanceEndpoints,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBinance {
+
+    pub async fn start() -> Result<Self> {
+        let api_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let stream_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoints = BinanceEndpoints {
+            stream: format!("ws://{}", stream_listener.local_addr()?),
+            api: format!("http://{}", api_listener.local_addr()?),
+        };
+        let state = Arc::new(Mutex::new(MockState::default()));
+        tokio::spawn(serve_api(api_listener, state.clone()));
+        tokio::spawn(serve_stream(stream_listener, state.clone()));
+        Ok(Self { endpoints, state })
+    }
+
+    pub fn add_snapshot(&self, ticker: &str, snapshot: serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        state.snapshots.entry(ticker.to_uppercase()).or_default().push_back(snapshot);
+    }
+
+    // Script of the next connection that has none yet
+    pub fn add_connection(&self, events: Vec<MockEvent>) {
+        self.state.lock().unwrap().connections.push_back(events);
+    }
+
+    pub fn depth_requests(&self) -> usize {
+        self.state.lock().unwrap().depth_requests
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().unwrap().connection_count
+    }
+
+}
+
+async fn serve_api(listener: TcpListener, state: Arc<Mutex<MockState>>) -> Result<()> {
+    loop {
+        let (socket, _) = listener.accept().await?;
+        tokio::spawn(handle_request(socket, state.clone()));
+    }
+}
+
+// Only GET .../depth?symbol=..., of spot or futures
+async fn handle_request(mut socket: TcpStream, state: Arc<Mutex<MockState>>) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let target = request.split_whitespace().nth(1).unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let symbol = query.split('&')
+        .find_map(|param| param.strip_prefix("symbol="))
+        .unwrap_or_default();
+    let body = {
+        let mut state = state.lock().unwrap();
+        let is_depth = path == "/api/v3/depth" || path == "/fapi/v1/depth";
+        if is_depth {
+            state.depth_requests += 1;
+        }
+        match state.snapshots.get_mut(symbol) {
+            Some(snapshots) if is_depth => {
+                if snapshots.len() > 1 {
+                    snapshots.pop_front()
+                } else {
+                    snapshots.front().cloned()
+                }
+            },
+            _ => None,
+        }
+    };
+    let (status, body) = match body {
+        Some(body) => ("200 OK", body.to_string()),
+        None => ("404 Not Found", String::from(r#"{"code":-1121,"msg":"Invalid symbol."}"#)),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+async fn serve_stream(listener: TcpListener, state: Arc<Mutex<MockState>>) -> Result<()> {
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let events = {
+            let mut state = state.lock().unwrap();
+            state.connection_count += 1;
+            state.connections.pop_front().unwrap_or_default()
+        };
+        tokio::spawn(play_connection(socket, events));
+    }
+}
+
+async fn play_connection(socket: TcpStream, events: Vec<MockEvent>) -> Result<()> {
+    let mut stream = accept_async(socket).await?;
+    for event in events {
+        match event {
+            MockEvent::Frame(frame) => stream.send(Message::Text(frame)).await?,
+            MockEvent::Pause(ms) => tokio::time::sleep(std::time::Duration::from_millis(ms)).await,
+            MockEvent::Disconnect => {
+                stream.close(None).await?;
+                return Ok(());
+            },
+        }
+    }
+    // idle until the quoter goes away
+    while let Some(Ok(_)) = stream.next().await {}
+    Ok(())
+}
@@ -3,20 +3,40 @@ mod order_book;
 mod connector;
 mod utils;
 mod market;
+mod snapshot;
+mod sync;
+mod route;
+mod fees;
+mod tape;
+mod analytics;
+mod futures_quoter;
+mod capture;
+mod history;
+#[cfg(test)]
+mod mock;
 
 pub use quoter::BinanceQuoter;
-pub use market::Market;
+pub use futures_quoter::BinanceFuturesQuoter;
+pub use market::{Market, Markets, load_markets};
+pub use route::route_markets;
+pub use order_book::{BinanceOrderBook, SwapType};
+pub use snapshot::{BinanceSnapshot, BinanceSnapshotQuoter};
+pub use sync::BookHealth;
+pub use fees::{BinanceFees, FeeTier, Liquidity};
+pub use connector::BinanceEndpoints;
+pub use capture::BinanceReplay;
 
 use std::{
     collections::HashMap,
     sync::{Mutex, Arc}
 };
 use eyre::Result;
-use order_book::BinanceOrderBook;
+use super::BPS;
 
 
 type MarketTicker = String;
-type OrderBooksShared = Arc<HashMap<String, Arc<Mutex<(BinanceOrderBook,)>>>>;
+type OrderBooksShared = Arc<HashMap<String, Arc<Mutex<(BinanceOrderBook, sync::BookSync)>>>>;
+type MarketTapesShared = Arc<HashMap<String, Arc<Mutex<tape::MarketTape>>>>;
 
 
 #[derive(Clone, Copy)]
@@ -58,12 +78,56 @@ impl TryFrom<u8> for BookSize {
     }
 }
 
+// Spot or USDⓈ-M perpetuals, which share the book and stream formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MarketType {
+    Spot,
+    UsdFutures,
+}
+
+impl MarketType {
+
+    fn depth_endpoint(&self, api_endpoint: &str) -> String {
+        match self {
+            MarketType::Spot => format!("{api_endpoint}/api/v3/depth"),
+            MarketType::UsdFutures => format!("{api_endpoint}/fapi/v1/depth"),
+        }
+    }
+
+    fn exchange_info_endpoint(&self, api_endpoint: &str) -> String {
+        match self {
+            MarketType::Spot => format!("{api_endpoint}/api/v3/exchangeInfo"),
+            MarketType::UsdFutures => format!("{api_endpoint}/fapi/v1/exchangeInfo"),
+        }
+    }
+
+}
+
+// How books follow the exchange
+#[derive(Clone, Copy)]
+enum StreamMode {
+    Diff, // diff-depth events stitched onto REST snapshots
+    Partial(BookSize), // the top levels, replaced on every message
+}
+
 pub mod suppported_markets {
     use super::Market;
 
-    // Supported markets
-    pub const ETHUSDT: Market = Market("ETH", "USDT");
-    pub const BTCUSDT: Market = Market("BTC", "USDT");
-    pub const ARBUSDT: Market = Market("ARB", "USDT");
+    // Supported markets, without lot size or notional filters.
+    // `load_markets` and `BinanceQuoter::fetch_markets` give the exchange's rules
+    pub const ETHUSDT: Market = Market::new("ETH", "USDT", 0.01);
+    pub const BTCUSDT: Market = Market::new("BTC", "USDT", 0.01);
+    pub const ARBUSDT: Market = Market::new("ARB", "USDT", 0.0001);
+    pub const ETHBTC: Market = Market::new("ETH", "BTC", 0.00001);
+
+    pub const ALL: [Market; 4] = [ETHUSDT, BTCUSDT, ARBUSDT, ETHBTC];
+}
+
+pub mod supported_futures_markets {
+    use super::Market;
+
+    // USDⓈ-M perpetuals, ticks differ from the spot markets.
+    // `BinanceFuturesQuoter::fetch_markets` gives the exchange's rules
+    pub const ETHUSDT: Market = Market::new("ETH", "USDT", 0.01);
 }
 
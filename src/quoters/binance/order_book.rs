@@ -1,101 +1,210 @@
+use std::collections::BTreeMap;
+
 use super::*;
 use connector::{BinanceAPIOrderBookUpdateData, BinanceAPIOrderBookData};
 
 
-#[derive(Debug, Clone)]
+type PriceTicks = i64; // price as a multiple of the market's tick size
+type Lots = i64; // quantity in units of the finest step size on Binance
+type Notional = i128; // lots times ticks, the quote of a fill before scaling
+
+// Finest tick size on Binance, used for books recorded without one
+const DEFAULT_TICK_SIZE: f64 = 0.00000001;
+const LOTS_PER_UNIT: f64 = 100_000_000.;
+
+// Price levels keyed by integer ticks with integer lot quantities, so fills
+// across many levels don't accumulate float error; bids and asks are both
+// ascending, so the best bid is the last level and the best ask the first
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(from = "BinanceOrderBookRecord", into = "BinanceOrderBookRecord")]
 pub struct BinanceOrderBook {
+    depth: u32,
+    tick_size: f64,
+    tick_fraction: (i64, i64), // the tick size as an exact numerator and denominator
+    last_update_time: u64,
+    bids: BTreeMap<PriceTicks, Lots>,
+    asks: BTreeMap<PriceTicks, Lots>,
+}
+
+// Levels as sorted lists, which is how books are recorded
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct BinanceOrderBookRecord {
     data: BinanceOrderBookData,
     depth: u32,
+    #[serde(default = "default_tick_size")]
+    tick_size: f64,
 }
 
-#[derive(Debug, Clone)]
+fn default_tick_size() -> f64 {
+    DEFAULT_TICK_SIZE
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct BinanceOrderBookData {
     pub last_update_time: u64,
     pub bids: Vec<Tick>,
     pub asks: Vec<Tick>,
 }
 
+#[derive(Clone, Copy)]
+enum Side {
+    Bid,
+    Ask,
+}
+
 impl BinanceOrderBook {
 
-    pub fn new(depth: u32, data: BinanceOrderBookData) -> Self {
-        Self { data, depth }
+    pub fn new(depth: u32, tick_size: f64, data: BinanceOrderBookData) -> Self {
+        let mut book = Self::empty(depth, tick_size);
+        book.last_update_time = data.last_update_time;
+        book.update_bids(data.bids);
+        book.update_asks(data.asks);
+        book
     }
 
-    pub fn update_from_stream(
-        &mut self, 
-        update_data: BinanceAPIOrderBookUpdateData
-    ) -> Result<()> {
-        self.update_bids(Self::parse_side(update_data.b)?);
-        self.update_asks(Self::parse_side(update_data.a)?);
-        self.data.last_update_time = update_data.E;
-        Ok(())
+    pub fn empty(depth: u32, tick_size: f64) -> Self {
+        Self {
+            depth,
+            tick_size,
+            tick_fraction: tick_fraction(tick_size),
+            last_update_time: 0,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    // Levels are (price, qty); levels falling on the same tick are merged
+    pub fn from_levels(
+        depth: u32,
+        tick_size: f64,
+        last_update_time: u64,
+        bids: Vec<(f64, f64)>,
+        asks: Vec<(f64, f64)>,
+    ) -> Self {
+        let mut book = Self::empty(depth, tick_size);
+        book.last_update_time = last_update_time;
+        for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+            for (price, qty) in levels {
+                let ticks = book.to_ticks(price);
+                *book.side_mut(side).entry(ticks).or_insert(0) += to_lots(qty);
+            }
+            book.truncate(side);
+        }
+        book
+    }
+
+    pub fn tick_size(&self) -> f64 {
+        self.tick_size
+    }
+
+    pub fn last_update_time(&self) -> u64 {
+        self.last_update_time
+    }
+
+    // Best first
+    pub fn bids(&self) -> impl Iterator<Item = Tick> + '_ {
+        self.bids.iter().rev().map(|(ticks, lots)| Tick::new(self.to_price(*ticks), to_qty(*lots)))
+    }
+
+    // Best first
+    pub fn asks(&self) -> impl Iterator<Item = Tick> + '_ {
+        self.asks.iter().map(|(ticks, lots)| Tick::new(self.to_price(*ticks), to_qty(*lots)))
+    }
+
+    pub fn mid_price(&self) -> Option<f64> {
+        let best_bid = self.bids().next()?.price;
+        let best_ask = self.asks().next()?.price;
+        Some((best_bid + best_ask) / 2.)
+    }
+
+    pub fn apply_delta(&mut self, delta: &BookDelta) {
+        for (side, levels) in [(Side::Bid, &delta.bids), (Side::Ask, &delta.asks)] {
+            for tick in levels {
+                self.set_level(side, *tick);
+            }
+            self.truncate(side);
+        }
+        self.data_updated(delta.time_ms);
+    }
+
+    fn data_updated(&mut self, last_update_time: u64) {
+        self.last_update_time = last_update_time;
     }
 
     fn update_bids(&mut self, updated_ticks: Vec<Tick>) {
-        // ? Assume ticks are ordered descending
-        self.data.bids = Self::update_ticks(
-            self.data.bids.clone(), 
-            updated_ticks, 
-            self.depth, 
-            false
-        );
+        for tick in updated_ticks {
+            self.set_level(Side::Bid, tick);
+        }
+        self.truncate(Side::Bid);
     }
 
     fn update_asks(&mut self, updated_ticks: Vec<Tick>) {
-        // ? Assume ticks are ordered ascending
-        self.data.asks = Self::update_ticks(
-            self.data.asks.clone(), 
-            updated_ticks, 
-            self.depth, 
-            true
-        );
+        for tick in updated_ticks {
+            self.set_level(Side::Ask, tick);
+        }
+        self.truncate(Side::Ask);
     }
 
-    fn update_ticks(
-        mut book: Vec<Tick>, 
-        updated_ticks: Vec<Tick>,
-        depth: u32, 
-        is_ascending: bool
-    ) -> Vec<Tick> {
-        // todo: efficient design
-        if book.is_empty() {
-            return updated_ticks;
+    // A zero quantity removes the level
+    fn set_level(&mut self, side: Side, tick: Tick) {
+        let ticks = self.to_ticks(tick.price);
+        let lots = to_lots(tick.qty);
+        let levels = self.side_mut(side);
+        if lots > 0 {
+            levels.insert(ticks, lots);
+        } else {
+            levels.remove(&ticks);
         }
-        for new_tick in updated_ticks {
-            let mut is_detected = false;
-            for i in 0..book.len() {
-                let old_tick = &mut book[i];
-                if old_tick.price == new_tick.price {
-                    old_tick.qty = new_tick.qty;
-                    is_detected = true;
-                    break;
-                }
-                // else if 
-                //     (is_ascending && old_tick.price>new_tick.price) || 
-                //     (!is_ascending && old_tick.price<new_tick.price) 
-                // {
-                //     book.insert(i, new_tick);
-                //     break;
-                // } else if i == book.len()-1 {
-                //     book.push(new_tick);
-                //     break;
-                // }
-            }
-            if !is_detected {
-                book.push(new_tick);
-            } 
-            
+    }
+
+    // Drops the levels furthest from the touch beyond the tracked depth
+    fn truncate(&mut self, side: Side) {
+        let depth = self.depth as usize;
+        let levels = self.side_mut(side);
+        while levels.len() > depth {
+            match side {
+                Side::Bid => levels.pop_first(),
+                Side::Ask => levels.pop_last(),
+            };
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<PriceTicks, Lots> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    fn to_ticks(&self, price: f64) -> PriceTicks {
+        let (numer, denom) = self.tick_fraction;
+        (price * denom as f64 / numer as f64).round() as PriceTicks
+    }
+
+    // Dividing an exact integer once keeps prices like 1890.01 exact
+    fn to_price(&self, ticks: PriceTicks) -> f64 {
+        let (numer, denom) = self.tick_fraction;
+        (ticks * numer) as f64 / denom as f64
+    }
+
+    fn to_notional(&self, quote: f64) -> Notional {
+        let (numer, denom) = self.tick_fraction;
+        (quote * LOTS_PER_UNIT * denom as f64 / numer as f64).round() as Notional
+    }
+
+    // Scaled once, so the quote of a fill is as exact as a single division
+    fn to_quote(&self, notional: Notional) -> f64 {
+        let (numer, denom) = self.tick_fraction;
+        (notional * numer as Notional) as f64 / (LOTS_PER_UNIT * denom as f64)
+    }
+
+    // Levels a swap of this type trades against, best first
+    fn book_side(&self, swap_type: SwapType) -> Box<dyn Iterator<Item = (PriceTicks, Lots)> + '_> {
+        if let SwapType::Sell = swap_type {
+            Box::new(self.bids.iter().rev().map(|(ticks, lots)| (*ticks, *lots)))
+        } else {
+            Box::new(self.asks.iter().map(|(ticks, lots)| (*ticks, *lots)))
         }
-        book.sort_by(|a, b| {
-            if is_ascending {
-                a.price.partial_cmp(&b.price).unwrap()
-            } else {
-                b.price.partial_cmp(&a.price).unwrap()
-            }
-        });
-        book.retain(|tick| tick.qty > 0.);
-        book.truncate(depth as usize);
-        book
     }
 
     pub fn query_exact_base(
@@ -103,26 +212,24 @@ impl BinanceOrderBook {
         swap_type: SwapType, 
         base_amount: f64
     ) -> (f64, f64) {
-        let book_side = if let SwapType::Sell = swap_type { 
-            &self.data.bids 
-        } else {
-            &self.data.asks
-        };
-
-        let mut base_left = base_amount;
-        let mut quote_used = 0.;
-        for order in book_side.iter() {
-            let base_fill = order.qty.min(base_left);
-            let quote_fill = base_fill * order.price;
-            base_left -= base_fill;
-            quote_used += quote_fill;
-
-            if base_left == 0. {
+        let base_lots = to_lots(base_amount);
+        let mut lots_left = base_lots;
+        let mut notional_used = 0;
+        for (ticks, lots) in self.book_side(swap_type) {
+            if lots_left <= 0 {
                 break;
             }
+            let lots_fill = lots.min(lots_left);
+            lots_left -= lots_fill;
+            notional_used += lots_fill as Notional * ticks as Notional;
         }
-        let base_used = base_amount - base_left;
-        (base_used, quote_used)
+        // a full fill uses exactly the amount asked for
+        let base_used = if lots_left <= 0 {
+            base_amount
+        } else {
+            to_qty(base_lots - lots_left)
+        };
+        (base_used, self.to_quote(notional_used))
     }
 
     pub fn query_exact_quote(
@@ -130,43 +237,108 @@ impl BinanceOrderBook {
         swap_type: SwapType,
         quote_amount: f64
     ) -> (f64, f64) {
-        let book_side = if let SwapType::Sell = swap_type { 
-            &self.data.bids 
+        let notional = self.to_notional(quote_amount);
+        let mut notional_left = notional;
+        let mut lots_used = 0;
+        // a level filled in part takes a fraction of a lot
+        let mut partial_lots = 0.;
+        for (ticks, lots) in self.book_side(swap_type) {
+            if notional_left <= 0 {
+                break;
+            }
+            let level_notional = lots as Notional * ticks as Notional;
+            if level_notional <= notional_left {
+                notional_left -= level_notional;
+                lots_used += lots;
+            } else {
+                partial_lots = notional_left as f64 / ticks as f64;
+                notional_left = 0;
+            }
+        }
+        let quote_used = if notional_left <= 0 {
+            quote_amount
         } else {
-            &self.data.asks 
+            self.to_quote(notional - notional_left)
         };
+        (quote_used, (lots_used as f64 + partial_lots) / LOTS_PER_UNIT)
+    }
 
-        let mut quote_left = quote_amount;
-        let mut base_used = 0.;
-        for order in book_side.iter() {
-            let quote_fill = quote_left.min(order.qty * order.price);
-            let base_fill = quote_fill / order.price;
-            quote_left -= quote_fill;
-            base_used += base_fill;
-
-            if quote_left == 0. {
+    // Removes the base liquidity a fill took from the side it traded against
+    pub fn take_base(&mut self, swap_type: SwapType, base_amount: f64) {
+        let levels = match swap_type {
+            SwapType::Sell => &mut self.bids,
+            SwapType::Buy => &mut self.asks,
+        };
+        let mut lots_left = to_lots(base_amount);
+        while lots_left > 0 {
+            let level = match swap_type {
+                SwapType::Sell => levels.last_entry(),
+                SwapType::Buy => levels.first_entry(),
+            };
+            let Some(mut level) = level else {
                 break;
+            };
+            let lots_fill = (*level.get()).min(lots_left);
+            *level.get_mut() -= lots_fill;
+            lots_left -= lots_fill;
+            if *level.get() <= 0 {
+                level.remove_entry();
             }
         }
-        let quote_used = quote_amount - quote_left;
-        (quote_used, base_used) 
     }
 
     fn parse_side(side: Vec<Vec<String>>) -> Result<Vec<Tick>> {
-        side.iter().map(|tick| {
-            Ok(Tick::new(
-                tick[0].parse::<f64>()?, 
-                tick[1].parse::<f64>()?
-            ))
-        } as Result<Tick>).collect()
+        side.iter().map(Tick::try_from).collect()
+    }
+
+}
+
+// (numerator, denominator) from the decimal digits of the tick, e.g. (1, 100)
+// for 0.01 and (10, 1) for 10, so neither a tick below 1 nor above rounds away
+fn tick_fraction(tick_size: f64) -> (i64, i64) {
+    let mut denom: i64 = 1;
+    loop {
+        let scaled = tick_size * denom as f64;
+        let is_whole = scaled.round() >= 1. && (scaled.round() - scaled).abs() <= scaled * 1e-9;
+        if is_whole || denom >= 1_000_000_000_000 {
+            return (scaled.round() as i64, denom);
+        }
+        denom *= 10;
+    }
+}
+
+fn to_lots(qty: f64) -> Lots {
+    (qty * LOTS_PER_UNIT).round() as Lots
+}
+
+fn to_qty(lots: Lots) -> f64 {
+    lots as f64 / LOTS_PER_UNIT
+}
+
+impl From<BinanceOrderBookRecord> for BinanceOrderBook {
+    fn from(record: BinanceOrderBookRecord) -> Self {
+        Self::new(record.depth, record.tick_size, record.data)
     }
+}
 
+impl From<BinanceOrderBook> for BinanceOrderBookRecord {
+    fn from(book: BinanceOrderBook) -> Self {
+        Self {
+            data: BinanceOrderBookData {
+                last_update_time: book.last_update_time,
+                bids: book.bids().collect(),
+                asks: book.asks().collect(),
+            },
+            depth: book.depth,
+            tick_size: book.tick_size,
+        }
+    }
 }
 
 impl std::fmt::Display for BinanceOrderBook {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.data.bids.is_empty() || self.data.asks.is_empty() {
+        if self.bids.is_empty() || self.asks.is_empty() {
             return Ok(());
         }
 
@@ -179,13 +351,13 @@ impl std::fmt::Display for BinanceOrderBook {
 
         let (min_price_w, min_qty_w) = {
             let dec_w = 3;
-            let max_ask_price = self.data.asks.last().map(|t| t.price).unwrap_or_default();
-            let max_bid_price = self.data.bids.first().map(|t| t.price).unwrap_or_default();
+            let max_ask_price = self.asks().last().map(|t| t.price).unwrap_or_default();
+            let max_bid_price = self.bids().next().map(|t| t.price).unwrap_or_default();
             let min_price_w = (max_ask_price.max(max_bid_price) as i32).to_string().len();
             
-            let max_ask_qty = self.data.asks.iter().map(|t| t.qty).max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap_or_default();
-            let max_bid_qty = self.data.bids.iter().map(|t| t.qty).max_by(|a, b| a.partial_cmp(b).unwrap()).unwrap_or_default();
-            let min_qty_w = (max_ask_qty.max(max_bid_qty) as i32).to_string().len();
+            let max_ask_qty = self.asks.values().copied().max().unwrap_or_default();
+            let max_bid_qty = self.bids.values().copied().max().unwrap_or_default();
+            let min_qty_w = (to_qty(max_ask_qty.max(max_bid_qty)) as i32).to_string().len();
 
             (min_price_w + dec_w, min_qty_w + dec_w)
         };
@@ -195,7 +367,7 @@ impl std::fmt::Display for BinanceOrderBook {
 
         let mut book_str = String::new();
         book_str.push_str("Asks:\n");
-        for ask in self.data.asks.iter().rev() {
+        for ask in self.asks.iter().rev().map(|(ticks, lots)| Tick::new(self.to_price(*ticks), to_qty(*lots))) {
             book_str.push_str(
                 &format!("\t{red_color}{0:>1$.2} @ {2:>3$.2}{no_color}\n", 
                     ask.price, price_width, ask.qty, qty_width
@@ -203,7 +375,7 @@ impl std::fmt::Display for BinanceOrderBook {
             );
         }
         book_str.push_str("Bids:\n");
-        for bid in self.data.bids.iter() {
+        for bid in self.bids() {
             book_str.push_str(
                 &format!("\t{green_color}{0:>1$.2} @ {2:>3$.2}{no_color}\n", 
                     bid.price, price_width, bid.qty, qty_width
@@ -217,14 +389,7 @@ impl std::fmt::Display for BinanceOrderBook {
 
 impl Default for BinanceOrderBook {
     fn default() -> Self {
-        Self {
-            data: BinanceOrderBookData {
-                last_update_time: 0,
-                bids: vec![],
-                asks: vec![], 
-            },
-            depth: 0
-        }
+        Self::empty(0, DEFAULT_TICK_SIZE)
     }
 }
 
@@ -252,10 +417,31 @@ impl TryFrom<BinanceAPIOrderBookData> for BinanceOrderBookData {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Levels set by one diff event, a zero quantity removes the level
+#[derive(Debug, Clone)]
+pub struct BookDelta {
+    pub time_ms: u64, // local receive time
+    pub bids: Vec<Tick>,
+    pub asks: Vec<Tick>,
+}
+
+// With the time the update was received
+impl TryFrom<(&BinanceAPIOrderBookUpdateData, u64)> for BookDelta {
+    type Error = eyre::Report;
+
+    fn try_from((update, received_ms): (&BinanceAPIOrderBookUpdateData, u64)) -> Result<Self, Self::Error> {
+        Ok(Self {
+            time_ms: received_ms,
+            bids: update.b.iter().map(Tick::try_from).collect::<Result<_>>()?,
+            asks: update.a.iter().map(Tick::try_from).collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Tick {
-    qty: f64,
-    price: f64,
+    pub qty: f64,
+    pub price: f64,
 }
 
 impl Tick {
@@ -264,6 +450,7 @@ impl Tick {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SwapType {
     Buy,
     Sell,
@@ -274,16 +461,17 @@ pub enum SwapType {
 mod tests {
     use super::*;
 
+    fn make_book(depth: u32, bids: Vec<Tick>, asks: Vec<Tick>) -> BinanceOrderBook {
+        BinanceOrderBook::new(depth, 0.01, BinanceOrderBookData { 
+            last_update_time: 0, 
+            bids, 
+            asks,
+        })
+    }
+
     #[test]
     fn test_update_empty() {
-        let mut book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                bids: vec![], 
-                asks: vec![] 
-            },
-            depth: 3,
-        };
+        let mut book = make_book(3, vec![], vec![]);
         let updated_bids = vec![
             Tick::new(1890., 1.),
             Tick::new(1889., 0.21),
@@ -297,17 +485,8 @@ mod tests {
         book.update_bids(updated_bids.clone());
         book.update_asks(updated_asks.clone());
 
-        let update_bids_len = updated_bids.len();
-        assert_eq!(book.data.bids.len(), update_bids_len);
-        for i in 0..update_bids_len {
-            assert_eq!(book.data.bids[i], updated_bids[i]);
-        }
-
-        let update_asks_len = updated_asks.len();
-        assert_eq!(book.data.asks.len(), update_asks_len);
-        for i in 0..update_asks_len {
-            assert_eq!(book.data.asks[i], updated_asks[i]);
-        }
+        assert_eq!(book.bids().collect::<Vec<_>>(), updated_bids);
+        assert_eq!(book.asks().collect::<Vec<_>>(), updated_asks);
     }
 
     #[test]
@@ -316,23 +495,17 @@ mod tests {
             Tick::new(1890., 0.1),
             Tick::new(1888., 3.22),
         ];
-        let mut book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                bids: old_bids.clone(), 
-                asks: vec![] 
-            }, 
-            depth: 3,
-        };
+        let mut book = make_book(3, old_bids.clone(), vec![]);
         let updated_ticks = vec![
             Tick::new(1890., 1.),
             Tick::new(1889., 0.21),
         ];
         book.update_bids(updated_ticks.clone());
 
-        assert_eq!(book.data.bids[0], updated_ticks[0]);
-        assert_eq!(book.data.bids[1], updated_ticks[1]);
-        assert_eq!(book.data.bids[2], old_bids[1]);
+        let bids = book.bids().collect::<Vec<_>>();
+        assert_eq!(bids[0], updated_ticks[0]);
+        assert_eq!(bids[1], updated_ticks[1]);
+        assert_eq!(bids[2], old_bids[1]);
     }
 
     #[test]
@@ -341,14 +514,7 @@ mod tests {
             Tick::new(1891., 0.1),
             Tick::new(1893., 3.22),
         ];
-        let mut book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                asks: old_asks.clone(), 
-                bids: vec![] 
-            }, 
-            depth: 4,
-        };
+        let mut book = make_book(4, vec![], old_asks.clone());
         let updated_ticks = vec![
             Tick::new(1891., 1.),
             Tick::new(1892., 0.9),
@@ -356,10 +522,11 @@ mod tests {
         ];
         book.update_asks(updated_ticks.clone());
 
-        assert_eq!(book.data.asks[0], updated_ticks[0]);
-        assert_eq!(book.data.asks[1], updated_ticks[1]);
-        assert_eq!(book.data.asks[2], old_asks[1]);
-        assert_eq!(book.data.asks[3], updated_ticks[2]);
+        let asks = book.asks().collect::<Vec<_>>();
+        assert_eq!(asks[0], updated_ticks[0]);
+        assert_eq!(asks[1], updated_ticks[1]);
+        assert_eq!(asks[2], old_asks[1]);
+        assert_eq!(asks[3], updated_ticks[2]);
     }
 
     #[test]
@@ -368,21 +535,13 @@ mod tests {
             Tick::new(1891., 0.1),
             Tick::new(1893., 3.22),
         ];
-        let mut book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                asks: old_asks.clone(), 
-                bids: vec![] 
-            }, 
-            depth: 2,
-        };
+        let mut book = make_book(2, vec![], old_asks.clone());
         let updated_ticks = vec![
             Tick::new(1891., 0.),
         ];
         book.update_asks(updated_ticks.clone());
 
-        assert_eq!(book.data.asks.len(), 1);
-        assert_eq!(book.data.asks[0], old_asks[1]);
+        assert_eq!(book.asks().collect::<Vec<_>>(), vec![old_asks[1]]);
     }
 
     #[test]
@@ -390,37 +549,82 @@ mod tests {
         let old_asks = vec![
             Tick::new(1891., 0.1),
         ];
-        let mut book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                asks: old_asks.clone(), 
-                bids: vec![] 
-            }, 
-            depth: 1,
-        };
+        let mut book = make_book(1, vec![], old_asks.clone());
         let updated_ticks = vec![
             Tick::new(1892., 0.9),
         ];
         book.update_asks(updated_ticks.clone());
 
-        assert_eq!(book.data.asks.len(), 1);
-        assert_eq!(book.data.asks[0], old_asks[0]);
+        assert_eq!(book.asks().collect::<Vec<_>>(), vec![old_asks[0]]);
+    }
+
+    #[test]
+    fn test_from_levels_merges_ticks() {
+        // 1890.004 rounds onto the 1890.00 tick
+        let book = BinanceOrderBook::from_levels(
+            2, 
+            0.01, 
+            0, 
+            vec![(1890., 1.), (1890.004, 0.5), (1889.99, 2.), (1889., 3.)], 
+            vec![],
+        );
+        assert_eq!(book.bids().collect::<Vec<_>>(), vec![
+            Tick::new(1890., 1.5),
+            Tick::new(1889.99, 2.),
+        ]);
+    }
+
+    #[test]
+    fn test_whole_number_ticks() {
+        assert_eq!(tick_fraction(0.01), (1, 100));
+        assert_eq!(tick_fraction(0.00000001), (1, 100_000_000));
+        assert_eq!(tick_fraction(1.), (1, 1));
+        assert_eq!(tick_fraction(10.), (10, 1));
+
+        let book = BinanceOrderBook::from_levels(3, 1., 0, vec![(40000., 1.), (39999., 2.)], vec![(40001., 1.)]);
+        assert_eq!(book.bids().collect::<Vec<_>>(), vec![Tick::new(40000., 1.), Tick::new(39999., 2.)]);
+        assert_eq!(book.query_exact_base(SwapType::Sell, 2.), (2., 79999.));
+
+        // 39994 rounds onto the 39990 tick
+        let book = BinanceOrderBook::from_levels(
+            3,
+            10.,
+            0,
+            vec![(40000., 1.), (39990., 1.), (39994., 1.)],
+            vec![(40010., 1.)],
+        );
+        assert_eq!(book.bids().collect::<Vec<_>>(), vec![Tick::new(40000., 1.), Tick::new(39990., 2.)]);
+        assert_eq!(book.asks().collect::<Vec<_>>(), vec![Tick::new(40010., 1.)]);
+        assert_eq!(book.query_exact_base(SwapType::Sell, 1.5), (1.5, 59995.));
+        assert_eq!(book.query_exact_quote(SwapType::Buy, 20005.), (20005., 0.5));
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let book = make_book(3, vec![Tick::new(1890.01, 1.)], vec![Tick::new(1890.02, 2.)]);
+        let json = serde_json::to_string(&book).unwrap();
+        let parsed: BinanceOrderBook = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.bids().collect::<Vec<_>>(), book.bids().collect::<Vec<_>>());
+        assert_eq!(parsed.asks().collect::<Vec<_>>(), book.asks().collect::<Vec<_>>());
+        assert_eq!(parsed.tick_size(), 0.01);
+
+        // recorded before books had a tick size
+        let legacy = r#"{"data":{"last_update_time":0,"bids":[{"qty":1.0,"price":1890.01}],"asks":[]},"depth":3}"#;
+        let parsed: BinanceOrderBook = serde_json::from_str(legacy).unwrap();
+        assert_eq!(parsed.bids().collect::<Vec<_>>(), vec![Tick::new(1890.01, 1.)]);
     }
 
     #[test]
     fn test_query_exact_base_sell() {
-        let book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                last_update_time: 0, 
-                bids: vec![
-                    Tick::new(1890., 1.),
-                    Tick::new(1889., 0.21),
-                    Tick::new(1888., 3.22),
-                ],
-                asks: vec![] 
-            }, 
-            depth: 3,
-        };
+        let book = make_book(
+            3,
+            vec![
+                Tick::new(1890., 1.),
+                Tick::new(1889., 0.21),
+                Tick::new(1888., 3.22),
+            ],
+            vec![],
+        );
         let base_amount = 5.;
         let avl_bid_qty = 4.43;
         let target_out = 8366.05;
@@ -428,29 +632,25 @@ mod tests {
             SwapType::Sell, 
             base_amount
         );
-        let allowed_err = 0.00001;
-        assert!(base_used-avl_bid_qty < allowed_err);
-        assert!(quote_out-target_out < allowed_err);
+        assert_eq!(base_used, avl_bid_qty);
+        assert_eq!(quote_out, target_out);
     }
 
     #[test]
     fn test_query_exact_base_buy() {
-        let book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                asks: vec![
+        let book = make_book(
+            3,
+            vec![
+                Tick::new(1890., 1.),
+                Tick::new(1889., 0.21),
+                Tick::new(1888., 3.22),
+            ],
+            vec![
                 Tick::new(1888., 3.22),
                 Tick::new(1889., 0.21),
                 Tick::new(1890., 1.),
-                ],
-                bids: vec![
-                    Tick::new(1890., 1.),
-                    Tick::new(1889., 0.21),
-                    Tick::new(1888., 3.22),
-                ],
-                last_update_time: 0, 
-            },
-            depth: 3,
-        };
+            ],
+        );
         let base_amount = 5.;
         let avl_ask_qty = 4.43;
         let target_out = 8366.05;
@@ -464,18 +664,15 @@ mod tests {
 
     #[test]
     fn test_query_exact_quote_sell() {
-        let book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                bids: vec![
-                    Tick::new(1890., 1.),
-                    Tick::new(1889., 0.21),
-                    Tick::new(1888., 3.22),
-                ],
-                asks: vec![], 
-                last_update_time: 0, 
-            },
-            depth: 3,
-        };
+        let book = make_book(
+            3,
+            vec![
+                Tick::new(1890., 1.),
+                Tick::new(1889., 0.21),
+                Tick::new(1888., 3.22),
+            ],
+            vec![],
+        );
         let quote_amount = 9000.;
         let avl_bid_qty_quote = 8366.05;
         let target_out = 4.43;
@@ -489,18 +686,15 @@ mod tests {
 
     #[test]
     fn test_query_exact_quote_buy() {
-        let book = BinanceOrderBook {
-            data: BinanceOrderBookData {
-                bids: vec![],
-                asks: vec![
-                    Tick::new(1888., 3.22),
-                    Tick::new(1889., 0.21),
-                    Tick::new(1890., 1.),
-                ], 
-                last_update_time: 0, 
-            },
-            depth: 3,
-        };
+        let book = make_book(
+            3,
+            vec![],
+            vec![
+                Tick::new(1888., 3.22),
+                Tick::new(1889., 0.21),
+                Tick::new(1890., 1.),
+            ],
+        );
         let quote_amount = 9000.;
         let avl_ask_qty_quote = 8366.05;
         let target_out = 4.43;
@@ -508,8 +702,101 @@ mod tests {
             SwapType::Buy, 
             quote_amount
         );
-        let allowed_err = 0.00001;
-        assert!(quote_used-avl_ask_qty_quote < allowed_err);
-        assert!(base_out-target_out < allowed_err);
+        assert_eq!(quote_used, avl_ask_qty_quote);
+        assert_eq!(base_out, target_out);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_query_many_small_levels() {
+        // 0.1 at every cent from 1999.99 down, where summing floats drifts
+        let bids = (1..=500).map(|i| (2000. - 0.01 * i as f64, 0.1)).collect();
+        let book = BinanceOrderBook::from_levels(500, 0.01, 0, bids, vec![]);
+        let target_out = (1..=500).map(|i| 200_000 - i).sum::<i64>() as f64 / 1000.;
+
+        let (base_used, quote_out) = book.query_exact_base(SwapType::Sell, 50.);
+        assert_eq!(base_used, 50.);
+        assert_eq!(quote_out, target_out);
+        let (quote_used, base_out) = book.query_exact_quote(SwapType::Sell, target_out);
+        assert_eq!(quote_used, target_out);
+        assert_eq!(base_out, 50.);
+
+        let mut book = book;
+        book.take_base(SwapType::Sell, 49.9);
+        assert_eq!(book.bids().collect::<Vec<_>>(), vec![Tick::new(1995., 0.1)]);
+    }
+
+    // Benchmarks, run with `cargo test --release bench_ -- --ignored --nocapture`
+
+    fn make_deep_book(depth: u32) -> BinanceOrderBook {
+        let levels = |direction: f64| (0..depth)
+            .map(|i| (2000. + direction * (0.01 + 0.01 * i as f64), 1.))
+            .collect::<Vec<_>>();
+        BinanceOrderBook::from_levels(depth, 0.01, 0, levels(-1.), levels(1.))
+    }
+
+    // Diff events touching levels throughout the book, a tenth of them removals
+    fn make_stream_updates(depth: u32, count: usize) -> Vec<BinanceAPIOrderBookUpdateData> {
+        (0..count)
+            .map(|i| {
+                let offset = 0.01 * ((i * 7919) % depth as usize) as f64;
+                let qty = if i % 10 == 0 { 0. } else { 1. + (i % 5) as f64 };
+                let level = |price: f64| vec![format!("{price:.2}"), format!("{qty}")];
+                serde_json::from_value(serde_json::json!({
+                    "e": "depthUpdate",
+                    "E": i,
+                    "s": "ETHUSDT",
+                    "U": i,
+                    "u": i,
+                    "b": [level(1999.99 - offset)],
+                    "a": [level(2000.01 + offset)],
+                })).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_update_from_stream() {
+        let updates_per_depth = 100_000;
+        for depth in [1000, 2000, 5000] {
+            let mut book = make_deep_book(depth);
+            let updates = make_stream_updates(depth, updates_per_depth);
+            let start = std::time::Instant::now();
+            for update in updates {
+                book.apply_delta(&BookDelta::try_from((&update, 0)).unwrap());
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "depth {depth}: {:.0} updates/s ({:.0} ns/update)",
+                updates_per_depth as f64 / elapsed.as_secs_f64(),
+                elapsed.as_nanos() as f64 / updates_per_depth as f64,
+            );
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_query_exact_base() {
+        let queries_per_depth = 10_000;
+        for depth in [1000, 2000, 5000] {
+            let book = make_deep_book(depth);
+            // from the touch to sweeping the whole side
+            let sizes = (0..queries_per_depth)
+                .map(|i| 1. + (i % depth as usize) as f64)
+                .collect::<Vec<_>>();
+            let start = std::time::Instant::now();
+            let mut quote_out = 0.;
+            for size in sizes {
+                quote_out += book.query_exact_base(SwapType::Sell, size).1;
+            }
+            let elapsed = start.elapsed();
+            assert!(quote_out > 0.);
+            println!(
+                "depth {depth}: {:.0} queries/s ({:.0} ns/query)",
+                queries_per_depth as f64 / elapsed.as_secs_f64(),
+                elapsed.as_nanos() as f64 / queries_per_depth as f64,
+            );
+        }
+    }
+
+}
@@ -1,97 +1,485 @@
 use eyre::Result;
 
 use super::*;
-use order_book::{BinanceOrderBook, BinanceOrderBookData, SwapType};
+use order_book::{BinanceOrderBook, SwapType};
+use sync::{BookSync, BookHealth};
 use market::{Market, Markets};
+use snapshot::{BinanceSnapshot, BinanceSnapshotQuoter};
+use route::Router;
+use fees::BinanceFees;
+use connector::BinanceEndpoints;
+use tape::{MarketTape, Bbo, Trade, TradeFlow};
+use analytics::{BookStats, ImpactPoint};
+use capture::{CaptureRecorder, CaptureRecord, CaptureShared};
 use super::super::Quoter;
 use crate::asset::{Asset, Domain};
 
 
-const BINANCE_STREAM_ENDPOINT: &str = "wss://stream.binance.com:9443";
-const BINANCE_API_ENDPOINT: &str = "https://api.binance.com";
-
 pub struct BinanceQuoter {
+    endpoints: BinanceEndpoints,
+    market_type: MarketType,
+    mode: StreamMode,
     refresh_rate_ms: RefreshRate,
-    order_books: Arc<HashMap<MarketTicker, Arc<Mutex<(BinanceOrderBook,)>>>>,
+    book_depth: u32,
+    order_books: OrderBooksShared,
+    tapes: MarketTapesShared, // bookTicker and aggTrade streams
     pub markets: Markets,
     stream_started: bool,
+    fees: BinanceFees, // taken from quotes
+    capture: Option<CaptureShared>, // raw stream and snapshots, see `create_recording`
 }
 
 impl BinanceQuoter {
 
+    const SYNC_ATTEMPTS: u32 = 5;
+    const STALE_AFTER_MS: u64 = 10_000; // without a book event
+    const TAPE_RETENTION_MS: u64 = 5 * 60_000;
+
+    // Books are synced from REST snapshots once the stream is buffering updates
     pub async fn create(
+        endpoints: &BinanceEndpoints,
         markets: Vec<Market>,
         book_depth: u32,
         refresh_rate_ms: u32,
     ) -> Result<Self> {
-        let markets: Markets = markets.into();
-        let refresh_rate_ms = refresh_rate_ms.try_into().expect("Invalid refresh rate");
+        Self::create_with_mode(endpoints, MarketType::Spot, markets, book_depth, refresh_rate_ms, StreamMode::Diff, None).await
+    }
 
-        let mut order_books = HashMap::new();
-        for market_ticker in &markets.tickers {
-            let book = connector::fetch_book(
-                BINANCE_API_ENDPOINT, 
-                market_ticker, 
-                book_depth
-            ).await?;
-            let orderbook = BinanceOrderBook::new(
-                book_depth, 
-                BinanceOrderBookData::try_from(book)?
-            );
-            order_books.insert(
-                market_ticker.clone(),
-                Arc::new(Mutex::new((orderbook,)))
-            );
-        }
+    // Like `create`, also writing everything the books are built from to a
+    // gzipped NDJSON capture at `capture_path`, see `BinanceReplay`
+    pub async fn create_recording(
+        endpoints: &BinanceEndpoints,
+        markets: Vec<Market>,
+        book_depth: u32,
+        refresh_rate_ms: u32,
+        capture_path: &str,
+    ) -> Result<Self> {
+        let capture = Self::create_capture(capture_path, &markets, book_depth)?;
+        Self::create_with_mode(
+            endpoints,
+            MarketType::Spot,
+            markets,
+            book_depth,
+            refresh_rate_ms,
+            StreamMode::Diff,
+            Some(capture),
+        ).await
+    }
+
+    // Follows only the top `book_size` levels (5, 10 or 20) of every book,
+    // which the stream sends whole, so no snapshots or syncing are needed
+    pub async fn create_partial(
+        endpoints: &BinanceEndpoints,
+        markets: Vec<Market>,
+        book_size: u8,
+        refresh_rate_ms: u32,
+    ) -> Result<Self> {
+        Self::create_partial_with_capture(endpoints, markets, book_size, refresh_rate_ms, None).await
+    }
+
+    // Like `create_partial`, capturing the stream like `create_recording`
+    pub async fn create_partial_recording(
+        endpoints: &BinanceEndpoints,
+        markets: Vec<Market>,
+        book_size: u8,
+        refresh_rate_ms: u32,
+        capture_path: &str,
+    ) -> Result<Self> {
+        Self::create_partial_with_capture(endpoints, markets, book_size, refresh_rate_ms, Some(capture_path)).await
+    }
+
+    async fn create_partial_with_capture(
+        endpoints: &BinanceEndpoints,
+        markets: Vec<Market>,
+        book_size: u8,
+        refresh_rate_ms: u32,
+        capture_path: Option<&str>,
+    ) -> Result<Self> {
+        let book_size: BookSize = book_size.try_into().map_err(|e: &str| eyre::eyre!(e))?;
+        let capture = capture_path
+            .map(|capture_path| Self::create_capture(capture_path, &markets, book_size as u32))
+            .transpose()?;
+        Self::create_with_mode(
+            endpoints,
+            MarketType::Spot,
+            markets,
+            book_size as u32,
+            refresh_rate_ms,
+            StreamMode::Partial(book_size),
+            capture,
+        ).await
+    }
+
+    // Starts a capture with the header `BinanceReplay` builds the books from
+    fn create_capture(capture_path: &str, markets: &[Market], book_depth: u32) -> Result<CaptureShared> {
+        let mut recorder = CaptureRecorder::create(capture_path)?;
+        recorder.record(&CaptureRecord::Start {
+            received_ms: utils::get_epoch_ms(),
+            book_depth,
+            tick_sizes: markets.iter().map(|market| (market.ticker(), market.tick_size())).collect(),
+        })?;
+        Ok(Arc::new(Mutex::new(recorder)))
+    }
+
+    pub(super) async fn create_with_mode(
+        endpoints: &BinanceEndpoints,
+        market_type: MarketType,
+        markets: Vec<Market>,
+        book_depth: u32,
+        refresh_rate_ms: u32,
+        mode: StreamMode,
+        capture: Option<CaptureShared>,
+    ) -> Result<Self> {
+        let refresh_rate_ms = refresh_rate_ms.try_into().expect("Invalid refresh rate");
+        let order_books = markets.iter()
+            .map(|market| (
+                market.ticker(),
+                Arc::new(Mutex::new((
+                    BinanceOrderBook::empty(book_depth, market.tick_size()),
+                    BookSync::new(),
+                )))
+            ))
+            .collect::<HashMap<_, _>>();
+        let tapes = markets.iter()
+            .map(|market| (market.ticker(), Arc::new(Mutex::new(MarketTape::new(Self::TAPE_RETENTION_MS)))))
+            .collect::<HashMap<_, _>>();
+        let markets: Markets = markets.into();
         let mut quoter = Self {
+            endpoints: endpoints.clone(),
+            market_type,
             order_books: Arc::new(order_books),
+            tapes: Arc::new(tapes),
             stream_started: false,
+            mode,
             refresh_rate_ms,
+            book_depth,
             markets,
+            fees: BinanceFees::default(),
+            capture,
         };
         quoter.start_stream();
+        match mode {
+            StreamMode::Diff => {
+                for market_ticker in &quoter.markets.tickers {
+                    connector::sync_book(
+                        market_type.depth_endpoint(&quoter.endpoints.api),
+                        market_ticker.clone(),
+                        book_depth,
+                        quoter.order_books.clone(),
+                        quoter.capture.clone(),
+                        Self::SYNC_ATTEMPTS,
+                    ).await?;
+                }
+            },
+            StreamMode::Partial(_) => quoter.wait_until_live(Self::STALE_AFTER_MS).await?,
+        }
         Ok(quoter)
     }
 
+    async fn wait_until_live(&self, timeout_ms: u64) -> Result<()> {
+        let start = std::time::Instant::now();
+        let is_live = |ticker| matches!(self.health(ticker), Ok(BookHealth::Live));
+        while !self.markets.tickers.iter().all(is_live) {
+            if start.elapsed().as_millis() as u64 > timeout_ms {
+                return Err(eyre::eyre!(format!("Books not live after {timeout_ms}ms")));
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    // Markets with the trading filters currently listed by the exchange
+    pub async fn fetch_markets(
+        endpoints: &BinanceEndpoints,
+        market_tickers: &[MarketTicker],
+    ) -> Result<Vec<Market>> {
+        Self::fetch_markets_of(endpoints, MarketType::Spot, market_tickers).await
+    }
+
+    pub(super) async fn fetch_markets_of(
+        endpoints: &BinanceEndpoints,
+        market_type: MarketType,
+        market_tickers: &[MarketTicker],
+    ) -> Result<Vec<Market>> {
+        let exchange_info = connector::fetch_exchange_info(&endpoints.api, market_type, market_tickers).await?;
+        market::markets_from_exchange_info(&exchange_info, market_tickers)
+    }
+
     fn start_stream(&mut self) {
         tokio::spawn(connector::start_stream(
-            BINANCE_STREAM_ENDPOINT,
+                self.endpoints.clone(),
                 self.markets.tickers.clone(),
-                self.refresh_rate_ms,
-                self.order_books.clone()
+                connector::StreamConfig {
+                    market_type: self.market_type,
+                    mode: self.mode,
+                    refresh_rate_ms: self.refresh_rate_ms,
+                    book_depth: self.book_depth,
+                    stale_after_ms: Self::STALE_AFTER_MS,
+                },
+                self.order_books.clone(),
+                self.tapes.clone(),
+                self.capture.clone(),
             )
         );
         self.stream_started = true;
     }
 
+    // Quoter for an account on another fee tier. It shares the books, so no
+    // new stream is opened
+    pub fn with_fees(&self, fees: BinanceFees) -> Self {
+        Self {
+            endpoints: self.endpoints.clone(),
+            market_type: self.market_type,
+            mode: self.mode,
+            refresh_rate_ms: self.refresh_rate_ms,
+            book_depth: self.book_depth,
+            order_books: self.order_books.clone(),
+            tapes: self.tapes.clone(),
+            markets: self.markets.clone(),
+            stream_started: self.stream_started,
+            fees,
+            capture: self.capture.clone(),
+        }
+    }
+
     pub fn get_book(&self, market: &MarketTicker) -> Result<BinanceOrderBook> {
         let book = self.order_books.get(market).unwrap();
         let book = book.lock().unwrap().0.clone();
         Ok(book)
     }
 
+    // The book as it was at `timestamp_ms`, by the local clock the stream was
+    // received on, up to the history retention
+    pub fn get_book_at(&self, market: &MarketTicker, timestamp_ms: u64) -> Result<BinanceOrderBook> {
+        let book = self.order_books.get(market)
+            .ok_or(eyre::eyre!(format!("No book for {market}")))?;
+        let book = book.lock().unwrap();
+        book.1.book_at(timestamp_ms)
+    }
+
+    pub fn health(&self, market: &MarketTicker) -> Result<BookHealth> {
+        let book = self.order_books.get(market)
+            .ok_or(eyre::eyre!(format!("No book for {market}")))?;
+        let book = book.lock().unwrap();
+        Ok(book.1.health(utils::get_epoch_ms(), Self::STALE_AFTER_MS))
+    }
+
+    // Best bid and offer as of the last bookTicker event, none until one
+    // arrives after connecting
+    pub fn bbo(&self, market: &MarketTicker) -> Result<Option<Bbo>> {
+        Ok(self.tape(market)?.lock().unwrap().bbo)
+    }
+
+    pub fn last_trade(&self, market: &MarketTicker) -> Result<Option<Trade>> {
+        Ok(self.tape(market)?.lock().unwrap().trades.last().copied())
+    }
+
+    // Trades in the last `window_ms`, up to the tape retention
+    pub fn trade_flow(&self, market: &MarketTicker, window_ms: u64) -> Result<TradeFlow> {
+        let tape = self.tape(market)?.lock().unwrap();
+        Ok(tape.trades.flow(utils::get_epoch_ms(), window_ms))
+    }
+
+    pub(super) fn tape(&self, market: &MarketTicker) -> Result<&Arc<Mutex<MarketTape>>> {
+        self.tapes.get(market)
+            .ok_or(eyre::eyre!(format!("No tape for {market}")))
+    }
+
+    // Amounts are net of taker fees
     pub async fn query(
         &self, 
         sell_token: String,
         buy_token: String,
         sell_amount: f64,
     ) -> Result<f64> {
-        let market = self.markets.get(&sell_token, &buy_token)
-            .ok_or(eyre::eyre!(format!("Unsupported Binance market between {sell_token} and {buy_token}")))?;
-        let book = self.get_book(&market.ticker())?;
-        let (amount_used, amount_bought) = if sell_token == market.base() {
-            book.query_exact_base(SwapType::Sell, sell_amount)
-        } else {
-            book.query_exact_quote(SwapType::Sell, sell_amount)
-        };
-        if amount_used != sell_amount {
-            Err(eyre::eyre!(format!("Partial fill: {amount_used}/{sell_amount}")))
-        } else {
-            Ok(amount_bought)
-        }        
+        let router = Router::new(&self.markets, &self.fees);
+        let (_, amount_out) = router.best_exact_in(
+            &sell_token, 
+            &buy_token, 
+            sell_amount, 
+            |market, hop_sell_token, hop_amount| self.with_live_book(&market.ticker(), |book| {
+                query_book(book, market, hop_sell_token, hop_amount)
+            }),
+        )?;
+        Ok(amount_out)
+    }
+
+    // Sell amount needed to receive exactly `buy_amount`
+    pub async fn query_exact_out(
+        &self, 
+        sell_token: String,
+        buy_token: String,
+        buy_amount: f64,
+    ) -> Result<f64> {
+        let router = Router::new(&self.markets, &self.fees);
+        let (_, amount_in) = router.best_exact_out(
+            &sell_token, 
+            &buy_token, 
+            buy_amount, 
+            |market, hop_buy_token, hop_amount| self.with_live_book(&market.ticker(), |book| {
+                query_book_exact_out(book, market, hop_buy_token, hop_amount)
+            }),
+        )?;
+        Ok(amount_in)
+    }
+
+    // Value at the mid price of every market on the best route, fees aside
+    pub async fn query_value(
+        &self, 
+        sell_token: String,
+        buy_token: String,
+        sell_amount: f64,
+    ) -> Result<f64> {
+        let fees = BinanceFees::zero();
+        let router = Router::new(&self.markets, &fees);
+        let (_, value) = router.best_exact_in(
+            &sell_token, 
+            &buy_token, 
+            sell_amount, 
+            |market, hop_sell_token, hop_amount| self.with_live_book(&market.ticker(), |book| {
+                value_at_mid(book, market, hop_sell_token, hop_amount)
+            }),
+        )?;
+        Ok(value)
+    }
+
+    // Spread, microprice and depth within `within_bps` of the mid price
+    pub fn book_stats(&self, market: &MarketTicker, within_bps: f64) -> Result<BookStats> {
+        self.with_live_book(market, |book| {
+            book.stats(within_bps).ok_or(eyre::eyre!(format!("{market} book is one-sided")))
+        })
+    }
+
+    pub fn impact_curve(
+        &self,
+        market: &MarketTicker,
+        swap_type: SwapType,
+        base_amounts: &[f64],
+    ) -> Result<Vec<ImpactPoint>> {
+        self.with_live_book(market, |book| Ok(book.impact_curve(swap_type, base_amounts)))
+    }
+
+    // Stale books return an error instead of a price the market has moved away from
+    fn with_live_book<T, F>(&self, market: &MarketTicker, query: F) -> Result<T>
+        where F: FnOnce(&BinanceOrderBook) -> Result<T>
+    {
+        let book = self.order_books.get(market)
+            .ok_or(eyre::eyre!(format!("No book for {market}")))?;
+        let book = book.lock().unwrap();
+        let (book, sync) = &*book;
+        let health = sync.health(utils::get_epoch_ms(), Self::STALE_AFTER_MS);
+        if health != BookHealth::Live {
+            return Err(eyre::eyre!(format!("{market} book is {health}")));
+        }
+        query(book)
+    }
+
+    pub fn snapshot(&self) -> BinanceSnapshot {
+        let books = self.markets.tickers.iter()
+            .map(|ticker| (ticker.clone(), self.get_book(ticker).unwrap()))
+            .collect();
+        BinanceSnapshot { timestamp_ms: utils::get_epoch_ms(), books }
+    }
+
+    // Books as of `timestamp_ms`, leaving out those that were out of sync or
+    // are older than the history
+    pub fn snapshot_at(&self, timestamp_ms: u64) -> BinanceSnapshot {
+        let books = self.markets.tickers.iter()
+            .filter_map(|ticker| Some((ticker.clone(), self.get_book_at(ticker, timestamp_ms).ok()?)))
+            .collect();
+        BinanceSnapshot { timestamp_ms, books }
+    }
+
+    // Like `query`, against the books as of `timestamp_ms`, e.g. when the
+    // block a DEX quote was read at was produced
+    pub async fn query_at(
+        &self,
+        sell_token: String,
+        buy_token: String,
+        sell_amount: f64,
+        timestamp_ms: u64,
+    ) -> Result<f64> {
+        let snapshot = self.snapshot_at(timestamp_ms);
+        BinanceSnapshotQuoter::new(&snapshot, &self.markets)
+            .with_fees(self.fees.clone())
+            .query(sell_token, buy_token, sell_amount).await
+    }
+
+
+}
+
+// Sizes are rounded to the market's lot step; dust below it is left unsold
+pub(super) fn query_book(
+    book: &BinanceOrderBook,
+    market: &Market,
+    sell_token: &str,
+    sell_amount: f64,
+) -> Result<f64> {
+    let filters = market.filters();
+    if sell_token == market.base() {
+        let base_amount = filters.floor_lot(sell_amount);
+        let (base_used, quote_bought) = book.query_exact_base(SwapType::Sell, base_amount);
+        check_fill(base_used, base_amount)?;
+        filters.check_order(base_amount, quote_bought)?;
+        Ok(quote_bought)
+    } else {
+        // selling the quote asset buys the base from the asks
+        let (quote_used, base_bought) = book.query_exact_quote(SwapType::Buy, sell_amount);
+        check_fill(quote_used, sell_amount)?;
+        let base_bought = filters.floor_lot(base_bought);
+        let (_, quote_used) = book.query_exact_base(SwapType::Buy, base_bought);
+        filters.check_order(base_bought, quote_used)?;
+        Ok(base_bought)
     }
+}
+
+// Amounts too small to trade are still valued, so lot and notional filters don't apply
+pub(super) fn value_at_mid(
+    book: &BinanceOrderBook,
+    market: &Market,
+    sell_token: &str,
+    sell_amount: f64,
+) -> Result<f64> {
+    let mid_price = book.mid_price()
+        .ok_or(eyre::eyre!(format!("{} book is one-sided", market.ticker())))?;
+    if sell_token == market.base() {
+        Ok(sell_amount * mid_price)
+    } else {
+        Ok(sell_amount / mid_price)
+    }
+}
 
+// Sizes are rounded up to the market's lot step, so at least `buy_amount` is received
+pub(super) fn query_book_exact_out(
+    book: &BinanceOrderBook,
+    market: &Market,
+    buy_token: &str,
+    buy_amount: f64,
+) -> Result<f64> {
+    let filters = market.filters();
+    if buy_token == market.base() {
+        let base_amount = filters.ceil_lot(buy_amount);
+        let (base_received, quote_sold) = book.query_exact_base(SwapType::Buy, base_amount);
+        check_fill(base_received, base_amount)?;
+        filters.check_order(base_amount, quote_sold)?;
+        Ok(quote_sold)
+    } else {
+        let (quote_received, base_sold) = book.query_exact_quote(SwapType::Sell, buy_amount);
+        check_fill(quote_received, buy_amount)?;
+        let base_sold = filters.ceil_lot(base_sold);
+        let (base_used, quote_received) = book.query_exact_base(SwapType::Sell, base_sold);
+        check_fill(base_used, base_sold)?;
+        filters.check_order(base_sold, quote_received)?;
+        Ok(base_sold)
+    }
+}
 
+fn check_fill(amount_filled: f64, amount: f64) -> Result<()> {
+    if amount_filled != amount {
+        Err(eyre::eyre!(format!("Partial fill: {amount_filled}/{amount}")))
+    } else {
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -110,6 +498,32 @@ impl Quoter for BinanceQuoter {
         ).await
     }
 
+    async fn query_exact_out(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_buy_amount: f64,
+    ) -> Result<f64> {
+        self.query_exact_out(
+            domain_sell_asset_id, 
+            domain_buy_asset_id, 
+            domain_buy_amount
+        ).await
+    }
+
+    async fn query_value(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        self.query_value(
+            domain_sell_asset_id, 
+            domain_buy_asset_id, 
+            domain_sell_amount
+        ).await
+    }
+
     fn get_domain_id(&self) -> Domain {
         Domain::Binance
     }
@@ -119,6 +533,7 @@ impl Quoter for BinanceQuoter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mock::{MockBinance, MockEvent, diff_event, snapshot, wait_for};
 
     async fn debug_query(
         quoter: &BinanceQuoter,
@@ -136,16 +551,152 @@ mod tests {
         }
     }
 
+    fn best_bid(quoter: &BinanceQuoter) -> Option<f64> {
+        let book = quoter.get_book(&suppported_markets::ETHUSDT.ticker()).unwrap();
+        let best_bid = book.bids().next().map(|level| level.price);
+        best_bid
+    }
+
+    async fn create_quoter(mock: &MockBinance) -> BinanceQuoter {
+        BinanceQuoter::create(&mock.endpoints, vec![suppported_markets::ETHUSDT], 50, 100)
+            .await.unwrap()
+            .with_fees(BinanceFees::zero())
+    }
+
     #[tokio::test]
     async fn test_binance_stream() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "2.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            // straddles the snapshot
+            diff_event("ethusdt", 99, 101, &[("1999.50", "1.0")], &[]),
+            MockEvent::Frame(String::from("not json")),
+            MockEvent::Frame(String::from(r#"{"stream":"ethusdt@depth@100ms","data":{"u":102}}"#)),
+            diff_event("ethusdt", 102, 102, &[], &[("2001.00", "0"), ("2002.00", "3.0")]),
+        ]);
+        let quoter = create_quoter(&mock).await;
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.get_book(&ticker).unwrap().asks().next().map(|level| level.price) == Some(2002.)).await;
+
+        assert_eq!(quoter.health(&ticker).unwrap(), BookHealth::Live);
+        assert_eq!(best_bid(&quoter), Some(1999.5));
+        let amount_out = quoter.query(String::from("ETH"), String::from("USDT"), 2.).await.unwrap();
+        assert_eq!(amount_out, 1999.5 + 1999.);
+        let amount_out = quoter.query(String::from("USDT"), String::from("ETH"), 2002.).await.unwrap();
+        assert_eq!(amount_out, 1.);
+        assert_eq!(mock.depth_requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_binance_stream_gap() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_snapshot("ethusdt", snapshot(105, &[("1995.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            diff_event("ethusdt", 101, 102, &[("1999.50", "1.0")], &[]),
+            // 103 and 104 are missing
+            diff_event("ethusdt", 105, 106, &[("1996.00", "1.0")], &[]),
+        ]);
+        let quoter = create_quoter(&mock).await;
+        wait_for(|| best_bid(&quoter) == Some(1996.)).await;
+
+        // the second snapshot replaced the book, the event after the gap was replayed
+        let book = quoter.get_book(&suppported_markets::ETHUSDT.ticker()).unwrap();
+        assert_eq!(book.bids().map(|level| level.price).collect::<Vec<_>>(), vec![1996., 1995.]);
+        assert_eq!(mock.depth_requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_binance_stream_bad_price() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_snapshot("ethusdt", snapshot(102, &[("1995.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            diff_event("ethusdt", 101, 101, &[("1999.50", "1.0")], &[]),
+            MockEvent::Pause(100),
+            diff_event("ethusdt", 102, 102, &[("not a price", "1.0")], &[]),
+            // unknown markets are skipped
+            diff_event("btcusdt", 500, 500, &[("40000.00", "1.0")], &[]),
+            diff_event("ethusdt", 103, 103, &[("1996.00", "1.0")], &[]),
+        ]);
+        let quoter = create_quoter(&mock).await;
+        wait_for(|| best_bid(&quoter) == Some(1996.)).await;
+
+        // only the book was resynced, the stream stayed up
+        let book = quoter.get_book(&suppported_markets::ETHUSDT.ticker()).unwrap();
+        assert_eq!(book.bids().map(|level| level.price).collect::<Vec<_>>(), vec![1996., 1995.]);
+        assert_eq!(mock.depth_requests(), 2);
+        assert_eq!(mock.connection_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_binance_stream_reconnect() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_snapshot("ethusdt", snapshot(200, &[("1990.00", "1.0")], &[("2001.00", "1.0")]));
+        mock.add_connection(vec![
+            diff_event("ethusdt", 101, 101, &[("1999.50", "1.0")], &[]),
+            MockEvent::Pause(100),
+            MockEvent::Disconnect,
+        ]);
+        mock.add_connection(vec![
+            diff_event("ethusdt", 200, 201, &[("1991.00", "1.0")], &[]),
+        ]);
+        let quoter = create_quoter(&mock).await;
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| best_bid(&quoter) == Some(1991.)).await;
+
+        // nothing from the first connection is stitched onto the new book
+        let book = quoter.get_book(&ticker).unwrap();
+        assert_eq!(book.bids().map(|level| level.price).collect::<Vec<_>>(), vec![1991., 1990.]);
+        assert_eq!(quoter.health(&ticker).unwrap(), BookHealth::Live);
+        assert_eq!(mock.connection_count(), 2);
+        assert_eq!(mock.depth_requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_query_at() {
+        let mock = MockBinance::start().await.unwrap();
+        mock.add_snapshot("ethusdt", snapshot(100, &[("1999.00", "1.0")], &[("2001.00", "1.0")]));
+        // books are timed by when the events arrive, whatever the exchange stamped
+        let start_ms = utils::get_epoch_ms();
+        mock.add_connection(vec![
+            MockEvent::Pause(300),
+            diff_event("ethusdt", 101, 101, &[("1999.50", "1.0")], &[]),
+            MockEvent::Pause(300),
+            diff_event("ethusdt", 102, 102, &[("1999.50", "0")], &[]),
+            MockEvent::Pause(300),
+            diff_event("ethusdt", 103, 103, &[], &[("2002.00", "1.0")]),
+        ]);
+        let quoter = create_quoter(&mock).await;
+        let ticker = suppported_markets::ETHUSDT.ticker();
+        wait_for(|| quoter.get_book(&ticker).unwrap().asks().count() == 2).await;
+        let last_ms = quoter.get_book(&ticker).unwrap().last_update_time();
+
+        let book = quoter.get_book_at(&ticker, last_ms - 450).unwrap();
+        assert_eq!(book.bids().map(|level| level.price).collect::<Vec<_>>(), vec![1999.5, 1999.]);
+        let sell_eth_at = |timestamp_ms| quoter.query_at(String::from("ETH"), String::from("USDT"), 1., timestamp_ms);
+        assert_eq!(sell_eth_at(last_ms - 750).await.unwrap(), 1999.);
+        assert_eq!(sell_eth_at(last_ms - 450).await.unwrap(), 1999.5);
+        assert_eq!(sell_eth_at(last_ms - 150).await.unwrap(), 1999.);
+        assert_eq!(quoter.get_book_at(&ticker, last_ms).unwrap().asks().count(), 2);
+        // before the first snapshot
+        assert!(quoter.get_book_at(&ticker, start_ms - 60_000).is_err());
+        assert!(sell_eth_at(start_ms - 60_000).await.is_err());
+    }
+
+    // Against the exchange, run with `cargo test binance_stream_live -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn test_binance_stream_live() {
         let book_depth = 50;
         let refresh_rate_ms = 100;
-        let mut quoter = BinanceQuoter::create(
+        let quoter = BinanceQuoter::create(
+            &BinanceEndpoints::default(),
             vec![suppported_markets::ETHUSDT, suppported_markets::BTCUSDT],
             book_depth,
             refresh_rate_ms,
         ).await.unwrap();
-        quoter.start_stream();
 
         let mut interval = tokio::time::interval(std::time::Duration::from_millis(100));
         loop {
@@ -167,4 +718,4 @@ mod tests {
         }
         
     }
-}
\ No newline at end of file
+}
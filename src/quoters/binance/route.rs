@@ -0,0 +1,227 @@
+use super::*;
+use market::{Market, Markets};
+use fees::BinanceFees;
+
+
+const MAX_HOPS: usize = 3; // enough for triangles through BTC
+
+type Asset = String;
+
+// Quotes trades through intermediate assets by walking the book of every market
+// on the path in turn. Hop quotes come from a closure so live and recorded books
+// share the routing
+pub(super) struct Router<'a> {
+    markets: &'a Markets,
+    fees: &'a BinanceFees, // taken from the output of every hop
+}
+
+impl<'a> Router<'a> {
+
+    pub fn new(markets: &'a Markets, fees: &'a BinanceFees) -> Self {
+        Self { markets, fees }
+    }
+
+    // Path that buys the most; `query_hop(market, sell_token, sell_amount)` quotes the amount out of a hop
+    pub fn best_exact_in<F>(
+        &self,
+        sell_token: &str,
+        buy_token: &str,
+        sell_amount: f64,
+        mut query_hop: F,
+    ) -> Result<(Vec<Asset>, f64)>
+        where F: FnMut(&Market, &str, f64) -> Result<f64>
+    {
+        self.best_path(
+            sell_token,
+            buy_token,
+            |path| self.exact_in(path, sell_amount, &mut query_hop),
+            |amount, best| amount > best,
+        )
+    }
+
+    // Path that sells the least; `query_hop(market, buy_token, buy_amount)` quotes the amount into a hop
+    pub fn best_exact_out<F>(
+        &self,
+        sell_token: &str,
+        buy_token: &str,
+        buy_amount: f64,
+        mut query_hop: F,
+    ) -> Result<(Vec<Asset>, f64)>
+        where F: FnMut(&Market, &str, f64) -> Result<f64>
+    {
+        self.best_path(
+            sell_token,
+            buy_token,
+            |path| self.exact_out(path, buy_amount, &mut query_hop),
+            |amount, best| amount < best,
+        )
+    }
+
+    pub fn exact_in<F>(&self, path: &[Asset], sell_amount: f64, query_hop: &mut F) -> Result<f64>
+        where F: FnMut(&Market, &str, f64) -> Result<f64>
+    {
+        let mut amount = sell_amount;
+        for hop in path.windows(2) {
+            let market = self.market(&hop[0], &hop[1])?;
+            amount = query_hop(market, &hop[0], amount)? * (1. - self.fees.taker_fee_bps(market) / BPS);
+        }
+        Ok(amount)
+    }
+
+    // Walks the path backwards from the amount the last hop has to deliver
+    pub fn exact_out<F>(&self, path: &[Asset], buy_amount: f64, query_hop: &mut F) -> Result<f64>
+        where F: FnMut(&Market, &str, f64) -> Result<f64>
+    {
+        let mut amount = buy_amount;
+        for hop in path.windows(2).rev() {
+            let market = self.market(&hop[0], &hop[1])?;
+            // the fee comes out of the hop's output, so it has to buy more
+            amount = query_hop(market, &hop[1], amount / (1. - self.fees.taker_fee_bps(market) / BPS))?;
+        }
+        Ok(amount)
+    }
+
+    fn market(&self, asset_a: &str, asset_b: &str) -> Result<&Market> {
+        self.markets.get(asset_a, asset_b)
+            .ok_or(eyre::eyre!(format!("Unsupported Binance market between {asset_a} and {asset_b}")))
+    }
+
+    // Paths that can't be quoted are skipped; the last error is returned if none can
+    fn best_path<Q, B>(
+        &self,
+        sell_token: &str,
+        buy_token: &str,
+        mut quote_path: Q,
+        is_better: B,
+    ) -> Result<(Vec<Asset>, f64)>
+        where Q: FnMut(&[Asset]) -> Result<f64>, B: Fn(f64, f64) -> bool
+    {
+        let mut best: Option<(Vec<Asset>, f64)> = None;
+        let mut last_error = None;
+        for path in self.markets.paths(sell_token, buy_token, MAX_HOPS) {
+            match quote_path(&path) {
+                Ok(amount) => {
+                    if best.as_ref().is_none_or(|(_, best_amount)| is_better(amount, *best_amount)) {
+                        best = Some((path, amount));
+                    }
+                },
+                Err(e) => last_error = Some(e),
+            }
+        }
+        best.ok_or_else(|| last_error.unwrap_or(
+            eyre::eyre!(format!("No Binance route between {sell_token} and {buy_token}"))
+        ))
+    }
+
+}
+
+// Markets on the routes between any two of `assets`, e.g. ETHBTC and BTCUSDT
+// next to ETHUSDT for ETH and USDT, in the order of `markets`
+pub fn route_markets(markets: &[Market], assets: &[String]) -> Vec<Market> {
+    let all_markets: Markets = markets.to_vec().into();
+    let mut tickers = Vec::new();
+    for (i, from) in assets.iter().enumerate() {
+        for to in assets.iter().skip(i + 1) {
+            for path in all_markets.paths(from, to, MAX_HOPS) {
+                tickers.extend(path.windows(2).filter_map(|hop| all_markets.get_ticker(hop[0].as_str(), hop[1].as_str())));
+            }
+        }
+    }
+    markets.iter()
+        .filter(|market| tickers.contains(&market.ticker()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quoter::{query_book, query_book_exact_out};
+
+    // Books around ETH at 2000 USDT and BTC at 40000 USDT, with a cheap ETHBTC market
+    fn make_books() -> HashMap<String, BinanceOrderBook> {
+        let mut books = HashMap::new();
+        let mut add = |market: Market, bid: f64, ask: f64, qty: f64| {
+            let book = BinanceOrderBook::from_levels(
+                1,
+                market.tick_size(),
+                0,
+                vec![(bid, qty)],
+                vec![(ask, qty)],
+            );
+            books.insert(market.ticker(), book);
+        };
+        add(suppported_markets::ETHUSDT, 1999., 2001., 1000.);
+        add(suppported_markets::BTCUSDT, 39990., 40010., 1000.);
+        add(suppported_markets::ARBUSDT, 1., 1.001, 1_000_000.);
+        add(suppported_markets::ETHBTC, 0.04999, 0.0501, 1000.);
+        books
+    }
+
+    fn make_markets() -> Markets {
+        vec![
+            suppported_markets::ETHUSDT,
+            suppported_markets::BTCUSDT,
+            suppported_markets::ARBUSDT,
+            suppported_markets::ETHBTC,
+        ].into()
+    }
+
+    #[test]
+    fn test_best_exact_in() {
+        let books = make_books();
+        let markets = make_markets();
+        let query_hop = |market: &Market, sell_token: &str, amount: f64| {
+            query_book(&books[&market.ticker()], market, sell_token, amount)
+        };
+
+        // selling ETH for BTC directly beats going through USDT
+        let (path, amount_out) = Router::new(&markets, &BinanceFees::zero()).best_exact_in("ETH", "BTC", 1., query_hop).unwrap();
+        assert_eq!(path, vec!["ETH", "BTC"]);
+        assert_eq!(amount_out, 0.04999);
+
+        // ARB -> USDT -> ETH beats the triangle through BTC
+        let (path, amount_out) = Router::new(&markets, &BinanceFees::zero()).best_exact_in("ARB", "ETH", 2001., query_hop).unwrap();
+        assert_eq!(path, vec!["ARB", "USDT", "ETH"]);
+        assert!((amount_out - 2001. / 2001.).abs() < 1e-9);
+
+        // 10bps taker fee per hop
+        let (_, amount_out) = Router::new(&markets, &BinanceFees::default()).best_exact_in("ARB", "ETH", 2001., query_hop).unwrap();
+        assert!((amount_out - 0.999 * 0.999).abs() < 1e-9);
+
+        assert!(Router::new(&markets, &BinanceFees::zero()).best_exact_in("ARB", "SOL", 1., query_hop).is_err());
+        // more than the books hold on every path
+        assert!(Router::new(&markets, &BinanceFees::zero()).best_exact_in("ETH", "BTC", 2000., query_hop).is_err());
+    }
+
+    #[test]
+    fn test_best_exact_out() {
+        let books = make_books();
+        let markets = make_markets();
+        let query_hop = |market: &Market, buy_token: &str, amount: f64| {
+            query_book_exact_out(&books[&market.ticker()], market, buy_token, amount)
+        };
+
+        let (path, amount_in) = Router::new(&markets, &BinanceFees::zero()).best_exact_out("ARB", "ETH", 1., query_hop).unwrap();
+        assert_eq!(path, vec!["ARB", "USDT", "ETH"]);
+        assert!((amount_in - 2001.).abs() < 1e-9);
+
+        let (_, amount_in) = Router::new(&markets, &BinanceFees::default()).best_exact_out("ARB", "ETH", 1., query_hop).unwrap();
+        // the grossed up ETH amount is rounded to a whole lot of 1e-8
+        assert!((amount_in - 2001. / 0.999 / 0.999).abs() < 2001. * 1e-8);
+    }
+
+    #[test]
+    fn test_route_markets() {
+        let tickers = |assets: &[&str]| {
+            let assets = assets.iter().map(|asset| asset.to_string()).collect::<Vec<_>>();
+            route_markets(&suppported_markets::ALL, &assets).iter().map(|market| market.ticker()).collect::<Vec<_>>()
+        };
+        assert_eq!(tickers(&["ETH", "USDT"]), vec!["ethusdt", "btcusdt", "ethbtc"]);
+        // ARB only trades against USDT, ETH is reached through it
+        assert_eq!(tickers(&["ARB", "ETH"]), vec!["ethusdt", "btcusdt", "arbusdt", "ethbtc"]);
+        assert_eq!(tickers(&["ARB", "USDT"]), vec!["arbusdt"]);
+        assert!(tickers(&["SOL", "USDT"]).is_empty());
+    }
+
+}
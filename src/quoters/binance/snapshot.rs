@@ -0,0 +1,358 @@
+use eyre::Result;
+
+use super::*;
+use market::{Market, Markets};
+use route::Router;
+use quoter::{query_book, query_book_exact_out, value_at_mid};
+use order_book::SwapType;
+use fees::BinanceFees;
+use super::super::Quoter;
+use crate::asset::Domain;
+
+
+// Books of all tracked markets at a point in time
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BinanceSnapshot {
+    pub timestamp_ms: u64,
+    pub books: HashMap<MarketTicker, BinanceOrderBook>,
+}
+
+impl BinanceSnapshot {
+
+    // Sells along the best route and removes the liquidity taken from every book
+    // on it, so later fills against the same snapshot see the depleted books.
+    // Routed with the fees the fill was quoted with, so the same path is taken
+    pub fn execute(
+        &mut self,
+        markets: &Markets,
+        fees: &BinanceFees,
+        sell_token: &str,
+        buy_token: &str,
+        sell_amount: f64,
+    ) -> Result<f64> {
+        let router = Router::new(markets, fees);
+        let (path, _) = router.best_exact_in(sell_token, buy_token, sell_amount, |market, hop_sell_token, hop_amount| {
+            query_book(self.book(market)?, market, hop_sell_token, hop_amount)
+        })?;
+        router.exact_in(&path, sell_amount, &mut |market, hop_sell_token, hop_amount| {
+            let book = self.book_mut(market)?;
+            let amount_bought = query_book(book, market, hop_sell_token, hop_amount)?;
+            if hop_sell_token == market.base() {
+                book.take_base(SwapType::Sell, market.filters().floor_lot(hop_amount));
+            } else {
+                book.take_base(SwapType::Buy, amount_bought);
+            }
+            Ok(amount_bought)
+        })
+    }
+
+    // Buys exactly `buy_amount` along the best route and returns the amount sold
+    pub fn execute_exact_out(
+        &mut self,
+        markets: &Markets,
+        fees: &BinanceFees,
+        sell_token: &str,
+        buy_token: &str,
+        buy_amount: f64,
+    ) -> Result<f64> {
+        let router = Router::new(markets, fees);
+        let (path, _) = router.best_exact_out(sell_token, buy_token, buy_amount, |market, hop_buy_token, hop_amount| {
+            query_book_exact_out(self.book(market)?, market, hop_buy_token, hop_amount)
+        })?;
+        router.exact_out(&path, buy_amount, &mut |market, hop_buy_token, hop_amount| {
+            let book = self.book_mut(market)?;
+            let amount_sold = query_book_exact_out(book, market, hop_buy_token, hop_amount)?;
+            if hop_buy_token == market.base() {
+                book.take_base(SwapType::Buy, market.filters().ceil_lot(hop_amount));
+            } else {
+                book.take_base(SwapType::Sell, amount_sold);
+            }
+            Ok(amount_sold)
+        })
+    }
+
+    fn book(&self, market: &Market) -> Result<&BinanceOrderBook> {
+        self.books.get(&market.ticker())
+            .ok_or(eyre::eyre!(format!("No recorded book for {}", market.ticker())))
+    }
+
+    fn book_mut(&mut self, market: &Market) -> Result<&mut BinanceOrderBook> {
+        self.books.get_mut(&market.ticker())
+            .ok_or(eyre::eyre!(format!("No recorded book for {}", market.ticker())))
+    }
+
+}
+
+// Quotes against a recorded snapshot instead of the live books
+pub struct BinanceSnapshotQuoter<'a> {
+    snapshot: &'a BinanceSnapshot,
+    markets: &'a Markets,
+    fees: BinanceFees, // taken from quotes
+}
+
+impl<'a> BinanceSnapshotQuoter<'a> {
+
+    pub fn new(snapshot: &'a BinanceSnapshot, markets: &'a Markets) -> Self {
+        Self { snapshot, markets, fees: BinanceFees::default() }
+    }
+
+    pub fn with_fees(mut self, fees: BinanceFees) -> Self {
+        self.fees = fees;
+        self
+    }
+
+}
+
+#[async_trait::async_trait]
+impl Quoter for BinanceSnapshotQuoter<'_> {
+
+    async fn query(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        let router = Router::new(self.markets, &self.fees);
+        let (_, amount_out) = router.best_exact_in(
+            &domain_sell_asset_id,
+            &domain_buy_asset_id,
+            domain_sell_amount,
+            |market, hop_sell_token, hop_amount| {
+                query_book(self.snapshot.book(market)?, market, hop_sell_token, hop_amount)
+            },
+        )?;
+        Ok(amount_out)
+    }
+
+    async fn query_exact_out(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_buy_amount: f64,
+    ) -> Result<f64> {
+        let router = Router::new(self.markets, &self.fees);
+        let (_, amount_in) = router.best_exact_out(
+            &domain_sell_asset_id,
+            &domain_buy_asset_id,
+            domain_buy_amount,
+            |market, hop_buy_token, hop_amount| {
+                query_book_exact_out(self.snapshot.book(market)?, market, hop_buy_token, hop_amount)
+            },
+        )?;
+        Ok(amount_in)
+    }
+
+    async fn query_value(
+        &self,
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        let fees = BinanceFees::zero();
+        let router = Router::new(self.markets, &fees);
+        let (_, value) = router.best_exact_in(
+            &domain_sell_asset_id,
+            &domain_buy_asset_id,
+            domain_sell_amount,
+            |market, hop_sell_token, hop_amount| {
+                value_at_mid(self.snapshot.book(market)?, market, hop_sell_token, hop_amount)
+            },
+        )?;
+        Ok(value)
+    }
+
+    fn get_domain_id(&self) -> Domain {
+        Domain::Binance
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use market::MarketFilters;
+    use fees::FeeTier;
+    use crate::asset::supported_assets;
+    use crate::simulation::SolverModel;
+
+    fn make_snapshot() -> BinanceSnapshot {
+        let snapshot = r#"{
+            "timestamp_ms": 0,
+            "books": {
+                "ethusdt": {
+                    "data": {
+                        "last_update_time": 0,
+                        "bids": [{"price": 1890.0, "qty": 1.0}, {"price": 1889.0, "qty": 2.0}],
+                        "asks": [{"price": 1891.0, "qty": 1.0}]
+                    },
+                    "depth": 2
+                }
+            }
+        }"#;
+        serde_json::from_str(snapshot).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_quote() {
+        let snapshot = make_snapshot();
+        let markets: Markets = vec![suppported_markets::ETHUSDT].into();
+        let quoter = BinanceSnapshotQuoter::new(&snapshot, &markets).with_fees(BinanceFees::zero());
+
+        let amount_out = quoter.get_amount_out(
+            &supported_assets::ETH,
+            &supported_assets::USDT,
+            2.
+        ).await.unwrap();
+        assert_eq!(amount_out, 3779.);
+        assert!(quoter.get_amount_out(
+            &supported_assets::ETH,
+            &supported_assets::USDT,
+            4.
+        ).await.is_err());
+        // USDT buys ETH from the asks
+        assert_eq!(quoter.get_amount_out(
+            &supported_assets::USDT,
+            &supported_assets::ETH,
+            945.5
+        ).await.unwrap(), 0.5);
+        assert!(quoter.get_amount_out(
+            &supported_assets::ARB,
+            &supported_assets::USDT,
+            1.
+        ).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_quote_exact_out() {
+        let snapshot = make_snapshot();
+        let markets: Markets = vec![suppported_markets::ETHUSDT].into();
+        let quoter = BinanceSnapshotQuoter::new(&snapshot, &markets).with_fees(BinanceFees::zero());
+
+        // receiving 2834.5 USDT sells 1 ETH at 1890 and 0.5 at 1889
+        let amount_in = quoter.get_amount_in(
+            &supported_assets::ETH,
+            &supported_assets::USDT,
+            2834.5
+        ).await.unwrap();
+        assert_eq!(amount_in, 1.5);
+        let amount_in = quoter.get_amount_in(
+            &supported_assets::USDT,
+            &supported_assets::ETH,
+            0.5
+        ).await.unwrap();
+        assert_eq!(amount_in, 945.5);
+        assert!(quoter.get_amount_in(
+            &supported_assets::USDT,
+            &supported_assets::ETH,
+            2.
+        ).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tier_quote() {
+        let snapshot = make_snapshot();
+        let markets: Markets = vec![suppported_markets::ETHUSDT].into();
+        let quoter = BinanceSnapshotQuoter::new(&snapshot, &markets).with_fees(BinanceFees::new(FeeTier::Vip3, false));
+
+        // walking the book pays 6bps taker on 1890 USDT, not the 4.2bps maker rate
+        let amount_out = quoter.get_amount_out(&supported_assets::ETH, &supported_assets::USDT, 1.).await.unwrap();
+        assert!((amount_out - 1890. * (1. - 6. / BPS)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_execute_depletes_book() {
+        let mut snapshot = make_snapshot();
+        let fees = BinanceFees::zero();
+        let markets: Markets = vec![suppported_markets::ETHUSDT].into();
+
+        assert_eq!(snapshot.execute(&markets, &fees, "ETH", "USDT", 1.5).unwrap(), 1890. + 0.5 * 1889.);
+        // the best level is gone and only 1.5 ETH is left at 1889
+        assert_eq!(snapshot.execute(&markets, &fees, "ETH", "USDT", 1.).unwrap(), 1889.);
+        assert!(snapshot.execute(&markets, &fees, "ETH", "USDT", 1.).is_err());
+
+        assert_eq!(snapshot.execute_exact_out(&markets, &fees, "USDT", "ETH", 0.5).unwrap(), 945.5);
+        assert!(snapshot.execute_exact_out(&markets, &fees, "USDT", "ETH", 0.6).is_err());
+    }
+
+    #[test]
+    fn test_execute_routes_through_usdt() {
+        let mut snapshot = make_snapshot();
+        let fees = BinanceFees::zero();
+        let arb_book = BinanceOrderBook::from_levels(1, 0.0001, 0, vec![(1., 3000.)], vec![(1.0001, 3000.)]);
+        snapshot.books.insert(suppported_markets::ARBUSDT.ticker(), arb_book);
+        let markets: Markets = vec![suppported_markets::ETHUSDT, suppported_markets::ARBUSDT].into();
+
+        // 1891 ARB -> 1891 USDT -> 1 ETH from the asks
+        assert_eq!(snapshot.execute(&markets, &fees, "ARB", "ETH", 1891.).unwrap(), 1.);
+        // both books were depleted
+        assert!(snapshot.execute(&markets, &fees, "ARB", "ETH", 1.).is_err());
+        assert!(snapshot.execute(&markets, &fees, "ARB", "USDT", 1110.).is_err());
+        assert_eq!(snapshot.execute(&markets, &fees, "ARB", "USDT", 1109.).unwrap(), 1109.);
+    }
+
+    #[test]
+    fn test_execute_routes_with_fees() {
+        let mut snapshot = make_snapshot();
+        let eth_btc_book = BinanceOrderBook::from_levels(1, 0.00001, 0, vec![(0.05, 1.)], vec![(0.05001, 1.)]);
+        let btc_usdt_book = BinanceOrderBook::from_levels(1, 0.01, 0, vec![(37830., 1.)], vec![(37831., 1.)]);
+        snapshot.books.insert(suppported_markets::ETHBTC.ticker(), eth_btc_book);
+        snapshot.books.insert(suppported_markets::BTCUSDT.ticker(), btc_usdt_book);
+        let markets: Markets = vec![
+            suppported_markets::ETHUSDT,
+            suppported_markets::BTCUSDT,
+            suppported_markets::ETHBTC,
+        ].into();
+
+        // 1891.5 USDT through BTC beats 1890 directly, until the second hop's fee
+        let fees = BinanceFees::default();
+        let amount_out = snapshot.execute(&markets, &fees, "ETH", "USDT", 1.).unwrap();
+        assert!((amount_out - 1890. * 0.999).abs() < 1e-9);
+        // the path through BTC was not priced, so its books were not touched
+        assert_eq!(snapshot.execute(&markets, &BinanceFees::zero(), "ETH", "BTC", 1.).unwrap(), 0.05);
+    }
+
+    #[test]
+    fn test_execute_respects_filters() {
+        let mut snapshot = make_snapshot();
+        let fees = BinanceFees::zero();
+        let filters = MarketFilters {
+            step_size: 0.1,
+            min_qty: 0.1,
+            max_qty: 100.,
+            min_notional: 200.,
+            ..MarketFilters::with_tick_size(0.01)
+        };
+        let markets: Markets = vec![Market::with_filters("ETH", "USDT", filters)].into();
+
+        // the dust below the lot step is not sold
+        assert!((snapshot.execute(&markets, &fees, "ETH", "USDT", 1.05).unwrap() - 1890.).abs() < 1e-9);
+        assert!(snapshot.execute(&markets, &fees, "ETH", "USDT", 0.1).is_err());
+        // buys are rounded down to the step and exact outputs rounded up
+        assert!((snapshot.execute(&markets, &fees, "USDT", "ETH", 950.).unwrap() - 0.5).abs() < 1e-9);
+        assert!((snapshot.execute_exact_out(&markets, &fees, "ETH", "USDT", 200.).unwrap() - 0.2).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_gas_is_valued_below_filters() {
+        let snapshot = make_snapshot();
+        let filters = MarketFilters {
+            step_size: 0.0001,
+            min_qty: 0.0001,
+            max_qty: 9000.,
+            min_notional: 5.,
+            ..MarketFilters::with_tick_size(0.01)
+        };
+        let markets: Markets = vec![Market::with_filters("ETH", "USDT", filters)].into();
+        let quoter = BinanceSnapshotQuoter::new(&snapshot, &markets);
+
+        // 400k gas at 0.1 gwei can't be sold as an order, but is still worth something
+        let model = SolverModel::new(0., 400_000, 0.1, &supported_assets::ETH, 0.);
+        assert!(quoter.get_amount_out(&supported_assets::ETH, &supported_assets::USDT, 0.00004).await.is_err());
+        let gas_cost = model.gas_cost_in(&supported_assets::USDT, &quoter).await.unwrap();
+        assert!((gas_cost - 0.00004 * 1890.5).abs() < 1e-12);
+        let value = quoter.get_value(&supported_assets::USDT, &supported_assets::ETH, 1890.5).await.unwrap();
+        assert!((value - 1.).abs() < 1e-12);
+    }
+
+
+}
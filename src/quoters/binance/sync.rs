@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+
+use super::*;
+use connector::{BinanceAPIOrderBookData, BinanceAPIOrderBookUpdateData};
+use order_book::{BinanceOrderBookData, BookDelta};
+use history::BookHistory;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SyncStatus {
+    Synced,
+    AwaitingSnapshot,
+    Gap, // the book is stale until a new snapshot arrives
+}
+
+// Whether a book can be quoted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookHealth {
+    Live,
+    AwaitingSnapshot,
+    Stale { idle_ms: u64 }, // no event within the staleness timeout
+    Disconnected,
+}
+
+impl std::fmt::Display for BookHealth {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookHealth::Live => write!(f, "live"),
+            BookHealth::AwaitingSnapshot => write!(f, "waiting for a snapshot"),
+            BookHealth::Stale { idle_ms } => write!(f, "stale for {idle_ms}ms"),
+            BookHealth::Disconnected => write!(f, "disconnected"),
+        }
+    }
+
+}
+
+// Keeps a book in sync with the diff-depth stream following the Binance procedure:
+// events are buffered until a REST snapshot arrives, events the snapshot already
+// covers are dropped, and a gap in update IDs invalidates the book until the next
+// snapshot. Every state the book goes through is kept in its history
+#[derive(Debug, Default)]
+pub(super) struct BookSync {
+    last_update_id: Option<u64>, // none while awaiting a snapshot
+    diff_applied: bool, // since the last snapshot
+    buffer: VecDeque<BinanceAPIOrderBookUpdateData>, // the latest `MAX_BUFFERED` events
+    connected: bool,
+    syncing: bool, // a task is fetching snapshots for the book
+    last_event_ms: u64, // local time the book last caught up with the exchange
+    history: BookHistory,
+}
+
+impl BookSync {
+
+    // 100s of 100ms events. Snapshots fetched after the oldest one was dropped
+    // cover it anyway
+    const MAX_BUFFERED: usize = 1_000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        match self.last_update_id {
+            Some(_) => SyncStatus::Synced,
+            None => SyncStatus::AwaitingSnapshot,
+        }
+    }
+
+    pub fn health(&self, now_ms: u64, stale_after_ms: u64) -> BookHealth {
+        if !self.connected {
+            return BookHealth::Disconnected;
+        }
+        if self.status() != SyncStatus::Synced {
+            return BookHealth::AwaitingSnapshot;
+        }
+        let idle_ms = now_ms.saturating_sub(self.last_event_ms);
+        if idle_ms > stale_after_ms {
+            BookHealth::Stale { idle_ms }
+        } else {
+            BookHealth::Live
+        }
+    }
+
+    // The book as it was at `time_ms`, in local receive time, while it's
+    // within the history retention
+    pub fn book_at(&self, time_ms: u64) -> Result<BinanceOrderBook> {
+        self.history.book_at(time_ms)
+    }
+
+    // False while another sync is running, so gaps don't pile up snapshot requests
+    pub fn start_sync(&mut self) -> bool {
+        !std::mem::replace(&mut self.syncing, true)
+    }
+
+    pub fn end_sync(&mut self) {
+        self.syncing = false;
+    }
+
+    pub fn on_connect(&mut self) {
+        self.connected = true;
+    }
+
+    // Events of the next connection can't be stitched onto this book, so it
+    // waits for a new snapshot
+    pub fn on_disconnect(&mut self) {
+        self.connected = false;
+        self.last_update_id = None;
+        self.buffer.clear();
+        self.history.on_gap(None);
+    }
+
+    // Books are timed by when their updates arrived rather than by the
+    // exchange's event time, which snapshots don't carry. A replay takes the
+    // receive times from the capture
+    pub fn on_update(
+        &mut self,
+        book: &mut BinanceOrderBook,
+        update: BinanceAPIOrderBookUpdateData,
+        received_ms: u64,
+    ) -> Result<SyncStatus> {
+        if self.last_update_id.is_none() {
+            if self.buffer.len() == Self::MAX_BUFFERED {
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back(update);
+            return Ok(SyncStatus::AwaitingSnapshot);
+        }
+        self.apply(book, update, received_ms)
+    }
+
+    // Replaces the book and replays the events buffered while waiting for it,
+    // which only take effect once the snapshot has arrived
+    pub fn on_snapshot(
+        &mut self,
+        book: &mut BinanceOrderBook,
+        depth: u32,
+        snapshot: BinanceAPIOrderBookData,
+        received_ms: u64,
+    ) -> Result<SyncStatus> {
+        let last_update_id = snapshot.last_update_id;
+        let data = BinanceOrderBookData { last_update_time: received_ms, ..BinanceOrderBookData::try_from(snapshot)? };
+        self.last_update_id = Some(last_update_id);
+        self.diff_applied = false;
+        self.last_event_ms = received_ms;
+        *book = BinanceOrderBook::new(depth, book.tick_size(), data);
+        self.history.on_book(book);
+
+        let mut buffered = std::mem::take(&mut self.buffer).into_iter();
+        while let Some(update) = buffered.next() {
+            if let SyncStatus::Gap = self.apply(book, update, received_ms)? {
+                self.buffer.extend(buffered);
+                return Ok(SyncStatus::Gap);
+            }
+        }
+        Ok(SyncStatus::Synced)
+    }
+
+    // Partial depth messages carry the whole top of the book, so each one
+    // replaces it and a missed message doesn't need a resync
+    pub fn on_partial_book(
+        &mut self,
+        book: &mut BinanceOrderBook,
+        depth: u32,
+        partial: BinanceAPIOrderBookData,
+        received_ms: u64,
+    ) -> Result<()> {
+        if self.last_update_id.is_some_and(|last_update_id| partial.last_update_id < last_update_id) {
+            return Ok(());
+        }
+        self.on_snapshot(book, depth, partial, received_ms)?;
+        Ok(())
+    }
+
+    fn apply(
+        &mut self,
+        book: &mut BinanceOrderBook,
+        update: BinanceAPIOrderBookUpdateData,
+        received_ms: u64,
+    ) -> Result<SyncStatus> {
+        let last_update_id = self.last_update_id.expect("Applying an update without a snapshot");
+        if update.u <= last_update_id {
+            return Ok(SyncStatus::Synced);
+        }
+        // the first event after a snapshot may straddle its last update ID,
+        // futures events name the event before them instead of following its ID
+        let is_continuous = if !self.diff_applied {
+            update.U <= last_update_id + 1
+        } else if let Some(previous_update_id) = update.pu {
+            previous_update_id == last_update_id
+        } else {
+            update.U == last_update_id + 1
+        };
+        if !is_continuous {
+            println!(
+                "Gap in {} updates: expected {}, got {}",
+                update.s, last_update_id + 1, update.U
+            );
+            self.on_gap(received_ms);
+            self.buffer = VecDeque::from([update]);
+            return Ok(SyncStatus::Gap);
+        }
+        // an event that can't be applied is a gap of its own, the next
+        // snapshot has to cover it
+        let delta = match BookDelta::try_from((&update, received_ms)) {
+            Ok(delta) => delta,
+            Err(e) => {
+                println!("Error parsing {} update {}: {e}", update.s, update.u);
+                self.on_gap(received_ms);
+                return Ok(SyncStatus::Gap);
+            },
+        };
+        self.last_update_id = Some(update.u);
+        self.diff_applied = true;
+        self.last_event_ms = received_ms;
+        book.apply_delta(&delta);
+        self.history.on_delta(book, delta);
+        Ok(SyncStatus::Synced)
+    }
+
+    fn on_gap(&mut self, received_ms: u64) {
+        self.last_update_id = None;
+        self.history.on_gap(Some(received_ms));
+        self.buffer.clear();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_snapshot(last_update_id: u64, bid_price: &str) -> BinanceAPIOrderBookData {
+        serde_json::from_str(&format!(r#"{{
+            "lastUpdateId": {last_update_id},
+            "bids": [["{bid_price}", "1.0"]],
+            "asks": [["2001.0", "1.0"]]
+        }}"#)).unwrap()
+    }
+
+    fn make_update(first_id: u64, final_id: u64, bid_price: &str) -> BinanceAPIOrderBookUpdateData {
+        serde_json::from_str(&format!(r#"{{
+            "e": "depthUpdate",
+            "E": {final_id},
+            "s": "ETHUSDT",
+            "U": {first_id},
+            "u": {final_id},
+            "b": [["{bid_price}", "1.0"]],
+            "a": []
+        }}"#)).unwrap()
+    }
+
+    fn make_futures_update(first_id: u64, final_id: u64, previous_id: u64, bid_price: &str) -> BinanceAPIOrderBookUpdateData {
+        let mut update = make_update(first_id, final_id, bid_price);
+        update.pu = Some(previous_id);
+        update
+    }
+
+    fn best_bid(book: &BinanceOrderBook) -> f64 {
+        book.query_exact_base(order_book::SwapType::Sell, 1.).1
+    }
+
+    #[test]
+    fn test_buffered_updates_replayed_after_snapshot() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        assert_eq!(sync.on_update(&mut book, make_update(95, 100, "1990.0"), 0).unwrap(), SyncStatus::AwaitingSnapshot);
+        assert_eq!(sync.on_update(&mut book, make_update(101, 105, "2000.0"), 0).unwrap(), SyncStatus::AwaitingSnapshot);
+        assert_eq!(sync.on_update(&mut book, make_update(106, 110, "2000.5"), 0).unwrap(), SyncStatus::AwaitingSnapshot);
+
+        // the first event is covered by the snapshot and the second straddles it
+        let status = sync.on_snapshot(&mut book, 10, make_snapshot(102, "1995.0"), 0).unwrap();
+        assert_eq!(status, SyncStatus::Synced);
+        assert_eq!(best_bid(&book), 2000.5);
+
+        assert_eq!(sync.on_update(&mut book, make_update(111, 111, "2001.5"), 0).unwrap(), SyncStatus::Synced);
+        assert_eq!(best_bid(&book), 2001.5);
+    }
+
+    #[test]
+    fn test_gap_invalidates_book() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        sync.on_snapshot(&mut book, 10, make_snapshot(100, "1995.0"), 0).unwrap();
+        assert_eq!(sync.on_update(&mut book, make_update(101, 105, "1996.0"), 0).unwrap(), SyncStatus::Synced);
+
+        // 106..107 was dropped
+        assert_eq!(sync.on_update(&mut book, make_update(108, 110, "1999.0"), 0).unwrap(), SyncStatus::Gap);
+        assert_eq!(sync.status(), SyncStatus::AwaitingSnapshot);
+        assert_eq!(sync.on_update(&mut book, make_update(111, 112, "1998.0"), 0).unwrap(), SyncStatus::AwaitingSnapshot);
+        assert_eq!(best_bid(&book), 1996.);
+
+        // a snapshot older than the buffered events leaves another gap
+        assert_eq!(sync.on_snapshot(&mut book, 10, make_snapshot(105, "1996.0"), 0).unwrap(), SyncStatus::Gap);
+        assert_eq!(sync.on_snapshot(&mut book, 10, make_snapshot(109, "1997.0"), 0).unwrap(), SyncStatus::Synced);
+        assert_eq!(best_bid(&book), 1999.);
+    }
+
+    #[test]
+    fn test_futures_updates_follow_previous_id() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        sync.on_snapshot(&mut book, 10, make_snapshot(100, "1995.0"), 0).unwrap();
+        assert_eq!(sync.on_update(&mut book, make_futures_update(98, 103, 97, "1996.0"), 0).unwrap(), SyncStatus::Synced);
+        // update IDs skip between futures events
+        assert_eq!(sync.on_update(&mut book, make_futures_update(107, 110, 103, "1997.0"), 0).unwrap(), SyncStatus::Synced);
+        assert_eq!(best_bid(&book), 1997.);
+        assert_eq!(sync.on_update(&mut book, make_futures_update(115, 120, 112, "1998.0"), 0).unwrap(), SyncStatus::Gap);
+    }
+
+    #[test]
+    fn test_health() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        let stale_after_ms = 5_000;
+        assert_eq!(sync.health(utils::get_epoch_ms(), stale_after_ms), BookHealth::Disconnected);
+
+        sync.on_connect();
+        assert_eq!(sync.health(utils::get_epoch_ms(), stale_after_ms), BookHealth::AwaitingSnapshot);
+
+        sync.on_snapshot(&mut book, 10, make_snapshot(100, "1995.0"), utils::get_epoch_ms()).unwrap();
+        sync.on_update(&mut book, make_update(101, 105, "1996.0"), utils::get_epoch_ms()).unwrap();
+        let now_ms = utils::get_epoch_ms();
+        assert_eq!(sync.health(now_ms, stale_after_ms), BookHealth::Live);
+        assert!(matches!(
+            sync.health(now_ms + 6_000, stale_after_ms), 
+            BookHealth::Stale { idle_ms } if idle_ms >= 6_000
+        ));
+
+        // a reconnected book is only live again after a new snapshot
+        sync.on_disconnect();
+        assert_eq!(sync.health(now_ms, stale_after_ms), BookHealth::Disconnected);
+        sync.on_connect();
+        assert_eq!(sync.on_update(&mut book, make_update(300, 305, "1997.0"), utils::get_epoch_ms()).unwrap(), SyncStatus::AwaitingSnapshot);
+        assert_eq!(sync.health(utils::get_epoch_ms(), stale_after_ms), BookHealth::AwaitingSnapshot);
+        sync.on_snapshot(&mut book, 10, make_snapshot(302, "1996.5"), utils::get_epoch_ms()).unwrap();
+        assert_eq!(sync.health(utils::get_epoch_ms(), stale_after_ms), BookHealth::Live);
+        assert_eq!(best_bid(&book), 1997.);
+    }
+
+    #[test]
+    fn test_buffer_keeps_latest_updates() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        let max_buffered = BookSync::MAX_BUFFERED as u64;
+        for id in 1..=max_buffered + 10 {
+            sync.on_update(&mut book, make_update(id, id, "1990.0"), 0).unwrap();
+        }
+        assert_eq!(sync.buffer.len(), BookSync::MAX_BUFFERED);
+        assert_eq!(sync.buffer.front().unwrap().U, 11);
+
+        // a snapshot from before the oldest buffered event can't be stitched on
+        assert_eq!(sync.on_snapshot(&mut book, 10, make_snapshot(5, "1995.0"), 0).unwrap(), SyncStatus::Gap);
+        assert_eq!(sync.on_snapshot(&mut book, 10, make_snapshot(max_buffered, "1995.0"), 0).unwrap(), SyncStatus::Synced);
+        assert_eq!(best_bid(&book), 1995.);
+    }
+
+    #[test]
+    fn test_one_sync_at_a_time() {
+        let mut sync = BookSync::new();
+        assert!(sync.start_sync());
+        // a gap found while syncing doesn't start another
+        assert!(!sync.start_sync());
+        sync.end_sync();
+        assert!(sync.start_sync());
+    }
+
+    #[test]
+    fn test_history_by_receive_time() {
+        let mut book = BinanceOrderBook::default();
+        let mut sync = BookSync::new();
+        // buffered events take effect when the snapshot arrives
+        sync.on_update(&mut book, make_update(95, 101, "1996.0"), 900).unwrap();
+        sync.on_snapshot(&mut book, 10, make_snapshot(100, "1995.0"), 1_000).unwrap();
+        // the event time of 102 is ignored
+        sync.on_update(&mut book, make_update(102, 102, "1997.0"), 1_500).unwrap();
+        assert!(sync.book_at(999).is_err());
+        assert_eq!(best_bid(&sync.book_at(1_000).unwrap()), 1996.);
+        assert_eq!(best_bid(&sync.book_at(1_499).unwrap()), 1996.);
+        assert_eq!(best_bid(&sync.book_at(1_500).unwrap()), 1997.);
+
+        sync.on_update(&mut book, make_update(110, 110, "1998.0"), 2_000).unwrap();
+        assert!(sync.book_at(2_000).is_err());
+        assert_eq!(best_bid(&sync.book_at(1_999).unwrap()), 1997.);
+    }
+
+}
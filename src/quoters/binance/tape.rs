@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+
+use super::*;
+use connector::{BinanceAPIBookTicker, BinanceAPIAggTrade, BinanceAPIMarkPrice};
+
+
+// Best bid and offer from the bookTicker stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bbo {
+    pub bid_price: f64,
+    pub bid_qty: f64,
+    pub ask_price: f64,
+    pub ask_qty: f64,
+    pub update_id: u64,
+    pub received_ms: u64, // local time, the stream has no event time
+}
+
+impl TryFrom<(BinanceAPIBookTicker, u64)> for Bbo {
+    type Error = eyre::Report;
+
+    fn try_from((ticker, received_ms): (BinanceAPIBookTicker, u64)) -> Result<Self> {
+        Ok(Self {
+            bid_price: ticker.bid_price.parse()?,
+            bid_qty: ticker.bid_qty.parse()?,
+            ask_price: ticker.ask_price.parse()?,
+            ask_qty: ticker.ask_qty.parse()?,
+            update_id: ticker.update_id,
+            received_ms,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trade {
+    pub price: f64,
+    pub qty: f64,
+    pub time_ms: u64, // trade time on the exchange
+    pub taker_buy: bool,
+}
+
+impl TryFrom<BinanceAPIAggTrade> for Trade {
+    type Error = eyre::Report;
+
+    fn try_from(trade: BinanceAPIAggTrade) -> Result<Self> {
+        Ok(Self {
+            price: trade.price.parse()?,
+            qty: trade.qty.parse()?,
+            time_ms: trade.trade_time,
+            taker_buy: !trade.buyer_is_maker,
+        })
+    }
+}
+
+// Mark price and funding of a perpetual from the markPrice stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Funding {
+    pub mark_price: f64,
+    pub index_price: f64,
+    pub rate: f64, // paid by longs to shorts at the next funding time, if positive
+    pub next_funding_ms: u64,
+    pub event_ms: u64,
+}
+
+impl Funding {
+
+    // Most perpetuals settle every 8 hours
+    pub const INTERVAL_MS: u64 = 8 * 60 * 60_000;
+
+    // Funding a long pays over `holding_ms` from `now_ms`, as a fraction of
+    // its notional, if every settlement in the period is at the current rate.
+    // Shorts receive it
+    pub fn expected_rate(&self, now_ms: u64, holding_ms: u64) -> f64 {
+        let until_ms = now_ms + holding_ms;
+        if until_ms < self.next_funding_ms {
+            return 0.;
+        }
+        let settlements = 1 + (until_ms - self.next_funding_ms) / Self::INTERVAL_MS;
+        self.rate * settlements as f64
+    }
+
+}
+
+impl TryFrom<BinanceAPIMarkPrice> for Funding {
+    type Error = eyre::Report;
+
+    fn try_from(mark_price: BinanceAPIMarkPrice) -> Result<Self> {
+        Ok(Self {
+            mark_price: mark_price.mark_price.parse()?,
+            index_price: mark_price.index_price.parse()?,
+            rate: mark_price.funding_rate.parse()?,
+            next_funding_ms: mark_price.next_funding_time,
+            event_ms: mark_price.event_time,
+        })
+    }
+}
+
+// Trade flow over a window of the tape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradeFlow {
+    pub trade_count: usize,
+    pub volume: f64, // base
+    pub signed_volume: f64, // taker buys minus taker sells, in base
+    pub vwap: Option<f64>, // none without trades
+}
+
+// Recent trades of a market, oldest first
+#[derive(Debug, Clone)]
+pub struct TradeTape {
+    trades: VecDeque<Trade>,
+    retention_ms: u64,
+}
+
+impl TradeTape {
+
+    pub fn new(retention_ms: u64) -> Self {
+        Self { trades: VecDeque::new(), retention_ms }
+    }
+
+    // Trades older than the retention before the latest one are dropped
+    pub fn push(&mut self, trade: Trade) {
+        self.trades.push_back(trade);
+        let cutoff_ms = trade.time_ms.saturating_sub(self.retention_ms);
+        while self.trades.front().is_some_and(|oldest| oldest.time_ms < cutoff_ms) {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn last(&self) -> Option<&Trade> {
+        self.trades.back()
+    }
+
+    // Trades from `window_ms` before `now_ms`
+    pub fn flow(&self, now_ms: u64, window_ms: u64) -> TradeFlow {
+        let from_ms = now_ms.saturating_sub(window_ms);
+        let mut flow = TradeFlow { trade_count: 0, volume: 0., signed_volume: 0., vwap: None };
+        let mut notional = 0.;
+        for trade in self.trades.iter().rev().take_while(|trade| trade.time_ms >= from_ms) {
+            flow.trade_count += 1;
+            flow.volume += trade.qty;
+            flow.signed_volume += if trade.taker_buy { trade.qty } else { -trade.qty };
+            notional += trade.qty * trade.price;
+        }
+        if flow.volume > 0. {
+            flow.vwap = Some(notional / flow.volume);
+        }
+        flow
+    }
+
+}
+
+// Top of book and trades of a market, and funding of perpetuals
+#[derive(Debug, Clone)]
+pub struct MarketTape {
+    pub bbo: Option<Bbo>,
+    pub trades: TradeTape,
+    pub funding: Option<Funding>,
+}
+
+impl MarketTape {
+
+    pub fn new(retention_ms: u64) -> Self {
+        Self { bbo: None, trades: TradeTape::new(retention_ms), funding: None }
+    }
+
+    // Updates can arrive out of order after a reconnect
+    pub fn on_book_ticker(&mut self, bbo: Bbo) {
+        if self.bbo.is_none_or(|last| bbo.update_id > last.update_id) {
+            self.bbo = Some(bbo);
+        }
+    }
+
+    pub fn on_trade(&mut self, trade: Trade) {
+        self.trades.push(trade);
+    }
+
+    pub fn on_mark_price(&mut self, funding: Funding) {
+        if self.funding.is_none_or(|last| funding.event_ms > last.event_ms) {
+            self.funding = Some(funding);
+        }
+    }
+
+    // The last BBO may have changed while no updates arrived. Funding is kept,
+    // its rate moves slowly
+    pub fn on_disconnect(&mut self) {
+        self.bbo = None;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_trade(time_ms: u64, price: f64, qty: f64, taker_buy: bool) -> Trade {
+        Trade { price, qty, time_ms, taker_buy }
+    }
+
+    #[test]
+    fn test_trade_flow() {
+        let mut tape = TradeTape::new(10_000);
+        tape.push(make_trade(1_000, 2000., 1., true));
+        tape.push(make_trade(5_000, 2010., 2., false));
+        tape.push(make_trade(6_000, 2020., 1., true));
+
+        let flow = tape.flow(6_000, 1_000);
+        assert_eq!(flow.trade_count, 2);
+        assert_eq!(flow.volume, 3.);
+        assert_eq!(flow.signed_volume, -1.);
+        assert_eq!(flow.vwap, Some((2010. * 2. + 2020.) / 3.));
+        assert_eq!(tape.flow(6_000, 10_000).signed_volume, 0.);
+        assert_eq!(tape.flow(20_000, 1_000).vwap, None);
+        assert_eq!(tape.last().unwrap().price, 2020.);
+
+        // the first trade is past the retention
+        tape.push(make_trade(12_000, 2030., 1., true));
+        assert_eq!(tape.flow(12_000, 20_000).trade_count, 3);
+    }
+
+    #[test]
+    fn test_bbo_ordering() {
+        let mut tape = MarketTape::new(1_000);
+        let bbo = Bbo { bid_price: 1999., bid_qty: 1., ask_price: 2001., ask_qty: 1., update_id: 2, received_ms: 0 };
+        tape.on_book_ticker(bbo);
+        tape.on_book_ticker(Bbo { update_id: 1, bid_price: 1990., ..bbo });
+        assert_eq!(tape.bbo, Some(bbo));
+    }
+
+    #[test]
+    fn test_expected_funding() {
+        let funding = Funding { mark_price: 2000., index_price: 2000., rate: 0.0001, next_funding_ms: 60_000, event_ms: 0 };
+        assert_eq!(funding.expected_rate(0, 30_000), 0.);
+        assert_eq!(funding.expected_rate(0, 60_000), 0.0001);
+        assert_eq!(funding.expected_rate(0, 60_000 + Funding::INTERVAL_MS), 0.0002);
+        // past the next funding time until the stream catches up
+        assert_eq!(funding.expected_rate(90_000, 0), 0.0001);
+
+        let mut tape = MarketTape::new(1_000);
+        tape.on_mark_price(Funding { event_ms: 2, ..funding });
+        tape.on_mark_price(Funding { event_ms: 1, rate: -0.0001, ..funding });
+        tape.on_disconnect();
+        assert_eq!(tape.funding.unwrap().rate, 0.0001);
+    }
+
+}
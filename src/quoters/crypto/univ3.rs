@@ -1,12 +1,12 @@
-use ethers::providers::{Provider, Http};
+use ethers::providers::{Provider, Http, Middleware};
 use ethers::contract::abigen;
-use ethers::types::{H160, U256};
+use ethers::types::{H160, U256, BlockId, BlockNumber};
 use std::sync::Arc;
 use eyre::Result;
 use futures::future::join_all;
 
 use super::super::Quoter;
-use crate::asset::Domain;
+use crate::asset::{Asset, Domain};
 
 
 abigen!(UniV3StaticQuoter, "./src/quoters/crypto/abis/UniV3Quoter.json");
@@ -14,6 +14,7 @@ abigen!(UniV3StaticQuoter, "./src/quoters/crypto/abis/UniV3Quoter.json");
 const ENABLED_FEE_AMOUNTS: [u32; 3] = [500, 3000, 10000];
 
 pub struct UniV3Quoter {
+    provider: Arc<Provider<Http>>,
     quoter_contract: UniV3StaticQuoter<Provider<Http>>,
     chain_id: u32,
 }
@@ -27,8 +28,39 @@ impl UniV3Quoter {
     ) -> Result<Self> {
         let provider = Arc::new(Provider::<Http>::try_from(rpc_url)?);
         let address = contract_address.parse::<H160>()?;
-        let quoter_contract = UniV3StaticQuoter::new(address, provider);
-        Ok(Self { quoter_contract, chain_id })
+        let quoter_contract = UniV3StaticQuoter::new(address, provider.clone());
+        Ok(Self { provider, quoter_contract, chain_id })
+    }
+
+    // Number of the latest block and the local time in ms it was read at. Block
+    // timestamps are whole seconds on the chain's clock, up to a second off the
+    // local clock books are kept by, so the block is placed by when it was seen
+    pub async fn latest_block(&self) -> Result<(u64, u64)> {
+        let number = self.provider.get_block_number().await?;
+        let received_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as u64;
+        Ok((number.as_u64(), received_ms))
+    }
+
+    // Like `get_amount_out`, with the pools as they were at `block_number`
+    pub async fn get_amount_out_at(
+        &self,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        sell_amount: f64,
+        block_number: u64,
+    ) -> Result<f64> {
+        let domain_id = self.get_domain_id();
+        let domain_sell_amount = sell_asset.convert_from_zero(domain_id, sell_amount)?;
+        let domain_buy_amount = self.query_all(
+            &sell_asset.get_domain_id(domain_id)?,
+            &buy_asset.get_domain_id(domain_id)?,
+            domain_sell_amount as u128,
+            BlockNumber::Number(block_number.into()).into(),
+        ).await?;
+        let buy_amount = buy_asset.convert_to_zero(domain_id, domain_buy_amount as f64)?;
+        Ok(buy_amount)
     }
 
     async fn query_all(
@@ -36,9 +68,10 @@ impl UniV3Quoter {
         token_in: &str,
         token_out: &str,
         amount_in: u128,
+        block: BlockId,
     ) -> Result<u128> {
         let fs_iter = ENABLED_FEE_AMOUNTS.map(|fee| {
-            self.query_single(&token_in, &token_out, amount_in, fee)
+            self.query_single(&token_in, &token_out, amount_in, fee, block)
         });
         let quotes = join_all(fs_iter).await.into_iter().collect::<Result<Vec<u128>>>()?;
         let best_quote = quotes.into_iter().max().unwrap(); // .ok_or(Err(eyre::eyre!("No valid quote")))?
@@ -52,6 +85,7 @@ impl UniV3Quoter {
         token_out: &str,
         amount_in: u128,
         fee: u32,
+        block: BlockId,
     ) -> Result<u128> {
         let params = QuoteExactInputSingleParams {
             token_in: token_in.parse()?,
@@ -60,7 +94,7 @@ impl UniV3Quoter {
             amount_in: U256::from(amount_in),
             sqrt_price_limit_x96: U256::zero(),
         };
-        let out: U256 = self.quoter_contract.quote_exact_input_single(params).await?;
+        let out: U256 = self.quoter_contract.quote_exact_input_single(params).block(block).call().await?;
         Ok(out.as_u128())
     }
 
@@ -78,7 +112,8 @@ impl Quoter for UniV3Quoter {
         let domain_buy_amount = self.query_all(
             &domain_sell_asset_id, 
             &domain_buy_asset_id, 
-            domain_sell_amount as u128
+            domain_sell_amount as u128,
+            BlockNumber::Latest.into(),
         ).await?;
         Ok(domain_buy_amount as f64)
     }
@@ -116,6 +151,7 @@ mod tests {
             usdt,
             amount_in,
             fee,
+            BlockNumber::Latest.into(),
         ).await;
         assert!(res.is_ok());
         let res = res.unwrap();
@@ -140,7 +176,7 @@ mod tests {
             &arb_eden_static_quoter.to_string(), 
             chain_id
         ).unwrap();
-        let res = quoter.query_all(weth, usdt, amount_in).await;
+        let res = quoter.query_all(weth, usdt, amount_in, BlockNumber::Latest.into()).await;
         assert!(res.is_ok());
         let res = res.unwrap();
         assert!(res > 0);
@@ -5,6 +5,8 @@ pub mod crypto;
 use crate::asset::{Asset, Domain};
 use eyre::Result;
 
+pub const BPS: f64 = 10_000.;
+
 #[async_trait::async_trait]
 pub trait Quoter {
 
@@ -27,6 +29,60 @@ pub trait Quoter {
         Ok(buy_amount)
     }
 
+    async fn get_amount_in(
+        &self,
+        sell_asset: &Asset, 
+        buy_asset: &Asset,
+        buy_amount: f64
+    ) -> Result<f64> {
+        let domain_id = self.get_domain_id();
+        let domain_sell_asset_id = sell_asset.get_domain_id(domain_id)?;
+        let domain_buy_asset_id = buy_asset.get_domain_id(domain_id)?;
+        let domain_buy_amount = buy_asset.convert_from_zero(domain_id, buy_amount)?;
+        let domain_sell_amount = self.query_exact_out(
+            domain_sell_asset_id, 
+            domain_buy_asset_id, 
+            domain_buy_amount
+        ).await?;
+        let sell_amount = sell_asset.convert_to_zero(domain_id, domain_sell_amount)?;
+        Ok(sell_amount)
+    }
+
+    // Sell amount valued in the buy asset at the venue's price, without walking
+    // the book or applying order filters, e.g. for small costs like gas
+    async fn get_value(
+        &self,
+        sell_asset: &Asset, 
+        buy_asset: &Asset,
+        sell_amount: f64
+    ) -> Result<f64> {
+        let domain_id = self.get_domain_id();
+        let domain_sell_asset_id = sell_asset.get_domain_id(domain_id)?;
+        let domain_buy_asset_id = buy_asset.get_domain_id(domain_id)?;
+        let domain_sell_amount = sell_asset.convert_from_zero(domain_id, sell_amount)?;
+        let domain_value = self.query_value(
+            domain_sell_asset_id, 
+            domain_buy_asset_id, 
+            domain_sell_amount
+        ).await?;
+        let value = buy_asset.convert_to_zero(domain_id, domain_value)?;
+        Ok(value)
+    }
+
+    // Expected cost of keeping the position a swap opens for `holding_ms`, as
+    // a fraction of its notional
+    async fn get_holding_cost(
+        &self,
+        sell_asset: &Asset, 
+        buy_asset: &Asset,
+        holding_ms: u64,
+    ) -> Result<f64> {
+        let domain_id = self.get_domain_id();
+        let domain_sell_asset_id = sell_asset.get_domain_id(domain_id)?;
+        let domain_buy_asset_id = buy_asset.get_domain_id(domain_id)?;
+        self.holding_cost(domain_sell_asset_id, domain_buy_asset_id, holding_ms).await
+    }
+
     async fn query(
         &self, 
         domain_sell_asset_id: String,
@@ -34,6 +90,36 @@ pub trait Quoter {
         domain_sell_amount: f64,
     ) -> Result<f64>;
 
+    // Sell amount needed to receive exactly the buy amount
+    async fn query_exact_out(
+        &self, 
+        _domain_sell_asset_id: String,
+        _domain_buy_asset_id: String,
+        _domain_buy_amount: f64,
+    ) -> Result<f64> {
+        Err(eyre::eyre!(format!("Exact output quotes are not supported on {:?}", self.get_domain_id())))
+    }
+
+    // Venues without a price to value at quote the amount as a swap
+    async fn query_value(
+        &self, 
+        domain_sell_asset_id: String,
+        domain_buy_asset_id: String,
+        domain_sell_amount: f64,
+    ) -> Result<f64> {
+        self.query(domain_sell_asset_id, domain_buy_asset_id, domain_sell_amount).await
+    }
+
+    // Swaps settle, only perpetuals keep a position open
+    async fn holding_cost(
+        &self, 
+        _domain_sell_asset_id: String,
+        _domain_buy_asset_id: String,
+        _holding_ms: u64,
+    ) -> Result<f64> {
+        Ok(0.)
+    }
+
     fn get_domain_id(&self) -> Domain;
 
 }
\ No newline at end of file
@@ -0,0 +1,810 @@
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::fs::File;
+use eyre::Result;
+
+use super::{
+    UserOrder,
+    OrderKind,
+    FeeEscalator,
+    Fill,
+    SolverAgent,
+    SolverQuote,
+    SimulationResult,
+    Distribution,
+    BlockClock,
+    Benchmark,
+    OrderOutcome,
+    race,
+};
+use crate::asset::{Asset, Domain};
+use crate::quoters::binance::{BinanceSnapshot, BinanceSnapshotQuoter, BinanceFees, Markets};
+
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DexQuote {
+    pub venue: String,
+    pub sell_asset: String,
+    pub buy_asset: String,
+    pub sell_amount: f64,
+    pub amount_out: f64,
+}
+
+// Market state captured by one iteration of the monitor loop
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MarketSnapshot {
+    pub timestamp_ms: u64,
+    pub binance: BinanceSnapshot,
+    pub dex_quotes: Vec<DexQuote>,
+}
+
+impl MarketSnapshot {
+
+    // How far past the recorded sizes a DEX quote is scaled. Price impact grows
+    // with size, so one size's rate says little about sizes far from it
+    pub const MAX_DEX_SIZE_RATIO: f64 = 2.;
+
+    // DEX quotes are recorded at a few sizes, the rate is interpolated between
+    // them and held past the smallest and largest
+    pub fn dex_amount_out(
+        &self,
+        venue: &str,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        sell_amount: f64,
+    ) -> Result<f64> {
+        let rate = self.dex_rate(venue, sell_asset, buy_asset, sell_amount, |q| (q.sell_amount, q.amount_out))?;
+        Ok(sell_amount * rate)
+    }
+
+    // Exact output is priced at the rate of the recorded quotes with the
+    // closest outputs
+    pub fn dex_amount_in(
+        &self,
+        venue: &str,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        buy_amount: f64,
+    ) -> Result<f64> {
+        let rate = self.dex_rate(venue, sell_asset, buy_asset, buy_amount, |q| (q.amount_out, q.sell_amount))?;
+        Ok(buy_amount * rate)
+    }
+
+    // The venue's quoted amount for the order
+    pub fn dex_amount(&self, venue: &str, order: &UserOrder) -> Result<f64> {
+        match order.kind {
+            OrderKind::Sell => self.dex_amount_out(venue, &order.sell_asset, &order.buy_asset, order.amount),
+            OrderKind::Buy => self.dex_amount_in(venue, &order.sell_asset, &order.buy_asset, order.amount),
+        }
+    }
+
+    // Every venue with recorded DEX quotes for the order's size
+    pub fn dex_benchmarks(&self, order: &UserOrder) -> Vec<Benchmark> {
+        let mut venues: Vec<&String> = Vec::new();
+        for q in self.dex_quotes.iter() {
+            if !venues.contains(&&q.venue) {
+                venues.push(&q.venue);
+            }
+        }
+        venues.into_iter()
+            .filter_map(|venue| {
+                let amount = self.dex_amount(venue, order).ok()?;
+                Some(Benchmark { venue: venue.clone(), kind: order.kind, amount })
+            })
+            .collect()
+    }
+
+    // Amount per unit of `size` at `size`, from the (size, amount) of every
+    // recorded quote of the venue
+    fn dex_rate(
+        &self,
+        venue: &str,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        size: f64,
+        point: impl Fn(&DexQuote) -> (f64, f64),
+    ) -> Result<f64> {
+        let mut points = self.dex_quotes.iter()
+            .filter(|q| q.venue == venue && q.sell_asset == sell_asset.id && q.buy_asset == buy_asset.id)
+            .map(point)
+            .filter(|(quote_size, _)| *quote_size > 0.)
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (Some(smallest), Some(largest)) = (points.first(), points.last()) else {
+            return Err(eyre::eyre!(format!("No recorded {venue} quote for {}/{}", sell_asset.id, buy_asset.id)));
+        };
+        if size < smallest.0 / Self::MAX_DEX_SIZE_RATIO || size > largest.0 * Self::MAX_DEX_SIZE_RATIO {
+            return Err(eyre::eyre!(format!(
+                "Recorded {venue} quotes for {}/{} are for {} to {}, too far from {size} to scale",
+                sell_asset.id, buy_asset.id, smallest.0, largest.0
+            )));
+        }
+        let rate = |(quote_size, amount): (f64, f64)| amount / quote_size;
+        let above = points.partition_point(|(quote_size, _)| *quote_size < size);
+        let rate = match above {
+            0 => rate(points[0]),
+            n if n == points.len() => rate(points[n - 1]),
+            n => {
+                let (below, above) = (points[n - 1], points[n]);
+                let weight = (size - below.0) / (above.0 - below.0);
+                rate(below) + weight * (rate(above) - rate(below))
+            },
+        };
+        Ok(rate)
+    }
+
+}
+
+// Appends snapshots to a newline-delimited JSON file
+pub struct SnapshotRecorder {
+    writer: BufWriter<File>,
+}
+
+impl SnapshotRecorder {
+
+    pub fn create(path: &str) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self { writer: BufWriter::new(file) })
+    }
+
+    pub fn record(&mut self, snapshot: &MarketSnapshot) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, snapshot)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+}
+
+pub fn load_snapshots(path: &str) -> Result<Vec<MarketSnapshot>> {
+    let reader = BufReader::new(File::open(path)?);
+    reader.lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum RecordedVenue {
+    Binance(BinanceFees), // of the solver's account
+    Dex(String),
+}
+
+pub type RecordedSolverAgent = SolverAgent<RecordedVenue>;
+
+#[derive(Debug, Clone)]
+pub struct BacktestReport {
+    pub orders: Vec<OrderOutcome>,
+}
+
+impl BacktestReport {
+
+    // Of the orders that settled, censored ones could still have filled
+    pub fn fill_rate(&self) -> f64 {
+        let filled = self.orders.iter()
+            .filter(|o| matches!(o.result, SimulationResult::Filled { .. }))
+            .count();
+        let settled = self.orders.len() - self.censored();
+        if settled == 0 {
+            return 0.;
+        }
+        filled as f64 / settled as f64
+    }
+
+    pub fn censored(&self) -> usize {
+        self.orders.iter()
+            .filter(|o| matches!(o.result, SimulationResult::Censored { .. }))
+            .count()
+    }
+
+    // Orders known to have filled or not within `blocks` blocks, which leaves
+    // out those censored earlier
+    pub fn settled_within(&self, blocks: u32) -> usize {
+        self.orders.iter()
+            .filter(|o| match o.result {
+                SimulationResult::Censored { steps } => steps >= blocks,
+                _ => true,
+            })
+            .count()
+    }
+
+    // Fills that happened within `blocks` blocks of order creation
+    pub fn fills_within(&self, blocks: u32) -> Vec<&Fill> {
+        self.orders.iter()
+            .filter_map(|o| match &o.result {
+                SimulationResult::Filled { fill, .. } if fill.step < blocks => Some(fill),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn fill_latency_ms(&self) -> Option<Distribution> {
+        self.filled_metric(|result| match result {
+            SimulationResult::Filled { fill, .. } => Some(fill.elapsed_ms as f64),
+            _ => None,
+        })
+    }
+
+    pub fn fee_bps(&self) -> Option<Distribution> {
+        self.filled_metric(|result| match result {
+            SimulationResult::Filled { fill, .. } => Some(fill.fee_bps),
+            _ => None,
+        })
+    }
+
+    pub fn user_surplus_bps(&self) -> Option<Distribution> {
+        let samples = self.orders.iter()
+            .filter_map(|o| o.user_surplus_bps())
+            .collect::<Vec<_>>();
+        Distribution::from_samples(&samples)
+    }
+
+    pub fn price_improvement_bps(&self, venue: &str) -> Option<Distribution> {
+        let samples = self.orders.iter()
+            .filter_map(|o| o.price_improvement_bps(venue))
+            .collect::<Vec<_>>();
+        Distribution::from_samples(&samples)
+    }
+
+    // Benchmark venues in the order they were first seen
+    pub fn benchmark_venues(&self) -> Vec<String> {
+        let mut venues: Vec<String> = Vec::new();
+        for benchmark in self.orders.iter().flat_map(|o| o.benchmarks.iter()) {
+            if !venues.contains(&benchmark.venue) {
+                venues.push(benchmark.venue.clone());
+            }
+        }
+        venues
+    }
+
+    fn filled_metric<F>(&self, metric: F) -> Option<Distribution>
+        where F: Fn(&SimulationResult) -> Option<f64>
+    {
+        let samples = self.orders.iter()
+            .filter_map(|o| metric(&o.result))
+            .collect::<Vec<_>>();
+        Distribution::from_samples(&samples)
+    }
+
+}
+
+impl std::fmt::Display for BacktestReport {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_dist = |dist: Option<Distribution>| {
+            dist.map(|d| d.to_string()).unwrap_or(String::from("-"))
+        };
+        writeln!(f, "orders: {} ({} censored)", self.orders.len(), self.censored())?;
+        writeln!(f, "fill rate: {:.2}%", self.fill_rate() * 100.)?;
+        writeln!(f, "fill latency (ms): {}", fmt_dist(self.fill_latency_ms()))?;
+        writeln!(f, "fee (bps): {}", fmt_dist(self.fee_bps()))?;
+        for venue in self.benchmark_venues() {
+            writeln!(f, "price improvement vs {venue} (bps): {}", fmt_dist(self.price_improvement_bps(&venue)))?;
+        }
+        write!(f, "user surplus vs best DEX (bps): {}", fmt_dist(self.user_surplus_bps()))
+    }
+
+}
+
+struct FlowOrder<'a> {
+    escalator: FeeEscalator<'a>,
+    benchmarks: Vec<Benchmark>,
+    blocks_seen: u32,
+}
+
+// Replays recorded snapshots through the escalator, escalating once per block of the order's chain
+pub struct Backtest {
+    snapshots: Vec<MarketSnapshot>,
+    markets: Markets,
+    reference_venue: String,
+    max_blocks: u32,
+}
+
+impl Backtest {
+
+    pub fn new(
+        snapshots: Vec<MarketSnapshot>,
+        markets: Markets,
+        reference_venue: &str,
+        max_blocks: u32,
+    ) -> Self {
+        Self {
+            snapshots,
+            markets,
+            reference_venue: reference_venue.to_string(),
+            max_blocks,
+        }
+    }
+
+    pub fn snapshot_count(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    // Timestamps of the first and last snapshot
+    pub fn time_range(&self) -> Option<(u64, u64)> {
+        Some((self.snapshots.first()?.timestamp_ms, self.snapshots.last()?.timestamp_ms))
+    }
+
+    // An order of every size is created at every `spacing`-th snapshot
+    pub fn make_orders(
+        &self,
+        template: &UserOrder,
+        sizes: &[f64],
+        spacing: usize,
+    ) -> Vec<(usize, UserOrder)> {
+        (0..self.snapshots.len())
+            .step_by(spacing.max(1))
+            .flat_map(|start| sizes.iter().map(move |size| {
+                let mut order = template.clone();
+                order.amount = *size;
+                (start, order)
+            }))
+            .collect()
+    }
+
+    pub async fn run(
+        &self,
+        orders: &[(usize, UserOrder)],
+        solvers: &[RecordedSolverAgent],
+    ) -> Result<BacktestReport> {
+        let mut results = Vec::with_capacity(orders.len());
+        for (start, order) in orders {
+            match self.run_order(*start, order, solvers).await {
+                Ok(result) => results.push(result),
+                Err(e) => println!("Skipping order at snapshot {start}: {e}"),
+            }
+        }
+        Ok(BacktestReport { orders: results })
+    }
+
+    pub async fn run_order(
+        &self,
+        start: usize,
+        order: &UserOrder,
+        solvers: &[RecordedSolverAgent],
+    ) -> Result<OrderOutcome> {
+        let created = self.snapshots.get(start)
+            .ok_or(eyre::eyre!(format!("No snapshot {start}")))?;
+        let (escalator, benchmarks) = self.open_order(created, order)?;
+
+        // every block sees the latest snapshot at or before it; recorded quotes are
+        // reused until the next snapshot and the order is censored when the data runs out
+        let mut steps = 0;
+        let mut snapshot_index = start;
+        let mut quotes = Vec::new();
+        let blocks = BlockClock::new(&[order.chain], created.timestamp_ms)
+            .take(self.max_blocks as usize);
+        for (step, block) in blocks.enumerate() {
+            let step = step as u32;
+            let mut advanced = quotes.is_empty();
+            while self.snapshots.get(snapshot_index + 1)
+                .is_some_and(|snapshot| snapshot.timestamp_ms <= block.timestamp_ms)
+            {
+                snapshot_index += 1;
+                advanced = true;
+            }
+            let snapshot = &self.snapshots[snapshot_index];
+            if snapshot_index + 1 == self.snapshots.len() && block.timestamp_ms > snapshot.timestamp_ms {
+                let result = SimulationResult::Censored { steps };
+                return Ok(OrderOutcome { result, benchmarks });
+            }
+            if advanced {
+                quotes = Vec::with_capacity(solvers.len());
+                for solver in solvers {
+                    quotes.push(self.quote(snapshot, solver, order).await.ok());
+                }
+            }
+            let elapsed_ms = block.timestamp_ms - created.timestamp_ms;
+            let user_amount = escalator.user_amount_at(step);
+            if let Some((winner, bidders)) = race(solvers, &quotes, user_amount) {
+                let solver = &solvers[winner];
+                let quote = quotes[winner].as_ref().unwrap();
+                if let Some(fill) = escalator.try_fill(step, elapsed_ms + solver.latency_ms, quote) {
+                    let result = SimulationResult::Filled {
+                        solver: solver.name.clone(),
+                        bidders,
+                        fill,
+                    };
+                    return Ok(OrderOutcome { result, benchmarks });
+                }
+            }
+            steps = step + 1;
+        }
+        let result = SimulationResult::Expired { steps };
+        Ok(OrderOutcome { result, benchmarks })
+    }
+
+    // Replays overlapping orders, created at the given times, on the block clock of
+    // their chains. Fills hedged on Binance take liquidity from the book, so later
+    // orders see the depleted book until the next snapshot replaces it. DEX
+    // liquidity is not shared between orders. Orders far from the recorded DEX
+    // quote sizes, or whose fill can't be taken from the book, are skipped
+    pub async fn run_flow(
+        &self,
+        orders: &[(u64, UserOrder)],
+        solvers: &[RecordedSolverAgent],
+    ) -> Result<BacktestReport> {
+        let last_snapshot = self.snapshots.last()
+            .ok_or(eyre::eyre!("No snapshots to run the order flow on"))?;
+        let mut creation_order = (0..orders.len()).collect::<Vec<_>>();
+        creation_order.sort_by_key(|i| orders[*i].0);
+        let start_ms = match creation_order.first() {
+            Some(first) => orders[*first].0,
+            None => return Ok(BacktestReport { orders: Vec::new() }),
+        };
+
+        let mut flow_orders: Vec<Option<FlowOrder>> = (0..orders.len()).map(|_| None).collect();
+        let mut outcomes: Vec<Option<OrderOutcome>> = vec![None; orders.len()];
+        let mut created_count = 0;
+        let mut open = Vec::new(); // in creation order
+        let mut skipped = Vec::new(); // failed to execute this block
+        let mut snapshot_index = 0;
+        let mut book: Option<BinanceSnapshot> = None;
+        let chains = orders.iter().map(|(_, order)| order.chain).collect::<Vec<_>>();
+        for block in BlockClock::new(&chains, start_ms) {
+            if block.timestamp_ms > last_snapshot.timestamp_ms {
+                break;
+            }
+            let mut advanced = book.is_none();
+            while self.snapshots.get(snapshot_index + 1)
+                .is_some_and(|snapshot| snapshot.timestamp_ms <= block.timestamp_ms)
+            {
+                snapshot_index += 1;
+                advanced = true;
+            }
+            let snapshot = &self.snapshots[snapshot_index];
+            if snapshot.timestamp_ms > block.timestamp_ms {
+                continue;
+            }
+            if advanced {
+                book = Some(snapshot.binance.clone());
+            }
+            let book = book.as_mut().unwrap();
+
+            while let Some(i) = creation_order.get(created_count).copied() {
+                let (created_ms, order) = &orders[i];
+                if *created_ms > block.timestamp_ms {
+                    break;
+                }
+                created_count += 1;
+                let created_index = self.snapshots.partition_point(|s| s.timestamp_ms <= *created_ms);
+                let opened = created_index.checked_sub(1)
+                    .ok_or(eyre::eyre!("Created before the first snapshot"))
+                    .and_then(|index| self.open_order(&self.snapshots[index], order));
+                match opened {
+                    Ok((escalator, benchmarks)) => {
+                        flow_orders[i] = Some(FlowOrder { escalator, benchmarks, blocks_seen: 0 });
+                        open.push(i);
+                    },
+                    Err(e) => println!("Skipping order created at {created_ms}: {e}"),
+                }
+            }
+
+            for i in open.iter().copied() {
+                let (created_ms, order) = &orders[i];
+                if order.chain != block.chain || outcomes[i].is_some() {
+                    continue;
+                }
+                let flow_order = flow_orders[i].as_mut().unwrap();
+                let mut quotes = Vec::with_capacity(solvers.len());
+                for solver in solvers {
+                    quotes.push(self.quote_with_book(snapshot, book, solver, order).await.ok());
+                }
+                let step = flow_order.blocks_seen;
+                let elapsed_ms = block.timestamp_ms - created_ms;
+                let user_amount = flow_order.escalator.user_amount_at(step);
+                if let Some((winner, bidders)) = race(solvers, &quotes, user_amount) {
+                    let solver = &solvers[winner];
+                    let quote = quotes[winner].as_ref().unwrap();
+                    if let Some(fill) = flow_order.escalator.try_fill(step, elapsed_ms + solver.latency_ms, quote) {
+                        if let RecordedVenue::Binance(fees) = &solver.venue {
+                            if let Err(e) = self.execute_on_book(book, fees, order) {
+                                println!("Skipping order created at {created_ms}: {e}");
+                                skipped.push(i);
+                                continue;
+                            }
+                        }
+                        let result = SimulationResult::Filled {
+                            solver: solver.name.clone(),
+                            bidders,
+                            fill,
+                        };
+                        outcomes[i] = Some(OrderOutcome { result, benchmarks: flow_order.benchmarks.clone() });
+                    }
+                }
+                flow_order.blocks_seen = step + 1;
+                if outcomes[i].is_none() && flow_order.blocks_seen >= self.max_blocks {
+                    let result = SimulationResult::Expired { steps: self.max_blocks };
+                    outcomes[i] = Some(OrderOutcome { result, benchmarks: flow_order.benchmarks.clone() });
+                }
+            }
+            for i in skipped.drain(..) {
+                flow_orders[i] = None;
+            }
+            open.retain(|i| outcomes[*i].is_none() && flow_orders[*i].is_some());
+            if open.is_empty() && created_count == orders.len() {
+                break;
+            }
+        }
+
+        let outcomes = outcomes.into_iter()
+            .zip(flow_orders)
+            .filter_map(|(outcome, flow_order)| {
+                let flow_order = flow_order?;
+                Some(outcome.unwrap_or(OrderOutcome {
+                    result: SimulationResult::Censored { steps: flow_order.blocks_seen },
+                    benchmarks: flow_order.benchmarks,
+                }))
+            })
+            .collect();
+        Ok(BacktestReport { orders: outcomes })
+    }
+
+    // Takes a Binance fill from the book along the path its solver's fees route it
+    fn execute_on_book(&self, book: &mut BinanceSnapshot, fees: &BinanceFees, order: &UserOrder) -> Result<f64> {
+        let sell_token = order.sell_asset.get_domain_id(Domain::Binance)?;
+        let buy_token = order.buy_asset.get_domain_id(Domain::Binance)?;
+        match order.kind {
+            OrderKind::Sell => book.execute(&self.markets, fees, &sell_token, &buy_token, order.amount),
+            OrderKind::Buy => book.execute_exact_out(&self.markets, fees, &sell_token, &buy_token, order.amount),
+        }
+    }
+
+    // The fee escalates from the reference venue's quote at creation
+    fn open_order<'a>(
+        &self,
+        created: &MarketSnapshot,
+        order: &'a UserOrder,
+    ) -> Result<(FeeEscalator<'a>, Vec<Benchmark>)> {
+        let reference_amount = created.dex_amount(&self.reference_venue, order)?;
+        let benchmarks = created.dex_benchmarks(order);
+        Ok((FeeEscalator::new(order, reference_amount), benchmarks))
+    }
+
+    async fn quote(
+        &self,
+        snapshot: &MarketSnapshot,
+        solver: &RecordedSolverAgent,
+        order: &UserOrder,
+    ) -> Result<SolverQuote> {
+        self.quote_with_book(snapshot, &snapshot.binance, solver, order).await
+    }
+
+    // Binance is quoted against `book`, which may be depleted by earlier fills
+    async fn quote_with_book(
+        &self,
+        snapshot: &MarketSnapshot,
+        book: &BinanceSnapshot,
+        solver: &RecordedSolverAgent,
+        order: &UserOrder,
+    ) -> Result<SolverQuote> {
+        let hedge_amount = match &solver.venue {
+            RecordedVenue::Binance(fees) => {
+                let book_quoter = BinanceSnapshotQuoter::new(book, &self.markets).with_fees(fees.clone());
+                order.quote_on(&book_quoter).await?
+            },
+            RecordedVenue::Dex(venue) => snapshot.dex_amount(venue, order)?,
+        };
+        // gas is always converted at the recorded Binance price
+        let price_quoter = BinanceSnapshotQuoter::new(book, &self.markets).with_fees(BinanceFees::zero());
+        let gas_cost = solver.model.gas_cost_in(order.quoted_asset(), &price_quoter).await?;
+        Ok(solver.model.make_quote(order.kind, hedge_amount, gas_cost))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::asset::{supported_assets, EVM};
+    use crate::quoters::binance::suppported_markets;
+    use crate::simulation::{LinearSchedule, FeeUnit, SolverModel};
+
+    // The DEX quotes 1, 10 and 100 ETH at the rate of `dex_amount_out` for 10
+    fn make_snapshot(timestamp_ms: u64, best_bid: f64, dex_amount_out: f64) -> MarketSnapshot {
+        let book = format!(r#"{{
+            "data": {{
+                "last_update_time": {timestamp_ms},
+                "bids": [{{"price": {best_bid}, "qty": 100.0}}],
+                "asks": [{{"price": {}, "qty": 100.0}}]
+            }},
+            "depth": 1
+        }}"#, best_bid + 0.01);
+        let mut books = HashMap::new();
+        books.insert(suppported_markets::ETHUSDT.ticker(), serde_json::from_str(&book).unwrap());
+        MarketSnapshot {
+            timestamp_ms,
+            binance: BinanceSnapshot { timestamp_ms, books },
+            dex_quotes: [1., 10., 100.].into_iter()
+                .map(|sell_amount| make_dex_quote(sell_amount, dex_amount_out * sell_amount / 10.))
+                .collect(),
+        }
+    }
+
+    fn make_dex_quote(sell_amount: f64, amount_out: f64) -> DexQuote {
+        DexQuote {
+            venue: String::from("oneinch"),
+            sell_asset: String::from("eth"),
+            buy_asset: String::from("usdt"),
+            sell_amount,
+            amount_out,
+        }
+    }
+
+    fn make_backtest(max_blocks: u32) -> Backtest {
+        let snapshots = vec![
+            make_snapshot(0, 1990., 20_000.),
+            make_snapshot(2000, 1995., 20_000.),
+            make_snapshot(4000, 2000., 20_000.),
+        ];
+        let markets: Markets = vec![suppported_markets::ETHUSDT].into();
+        Backtest::new(snapshots, markets, "oneinch", max_blocks)
+    }
+
+    fn make_solvers() -> Vec<RecordedSolverAgent> {
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.);
+        vec![SolverAgent::new("binance", RecordedVenue::Binance(BinanceFees::zero()), model, 0, 0.)]
+    }
+
+    // Optimism blocks line up with the snapshots
+    fn make_order(chain: EVM) -> UserOrder {
+        UserOrder::new(
+            chain,
+            &supported_assets::WETH,
+            &supported_assets::USDT,
+            1.,
+            Arc::new(LinearSchedule::new(0., 10., FeeUnit::Bps)),
+        )
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = make_snapshot(0, 1990., 20_000.);
+        let line = serde_json::to_string(&snapshot).unwrap();
+        let parsed: MarketSnapshot = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.dex_quotes.len(), 3);
+        assert_eq!(
+            parsed.dex_amount_out("oneinch", &supported_assets::ETH, &supported_assets::USDT, 1.).unwrap(),
+            2000.
+        );
+    }
+
+    #[test]
+    fn test_dex_amounts_between_recorded_sizes() {
+        let mut snapshot = make_snapshot(0, 1990., 20_000.);
+        // 20 bps of impact at 100 ETH
+        snapshot.dex_quotes[2].amount_out = 199_600.;
+        let (eth, usdt) = (&supported_assets::ETH, &supported_assets::USDT);
+        let amount_out = |sell_amount| snapshot.dex_amount_out("oneinch", eth, usdt, sell_amount);
+        assert_eq!(amount_out(10.).unwrap(), 20_000.);
+        assert!((amount_out(55.).unwrap() - 55. * 1998.).abs() < 1e-6);
+        assert!((amount_out(200.).unwrap() - 200. * 1996.).abs() < 1e-6);
+        assert_eq!(amount_out(0.5).unwrap(), 1000.);
+        // too far from the recorded sizes to be priced
+        assert!(amount_out(201.).is_err());
+        assert!(amount_out(0.4).is_err());
+        assert!((snapshot.dex_amount_in("oneinch", eth, usdt, 199_600.).unwrap() - 100.).abs() < 1e-9);
+
+        // a benchmark per venue, not per recorded size
+        let mut order = make_order(EVM::Optimism);
+        assert_eq!(snapshot.dex_benchmarks(&order).len(), 1);
+        snapshot.dex_quotes = vec![make_dex_quote(10., 20_000.)];
+        order.amount = 100.;
+        assert!(snapshot.dex_benchmarks(&order).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backtest_fills_when_price_recovers() {
+        let backtest = make_backtest(3);
+        let result = backtest.run_order(0, &make_order(EVM::Optimism), &make_solvers()).await.unwrap();
+        match &result.result {
+            SimulationResult::Filled { fill, .. } => {
+                // step 1: 1995 >= 2000 * (1 - 10bps) = 1998 fails; step 2: 2000 >= 1996
+                assert_eq!(fill.step, 2);
+                assert_eq!(fill.elapsed_ms, 4000);
+            },
+            _ => panic!("Order should fill"),
+        }
+        assert!((result.user_surplus_bps().unwrap() + 20.).abs() < 1e-9);
+        assert_eq!(result.price_improvement_bps("oneinch"), result.user_surplus_bps());
+    }
+
+    #[tokio::test]
+    async fn test_backtest_buy_order() {
+        let backtest = make_backtest(3);
+        let template = make_order(EVM::Optimism);
+        // receive exactly 2000 USDT, which oneinch prices at 1 ETH
+        let order = UserOrder::new_buy(
+            template.chain,
+            &template.sell_asset,
+            &template.buy_asset,
+            2000.,
+            template.schedule.clone(),
+        );
+        let result = backtest.run_order(0, &order, &make_solvers()).await.unwrap();
+        match &result.result {
+            SimulationResult::Filled { fill, .. } => {
+                // step 1: 2000 / 1995 > 1.001 ETH; step 2: 2000 / 2000 <= 1.002 ETH
+                assert_eq!(fill.step, 2);
+                assert!((fill.user_amount - 1.002).abs() < 1e-9);
+                assert!((fill.solver_profit() - 0.002).abs() < 1e-9);
+            },
+            _ => panic!("Order should fill"),
+        }
+        assert!((result.user_surplus_bps().unwrap() + 20.).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_backtest_escalates_per_block() {
+        let backtest = make_backtest(60);
+        let result = backtest.run_order(0, &make_order(EVM::Arbitrum), &make_solvers()).await.unwrap();
+        match &result.result {
+            SimulationResult::Filled { fill, .. } => {
+                // still on the first snapshot: 1990 >= 2000 * (1 - 50bps) after 5 blocks
+                assert_eq!(fill.step, 5);
+                assert_eq!(fill.elapsed_ms, 1250);
+            },
+            _ => panic!("Order should fill"),
+        }
+
+    }
+
+    #[tokio::test]
+    async fn test_backtest_censors_orders_when_data_runs_out() {
+        let backtest = make_backtest(60);
+        // the next Ethereum slot is after the last snapshot
+        let result = backtest.run_order(0, &make_order(EVM::Ethereum), &make_solvers()).await.unwrap();
+        assert!(matches!(result.result, SimulationResult::Censored { steps: 1 }));
+        assert_eq!(result.user_surplus_bps(), None);
+
+        // the price never recovers: the Arbitrum order escalates to a fill within
+        // the data, the Optimism one runs out of snapshots after 2 blocks
+        let snapshots = vec![make_snapshot(0, 1990., 20_000.), make_snapshot(2000, 1990., 20_000.)];
+        let backtest = Backtest::new(snapshots, vec![suppported_markets::ETHUSDT].into(), "oneinch", 60);
+        let orders = vec![(0, make_order(EVM::Optimism)), (0, make_order(EVM::Arbitrum))];
+        let report = backtest.run(&orders, &make_solvers()).await.unwrap();
+        assert!(matches!(report.orders[0].result, SimulationResult::Censored { steps: 2 }));
+        assert!(matches!(report.orders[1].result, SimulationResult::Filled { .. }));
+        assert_eq!(report.censored(), 1);
+        assert_eq!(report.fill_rate(), 1.);
+        assert_eq!(report.fee_bps().unwrap().count, 1);
+        assert_eq!(report.settled_within(2), 2);
+        assert_eq!(report.settled_within(3), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backtest_report() {
+        let backtest = make_backtest(2);
+        let orders = backtest.make_orders(&make_order(EVM::Optimism), &[1., 2.], 1);
+        assert_eq!(orders.len(), 6);
+
+        let report = backtest.run(&orders, &make_solvers()).await.unwrap();
+        assert_eq!(report.orders.len(), 6);
+        // orders created at the first snapshot expire before the price recovers
+        assert!((report.fill_rate() - 4. / 6.).abs() < 1e-9);
+        assert_eq!(report.fee_bps().unwrap().count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_flow_orders_compete_for_book_liquidity() {
+        let backtest = make_backtest(3);
+        let mut order = make_order(EVM::Optimism);
+        order.amount = 60.;
+        // both orders clear at 4000 but the book only has 100 ETH at the best bid
+        let orders = vec![(0, order.clone()), (0, order.clone())];
+        let report = backtest.run_flow(&orders, &make_solvers()).await.unwrap();
+        assert_eq!(report.orders.len(), 2);
+        assert!(matches!(report.orders[0].result, SimulationResult::Filled { .. }));
+        assert!(matches!(report.orders[1].result, SimulationResult::Expired { steps: 3 }));
+
+        // without contention both fill
+        let report = backtest.run(&[(0, order.clone()), (0, order)], &make_solvers()).await.unwrap();
+        assert_eq!(report.fill_rate(), 1.);
+    }
+
+}
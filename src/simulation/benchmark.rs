@@ -0,0 +1,99 @@
+use super::{SimulationResult, OrderKind, BPS};
+
+
+// Aggregator quote captured at order creation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Benchmark {
+    pub venue: String,
+    pub kind: OrderKind,
+    pub amount: f64, // amount out of sells, amount in of buys
+}
+
+impl Benchmark {
+
+    // Positive when the user did better than the benchmark quoted
+    pub fn price_improvement_bps(&self, user_amount: f64) -> f64 {
+        self.kind.surplus(user_amount, self.amount) / self.amount * BPS
+    }
+
+}
+
+// Escalator execution of an order judged against the benchmarks at its creation
+#[derive(Debug, Clone)]
+pub struct OrderOutcome {
+    pub result: SimulationResult,
+    pub benchmarks: Vec<Benchmark>,
+}
+
+impl OrderOutcome {
+
+    pub fn price_improvement_bps(&self, venue: &str) -> Option<f64> {
+        let benchmark = self.benchmarks.iter().find(|b| b.venue == venue)?;
+        self.user_amount().map(|amount| benchmark.price_improvement_bps(amount))
+    }
+
+    // Price improvement against the best benchmark
+    pub fn user_surplus_bps(&self) -> Option<f64> {
+        let user_amount = self.user_amount()?;
+        self.benchmarks.iter()
+            .map(|benchmark| benchmark.price_improvement_bps(user_amount))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+
+    fn user_amount(&self) -> Option<f64> {
+        match &self.result {
+            SimulationResult::Filled { fill, .. } => Some(fill.user_amount),
+            SimulationResult::Expired { .. } | SimulationResult::Censored { .. } => None,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::supported_assets;
+    use crate::simulation::{Fill, SolverModel};
+
+    fn make_outcome(kind: OrderKind, user_amount: f64, benchmarks: &[(&str, f64)]) -> OrderOutcome {
+        let solver_quote = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.)
+            .make_quote(kind, 2010., 0.);
+        let fill = Fill {
+            step: 0,
+            elapsed_ms: 0,
+            fee_bps: 0.,
+            fee_amount: 0.,
+            user_amount,
+            reference_amount: 2000.,
+            solver_quote,
+        };
+        OrderOutcome {
+            result: SimulationResult::Filled { solver: String::from("binance"), bidders: 1, fill },
+            benchmarks: benchmarks.iter()
+                .map(|(venue, amount)| Benchmark { venue: venue.to_string(), kind, amount: *amount })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_price_improvement() {
+        let outcome = make_outcome(OrderKind::Sell, 2000., &[("oneinch", 2000.), ("univ3", 2004.)]);
+        assert_eq!(outcome.price_improvement_bps("oneinch"), Some(0.));
+        assert!((outcome.price_improvement_bps("univ3").unwrap() + 19.96).abs() < 0.01);
+        assert_eq!(outcome.user_surplus_bps(), outcome.price_improvement_bps("univ3"));
+        assert!(outcome.price_improvement_bps("paraswap").is_none());
+
+        let expired = OrderOutcome { result: SimulationResult::Expired { steps: 10 }, ..outcome };
+        assert!(expired.user_surplus_bps().is_none());
+    }
+
+    #[test]
+    fn test_buy_price_improvement() {
+        // paying less than a benchmark is an improvement
+        let outcome = make_outcome(OrderKind::Buy, 2000., &[("oneinch", 2004.), ("univ3", 1996.)]);
+        assert!((outcome.price_improvement_bps("oneinch").unwrap() - 19.96).abs() < 0.01);
+        assert!((outcome.price_improvement_bps("univ3").unwrap() + 20.04).abs() < 0.01);
+        assert_eq!(outcome.user_surplus_bps(), outcome.price_improvement_bps("univ3"));
+    }
+
+}
@@ -0,0 +1,83 @@
+use crate::asset::EVM;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub chain: EVM,
+    pub number: u64,
+    pub timestamp_ms: u64,
+}
+
+// Discrete-event clock interleaving the blocks of several chains in time order.
+// Blocks are aligned to each chain's block time from the epoch, so block numbers
+// are only meaningful relative to each other
+pub struct BlockClock {
+    next_blocks: Vec<Block>,
+}
+
+impl BlockClock {
+
+    // The first block of every chain is the first one at or after `start_ms`
+    pub fn new(chains: &[EVM], start_ms: u64) -> Self {
+        let mut next_blocks: Vec<Block> = Vec::with_capacity(chains.len());
+        for chain in chains {
+            if next_blocks.iter().any(|block| block.chain == *chain) {
+                continue;
+            }
+            let number = start_ms.div_ceil(chain.block_time_ms());
+            next_blocks.push(Block {
+                chain: *chain,
+                number,
+                timestamp_ms: number * chain.block_time_ms(),
+            });
+        }
+        Self { next_blocks }
+    }
+
+}
+
+impl Iterator for BlockClock {
+    type Item = Block;
+
+    // Simultaneous blocks are produced in the order their chains were given
+    fn next(&mut self) -> Option<Block> {
+        let next = self.next_blocks.iter_mut()
+            .reduce(|earliest, block| {
+                if block.timestamp_ms < earliest.timestamp_ms { block } else { earliest }
+            })?;
+        let block = *next;
+        next.number += 1;
+        next.timestamp_ms += block.chain.block_time_ms();
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_are_aligned_to_block_time() {
+        let blocks = BlockClock::new(&[EVM::Ethereum], 13_000).take(2).collect::<Vec<_>>();
+        assert_eq!(blocks[0], Block { chain: EVM::Ethereum, number: 2, timestamp_ms: 24_000 });
+        assert_eq!(blocks[1].timestamp_ms, 36_000);
+
+        let block = BlockClock::new(&[EVM::Optimism], 4000).next().unwrap();
+        assert_eq!(block.timestamp_ms, 4000);
+    }
+
+    #[test]
+    fn test_chains_are_interleaved() {
+        let blocks = BlockClock::new(&[EVM::Ethereum, EVM::Optimism, EVM::Ethereum], 0)
+            .take_while(|block| block.timestamp_ms <= 12_000)
+            .collect::<Vec<_>>();
+        let ethereum_blocks = blocks.iter().filter(|block| block.chain == EVM::Ethereum).count();
+        let optimism_blocks = blocks.iter().filter(|block| block.chain == EVM::Optimism).count();
+        assert_eq!(ethereum_blocks, 2);
+        assert_eq!(optimism_blocks, 7);
+        assert!(blocks.windows(2).all(|w| w[0].timestamp_ms <= w[1].timestamp_ms));
+        // the Ethereum slot at 12s comes before the Optimism block at the same time
+        assert_eq!(blocks[blocks.len() - 2].chain, EVM::Ethereum);
+    }
+
+}
@@ -0,0 +1,151 @@
+use eyre::Result;
+
+use super::{UserOrder, SolverModel, SolverQuote, BPS};
+use crate::quoters::Quoter;
+
+
+// A solver hedging on its own venue, with its own costs and speed.
+// The venue is a live quoter or, in backtests, a recorded one.
+pub struct SolverAgent<V> {
+    pub name: String,
+    pub venue: V,
+    pub model: SolverModel,
+    pub latency_ms: u64,
+    pub min_margin_bps: f64,
+}
+
+pub type LiveSolverAgent<'a> = SolverAgent<&'a (dyn Quoter + Sync)>;
+
+impl<V> SolverAgent<V> {
+
+    pub fn new(
+        name: &str,
+        venue: V,
+        model: SolverModel,
+        latency_ms: u64,
+        min_margin_bps: f64,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            venue,
+            model,
+            latency_ms,
+            min_margin_bps,
+        }
+    }
+
+    pub fn wants_fill(&self, quote: &SolverQuote, user_amount: f64) -> bool {
+        quote.profit(user_amount) >= user_amount * self.min_margin_bps / BPS
+    }
+
+}
+
+impl LiveSolverAgent<'_> {
+
+    pub async fn quote(&self, order: &UserOrder) -> Result<SolverQuote> {
+        self.model.quote(self.venue, order).await
+    }
+
+}
+
+// Fastest solver willing to fill wins the race; ties go to the better net amount.
+// Returns the winner's index and the number of solvers that wanted the order.
+pub fn race<V>(
+    agents: &[SolverAgent<V>],
+    quotes: &[Option<SolverQuote>],
+    user_amount: f64,
+) -> Option<(usize, usize)> {
+    let bidders = agents.iter()
+        .zip(quotes)
+        .enumerate()
+        .filter_map(|(i, (agent, quote))| {
+            quote.as_ref()
+                .filter(|quote| agent.wants_fill(quote, user_amount))
+                .map(|quote| (i, agent.latency_ms, quote.profit(user_amount)))
+        })
+        // a quote from an empty book can't be ranked
+        .filter(|(_, _, profit)| profit.is_finite())
+        .collect::<Vec<_>>();
+    let winner = bidders.iter()
+        .min_by(|a, b| {
+            a.1.cmp(&b.1).then(b.2.total_cmp(&a.2))
+        })
+        .map(|(i, _, _)| *i)?;
+    Some((winner, bidders.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::supported_assets;
+    use crate::simulation::OrderKind;
+
+    fn make_agent(
+        name: &str,
+        latency_ms: u64,
+        min_margin_bps: f64,
+    ) -> SolverAgent<()> {
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.);
+        SolverAgent::new(name, (), model, latency_ms, min_margin_bps)
+    }
+
+    fn make_quote(net_amount_out: f64) -> Option<SolverQuote> {
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.);
+        Some(model.make_quote(OrderKind::Sell, net_amount_out, 0.))
+    }
+
+    #[test]
+    fn test_fastest_profitable_solver_wins() {
+        let agents = vec![
+            make_agent("slow", 500, 0.),
+            make_agent("fast", 50, 0.),
+            make_agent("fastest", 10, 0.),
+        ];
+        let quotes = vec![make_quote(1001.), make_quote(1001.), make_quote(999.)];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 2)));
+    }
+
+    #[test]
+    fn test_min_margin_excludes_solver() {
+        let agents = vec![
+            make_agent("greedy", 10, 50.),
+            make_agent("modest", 100, 5.),
+        ];
+        let quotes = vec![make_quote(1001.), make_quote(1001.)];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 1)));
+        assert_eq!(race(&agents, &quotes, 1000.5), None);
+    }
+
+    #[test]
+    fn test_latency_tie_goes_to_better_quote() {
+        let agents = vec![
+            make_agent("a", 10, 0.),
+            make_agent("b", 10, 0.),
+        ];
+        let quotes = vec![make_quote(1001.), make_quote(1002.)];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 2)));
+        assert_eq!(race(&agents, &[None, None], 1000.), None);
+
+        // buying, the cheaper quote is the better one
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.);
+        let quotes = vec![
+            Some(model.make_quote(OrderKind::Buy, 999., 0.)),
+            Some(model.make_quote(OrderKind::Buy, 998., 0.)),
+        ];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 2)));
+        assert_eq!(race(&agents, &quotes, 998.5), Some((1, 1)));
+    }
+
+    #[test]
+    fn test_non_finite_quote_is_skipped() {
+        let agents = vec![
+            make_agent("a", 10, 0.),
+            make_agent("b", 10, 0.),
+        ];
+        let quotes = vec![make_quote(f64::NAN), make_quote(1001.)];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 1)));
+        let quotes = vec![make_quote(f64::INFINITY), make_quote(1001.)];
+        assert_eq!(race(&agents, &quotes, 1000.), Some((1, 1)));
+    }
+
+}
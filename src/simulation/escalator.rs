@@ -0,0 +1,158 @@
+use super::{UserOrder, SolverQuote};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub step: u32, // blocks since the order was created
+    pub elapsed_ms: u64,
+    pub fee_bps: f64,
+    pub fee_amount: f64, // in the order's quoted asset
+    pub user_amount: f64, // received by sells, paid by buys
+    pub reference_amount: f64,
+    pub solver_quote: SolverQuote,
+}
+
+impl Fill {
+
+    pub fn solver_profit(&self) -> f64 {
+        self.solver_quote.profit(self.user_amount)
+    }
+
+    pub fn breakeven_fee_bps(&self) -> f64 {
+        self.solver_quote.breakeven_fee_bps(self.reference_amount)
+    }
+
+}
+
+// Fee is taken from the reference amount the user was quoted at order creation:
+// sells receive less and buys pay more as the fee escalates
+pub struct FeeEscalator<'a> {
+    order: &'a UserOrder,
+    reference_amount: f64,
+}
+
+impl<'a> FeeEscalator<'a> {
+
+    pub fn new(order: &'a UserOrder, reference_amount: f64) -> Self {
+        Self { order, reference_amount }
+    }
+
+    pub fn fee_amount_at(&self, step: u32) -> f64 {
+        self.order.schedule.fee_at(step as u64).amount(self.reference_amount)
+    }
+
+    pub fn user_amount_at(&self, step: u32) -> f64 {
+        self.order.kind.charge(self.reference_amount, self.fee_amount_at(step))
+    }
+
+    pub fn try_fill(
+        &self,
+        step: u32,
+        elapsed_ms: u64,
+        solver_quote: &SolverQuote,
+    ) -> Option<Fill> {
+        let user_amount = self.user_amount_at(step);
+        if !solver_quote.is_profitable(user_amount) {
+            return None;
+        }
+        let fee = self.order.schedule.fee_at(step as u64);
+        Some(Fill {
+            step,
+            elapsed_ms,
+            fee_bps: fee.bps(self.reference_amount),
+            fee_amount: fee.amount(self.reference_amount),
+            user_amount,
+            reference_amount: self.reference_amount,
+            solver_quote: solver_quote.clone(),
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::asset::{supported_assets, EVM};
+    use crate::simulation::{LinearSchedule, DutchAuctionSchedule, FeeUnit, SolverModel, OrderKind};
+
+    fn make_order() -> UserOrder {
+        UserOrder::new(
+            EVM::Arbitrum,
+            &supported_assets::WETH,
+            &supported_assets::USDT,
+            1.,
+            Arc::new(LinearSchedule::new(0., 5., FeeUnit::Bps)),
+        )
+    }
+
+    fn make_quote(hedge_amount_out: f64) -> SolverQuote {
+        SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.)
+            .make_quote(OrderKind::Sell, hedge_amount_out, 0.)
+    }
+
+    #[test]
+    fn test_no_fill_below_min_amount_out() {
+        let order = make_order();
+        let escalator = FeeEscalator::new(&order, 2000.);
+        assert_eq!(escalator.user_amount_at(0), 2000.);
+        assert!(escalator.try_fill(0, 0, &make_quote(1999.)).is_none());
+    }
+
+    #[test]
+    fn test_fill_once_fee_covers_spread() {
+        let order = make_order();
+        let escalator = FeeEscalator::new(&order, 2000.);
+        let solver_quote = make_quote(1998.5);
+
+        let fill_step = (0..10)
+            .find(|step| escalator.try_fill(*step, 0, &solver_quote).is_some())
+            .unwrap();
+        assert_eq!(fill_step, 2);
+
+        let fill = escalator.try_fill(fill_step, 4000, &solver_quote).unwrap();
+        assert_eq!(fill.fee_bps, 10.);
+        assert_eq!(fill.fee_amount, 2.);
+        assert_eq!(fill.user_amount, 1998.);
+        assert_eq!(fill.solver_profit(), 0.5);
+        assert!((fill.breakeven_fee_bps() - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_absolute_fee_capped() {
+        let order = make_order().with_schedule(
+            Arc::new(DutchAuctionSchedule::new(0., 1., 4, FeeUnit::Absolute))
+        );
+        let escalator = FeeEscalator::new(&order, 2000.);
+        assert!(escalator.try_fill(100, 0, &make_quote(1998.5)).is_none());
+
+        let fill = escalator.try_fill(4, 0, &make_quote(1999.)).unwrap();
+        assert_eq!(fill.fee_amount, 1.);
+        assert_eq!(fill.fee_bps, 5.);
+    }
+
+    #[test]
+    fn test_buy_order_pays_more() {
+        let order = UserOrder::new_buy(
+            EVM::Arbitrum,
+            &supported_assets::USDT,
+            &supported_assets::WETH,
+            1.,
+            Arc::new(LinearSchedule::new(0., 5., FeeUnit::Bps)),
+        );
+        // the solver buys the 1 ETH for 2001.5 USDT
+        let solver_quote = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.)
+            .make_quote(OrderKind::Buy, 2001.5, 0.);
+        let escalator = FeeEscalator::new(&order, 2000.);
+        assert_eq!(escalator.user_amount_at(1), 2001.);
+        assert!(escalator.try_fill(1, 0, &solver_quote).is_none());
+
+        let fill = escalator.try_fill(2, 0, &solver_quote).unwrap();
+        assert_eq!(fill.fee_bps, 10.);
+        assert_eq!(fill.user_amount, 2002.);
+        assert_eq!(fill.solver_profit(), 0.5);
+        assert!((fill.breakeven_fee_bps() - 7.5).abs() < 1e-9);
+    }
+
+}
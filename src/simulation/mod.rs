@@ -0,0 +1,45 @@
+mod order;
+mod schedule;
+mod solver;
+mod competition;
+mod escalator;
+mod simulator;
+mod stats;
+mod benchmark;
+mod clock;
+mod backtest;
+mod price_path;
+mod synthetic;
+mod optimizer;
+mod order_flow;
+
+pub use order::{UserOrder, OrderKind};
+pub use schedule::{
+    EscalationSchedule,
+    FeeUnit,
+    LinearSchedule,
+    ExponentialSchedule,
+    StepSchedule,
+    DutchAuctionSchedule,
+};
+pub use solver::{SolverModel, SolverQuote};
+pub use competition::{SolverAgent, LiveSolverAgent, race};
+pub use escalator::{FeeEscalator, Fill};
+pub use simulator::{Simulator, SimulationResult};
+pub use stats::Distribution;
+pub use benchmark::{Benchmark, OrderOutcome};
+pub use clock::BlockClock;
+pub use backtest::{
+    Backtest,
+    DexQuote,
+    MarketSnapshot,
+    RecordedVenue,
+    SnapshotRecorder,
+    load_snapshots,
+};
+pub use price_path::{PathParams, PricePathGenerator};
+pub use synthetic::{BookShape, SyntheticDex, SyntheticMarket, mid_prices};
+pub use optimizer::{Objective, ParamGrid, ScheduleOptimizer, ScheduleParams};
+pub use order_flow::{OrderFlowGenerator, PairFlow, SizeDistribution, load_sizes_csv};
+
+pub use crate::quoters::BPS;
@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use eyre::Result;
+
+use super::{UserOrder, EscalationSchedule, LinearSchedule, FeeUnit, Backtest};
+use super::backtest::{BacktestReport, RecordedSolverAgent};
+
+
+// Capped linear escalation in bps, the family of schedules the optimizer searches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScheduleParams {
+    pub start: f64,
+    pub rate: f64, // per block
+    pub cap: f64,
+}
+
+impl ScheduleParams {
+
+    pub fn schedule(&self) -> LinearSchedule {
+        LinearSchedule::new(self.start, self.rate, FeeUnit::Bps).with_cap(self.cap)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.rate > 0. && self.cap >= self.start
+    }
+
+    // Moves of a single parameter by its step, in both directions
+    fn neighbours(&self, step: &ScheduleParams) -> Vec<ScheduleParams> {
+        [-1., 1.].iter()
+            .flat_map(|direction| [
+                ScheduleParams { start: self.start + direction * step.start, ..*self },
+                ScheduleParams { rate: self.rate + direction * step.rate, ..*self },
+                ScheduleParams { cap: self.cap + direction * step.cap, ..*self },
+            ])
+            .filter(|params| params.is_valid())
+            .collect()
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct ParamGrid {
+    pub start: Vec<f64>,
+    pub rate: Vec<f64>,
+    pub cap: Vec<f64>,
+}
+
+impl ParamGrid {
+
+    pub fn candidates(&self) -> Vec<ScheduleParams> {
+        let mut candidates = Vec::new();
+        for start in self.start.iter() {
+            for rate in self.rate.iter() {
+                for cap in self.cap.iter() {
+                    let params = ScheduleParams { start: *start, rate: *rate, cap: *cap };
+                    if params.is_valid() {
+                        candidates.push(params);
+                    }
+                }
+            }
+        }
+        candidates
+    }
+
+}
+
+// Minimize the expected fee paid by the user subject to a target fill
+// probability within a number of blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Objective {
+    pub target_fill_rate: f64,
+    pub within_blocks: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub params: ScheduleParams,
+    pub fill_rate: f64, // within the objective's blocks
+    pub expected_cost_bps: f64, // mean fee of those fills, infinite without fills
+}
+
+impl Evaluation {
+
+    pub fn from_report(params: ScheduleParams, report: &BacktestReport, objective: &Objective) -> Self {
+        let fills = report.fills_within(objective.within_blocks);
+        let settled = report.settled_within(objective.within_blocks);
+        let fill_rate = if settled == 0 {
+            0.
+        } else {
+            fills.len() as f64 / settled as f64
+        };
+        let expected_cost_bps = if fills.is_empty() {
+            f64::INFINITY
+        } else {
+            fills.iter().map(|fill| fill.fee_bps).sum::<f64>() / fills.len() as f64
+        };
+        Self { params, fill_rate, expected_cost_bps }
+    }
+
+    pub fn is_feasible(&self, objective: &Objective) -> bool {
+        self.fill_rate >= objective.target_fill_rate
+    }
+
+    // Feasible evaluations beat infeasible ones; feasible ones are ranked by cost
+    // and infeasible ones by how close they get to the target fill rate
+    pub fn compare(&self, other: &Evaluation, objective: &Objective) -> Ordering {
+        match (self.is_feasible(objective), other.is_feasible(objective)) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (true, true) => self.expected_cost_bps.total_cmp(&other.expected_cost_bps),
+            (false, false) => other.fill_rate.total_cmp(&self.fill_rate)
+                .then(self.expected_cost_bps.total_cmp(&other.expected_cost_bps)),
+        }
+    }
+
+}
+
+impl std::fmt::Display for Evaluation {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: fill rate {:.2}%, expected cost {:.2} bps",
+            self.params.schedule().name(), self.fill_rate * 100., self.expected_cost_bps
+        )
+    }
+
+}
+
+// Scores escalation parameters by backtesting the same orders with each of them.
+// The backtest should run for at least the objective's number of blocks
+pub struct ScheduleOptimizer<'a> {
+    backtest: &'a Backtest,
+    orders: Vec<(usize, UserOrder)>,
+    solvers: &'a [RecordedSolverAgent],
+    objective: Objective,
+}
+
+impl<'a> ScheduleOptimizer<'a> {
+
+    pub fn new(
+        backtest: &'a Backtest,
+        orders: Vec<(usize, UserOrder)>,
+        solvers: &'a [RecordedSolverAgent],
+        objective: Objective,
+    ) -> Self {
+        Self { backtest, orders, solvers, objective }
+    }
+
+    pub async fn evaluate(&self, params: ScheduleParams) -> Result<Evaluation> {
+        let schedule = Arc::new(params.schedule());
+        let orders = self.orders.iter()
+            .map(|(start, order)| (*start, order.with_schedule(schedule.clone())))
+            .collect::<Vec<_>>();
+        let report = self.backtest.run(&orders, self.solvers).await?;
+        Ok(Evaluation::from_report(params, &report, &self.objective))
+    }
+
+    // All candidates of the grid, best first. Never empty
+    pub async fn grid_search(&self, grid: &ParamGrid) -> Result<Vec<Evaluation>> {
+        let candidates = grid.candidates();
+        if candidates.is_empty() {
+            return Err(eyre::eyre!("No valid schedule in the grid"));
+        }
+        let mut evaluations = Vec::new();
+        for params in candidates {
+            evaluations.push(self.evaluate(params).await?);
+        }
+        evaluations.sort_by(|a, b| a.compare(b, &self.objective));
+        Ok(evaluations)
+    }
+
+    // Moves one parameter at a time while that improves on the best evaluation
+    // and halves the steps after a round without improvement
+    pub async fn coordinate_search(
+        &self,
+        initial: Evaluation,
+        mut step: ScheduleParams,
+        rounds: usize,
+    ) -> Result<Evaluation> {
+        let mut best = initial;
+        for _ in 0..rounds {
+            let mut improved = false;
+            for params in best.params.neighbours(&step) {
+                let evaluation = self.evaluate(params).await?;
+                if evaluation.compare(&best, &self.objective) == Ordering::Less {
+                    best = evaluation;
+                    improved = true;
+                }
+            }
+            if !improved {
+                step = ScheduleParams {
+                    start: step.start / 2.,
+                    rate: step.rate / 2.,
+                    cap: step.cap / 2.,
+                };
+            }
+        }
+        Ok(best)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::{supported_assets, EVM};
+    use crate::quoters::binance::{suppported_markets, BinanceFees};
+    use crate::simulation::{
+        BookShape,
+        SyntheticDex,
+        SyntheticMarket,
+        SolverAgent,
+        SolverModel,
+        RecordedVenue,
+    };
+
+    // Flat market where Binance beats the 6bps DEX reference by ~5.5bps
+    fn make_backtest() -> Backtest {
+        let market = SyntheticMarket {
+            market: suppported_markets::ETHUSDT,
+            sell_asset: supported_assets::WETH.clone(),
+            buy_asset: supported_assets::USDT.clone(),
+            quote_sizes: vec![1.],
+            book: BookShape {
+                half_spread_bps: 0.5,
+                level_step_bps: 1.,
+                levels: 5,
+                level_qty: 10.,
+                qty_growth: 0.,
+            },
+            dexes: vec![SyntheticDex { venue: String::from("oneinch"), cost_bps: 6., lag_steps: 0 }],
+        };
+        let snapshots = market.snapshots(&[2000.; 10], 0, 2000);
+        Backtest::new(snapshots, vec![suppported_markets::ETHUSDT].into(), "oneinch", 20)
+    }
+
+    fn make_optimizer<'a>(
+        backtest: &'a Backtest,
+        solvers: &'a [RecordedSolverAgent],
+        within_blocks: u32,
+    ) -> ScheduleOptimizer<'a> {
+        let template = UserOrder::new(
+            EVM::Arbitrum,
+            &supported_assets::WETH,
+            &supported_assets::USDT,
+            1.,
+            Arc::new(LinearSchedule::new(0., 1., FeeUnit::Bps)),
+        );
+        let orders = backtest.make_orders(&template, &[1.], 4);
+        let objective = Objective { target_fill_rate: 1., within_blocks };
+        ScheduleOptimizer::new(backtest, orders, solvers, objective)
+    }
+
+    fn make_solvers() -> Vec<RecordedSolverAgent> {
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.);
+        vec![SolverAgent::new("binance", RecordedVenue::Binance(BinanceFees::zero()), model, 0, 0.)]
+    }
+
+    #[test]
+    fn test_compare_evaluations() {
+        let objective = Objective { target_fill_rate: 0.9, within_blocks: 10 };
+        let params = ScheduleParams { start: 0., rate: 1., cap: 10. };
+        let make = |fill_rate, expected_cost_bps| Evaluation { params, fill_rate, expected_cost_bps };
+
+        assert_eq!(make(0.9, 5.).compare(&make(0.95, 3.), &objective), Ordering::Greater);
+        assert_eq!(make(0.95, 5.).compare(&make(0.5, 1.), &objective), Ordering::Less);
+        assert_eq!(make(0.5, 1.).compare(&make(0.6, 8.), &objective), Ordering::Greater);
+    }
+
+    #[tokio::test]
+    async fn test_grid_search() {
+        let backtest = make_backtest();
+        let solvers = make_solvers();
+        let grid = ParamGrid { start: vec![-10., 0.], rate: vec![1., 3.], cap: vec![-20., 10.] };
+        assert_eq!(grid.candidates().len(), 4);
+
+        // -10 + 1/block reaches -5bps after 5 blocks
+        let evaluations = make_optimizer(&backtest, &solvers, 10).grid_search(&grid).await.unwrap();
+        assert_eq!(evaluations[0].params, ScheduleParams { start: -10., rate: 1., cap: 10. });
+        assert_eq!(evaluations[0].fill_rate, 1.);
+        assert!((evaluations[0].expected_cost_bps + 5.).abs() < 1e-9);
+
+        // which is too slow when orders must fill within 3 blocks
+        let optimizer = make_optimizer(&backtest, &solvers, 3);
+        let evaluations = optimizer.grid_search(&grid).await.unwrap();
+        assert_eq!(evaluations[0].params, ScheduleParams { start: -10., rate: 3., cap: 10. });
+        assert!((evaluations[0].expected_cost_bps + 4.).abs() < 1e-9);
+
+        let step = ScheduleParams { start: 1., rate: 1., cap: 1. };
+        let best = optimizer.coordinate_search(evaluations[0].clone(), step, 3).await.unwrap();
+        assert!(best.is_feasible(&optimizer.objective));
+        assert!(best.expected_cost_bps <= -4.);
+
+        // every cap is below its start
+        let grid = ParamGrid { start: vec![0.], rate: vec![1.], cap: vec![-20.] };
+        assert!(optimizer.grid_search(&grid).await.is_err());
+    }
+
+}
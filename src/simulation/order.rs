@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use eyre::Result;
+
+use super::EscalationSchedule;
+use crate::asset::{Asset, EVM};
+use crate::quoters::Quoter;
+
+
+// Which side of the trade the user fixes. The other side is the quoted amount
+// that the fee escalates against: the amount out of sells and the amount in of buys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderKind {
+    Sell, // exact sell amount
+    Buy, // exact buy amount
+}
+
+impl OrderKind {
+
+    // How much better `amount` is for the user than `reference`
+    pub fn surplus(&self, amount: f64, reference: f64) -> f64 {
+        match self {
+            OrderKind::Sell => amount - reference,
+            OrderKind::Buy => reference - amount,
+        }
+    }
+
+    // `amount` made worse for the user by `cost`
+    pub fn charge(&self, amount: f64, cost: f64) -> f64 {
+        match self {
+            OrderKind::Sell => amount - cost,
+            OrderKind::Buy => amount + cost,
+        }
+    }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct UserOrder {
+    pub chain: EVM, // escalation is enforced per block of the settlement chain
+    pub kind: OrderKind,
+    pub sell_asset: Asset,
+    pub buy_asset: Asset,
+    pub amount: f64, // sell amount of sells, buy amount of buys
+    pub schedule: Arc<dyn EscalationSchedule>,
+}
+
+impl UserOrder {
+
+    pub fn new(
+        chain: EVM,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        sell_amount: f64,
+        schedule: Arc<dyn EscalationSchedule>,
+    ) -> Self {
+        Self {
+            chain,
+            kind: OrderKind::Sell,
+            sell_asset: sell_asset.clone(),
+            buy_asset: buy_asset.clone(),
+            amount: sell_amount,
+            schedule,
+        }
+    }
+
+    pub fn new_buy(
+        chain: EVM,
+        sell_asset: &Asset,
+        buy_asset: &Asset,
+        buy_amount: f64,
+        schedule: Arc<dyn EscalationSchedule>,
+    ) -> Self {
+        Self {
+            kind: OrderKind::Buy,
+            ..Self::new(chain, sell_asset, buy_asset, buy_amount, schedule)
+        }
+    }
+
+    pub fn with_schedule(&self, schedule: Arc<dyn EscalationSchedule>) -> Self {
+        Self { schedule, ..self.clone() }
+    }
+
+    pub fn is_same_trade(&self, other: &UserOrder) -> bool {
+        self.kind == other.kind
+            && self.sell_asset.id == other.sell_asset.id
+            && self.buy_asset.id == other.buy_asset.id
+            && self.amount == other.amount
+    }
+
+    // Asset of the quoted amount, which fees and solver costs are paid in
+    pub fn quoted_asset(&self) -> &Asset {
+        match self.kind {
+            OrderKind::Sell => &self.buy_asset,
+            OrderKind::Buy => &self.sell_asset,
+        }
+    }
+
+    // The venue's quote for the side of the trade the user doesn't fix
+    pub async fn quote_on(&self, venue: &(dyn Quoter + Sync)) -> Result<f64> {
+        match self.kind {
+            OrderKind::Sell => venue.get_amount_out(&self.sell_asset, &self.buy_asset, self.amount).await,
+            OrderKind::Buy => venue.get_amount_in(&self.sell_asset, &self.buy_asset, self.amount).await,
+        }
+    }
+
+}
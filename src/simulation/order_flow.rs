@@ -0,0 +1,214 @@
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::sync::Arc;
+use eyre::Result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::distributions::WeightedIndex;
+use rand_distr::{Distribution as _, Exp, LogNormal};
+
+use super::{UserOrder, EscalationSchedule};
+use crate::asset::{Asset, EVM};
+
+
+// Order sizes in sell asset
+#[derive(Debug, Clone)]
+pub enum SizeDistribution {
+    LogNormal { mu: f64, sigma: f64 }, // of the log size
+    Empirical(Vec<f64>), // resampled uniformly
+}
+
+impl SizeDistribution {
+
+    pub fn log_normal(median: f64, sigma: f64) -> Self {
+        SizeDistribution::LogNormal { mu: median.ln(), sigma }
+    }
+
+    pub fn fit_log_normal(sizes: &[f64]) -> Result<Self> {
+        let log_sizes = sizes.iter()
+            .filter(|size| **size > 0.)
+            .map(|size| size.ln())
+            .collect::<Vec<_>>();
+        if log_sizes.len() < 2 {
+            return Err(eyre::eyre!("Not enough sizes to fit a log-normal distribution"));
+        }
+        let n = log_sizes.len() as f64;
+        let mu = log_sizes.iter().sum::<f64>() / n;
+        let variance = log_sizes.iter().map(|x| (x - mu).powi(2)).sum::<f64>() / (n - 1.);
+        Ok(SizeDistribution::LogNormal { mu, sigma: variance.sqrt() })
+    }
+
+    fn check(&self) -> Result<()> {
+        match self {
+            SizeDistribution::LogNormal { mu, sigma } => {
+                if !mu.is_finite() || !sigma.is_finite() || *sigma < 0. {
+                    return Err(eyre::eyre!(format!("Invalid log-normal sizes (mu {mu}, sigma {sigma})")));
+                }
+                Ok(())
+            },
+            SizeDistribution::Empirical(sizes) if sizes.is_empty() => {
+                Err(eyre::eyre!("No sizes to resample"))
+            },
+            SizeDistribution::Empirical(_) => Ok(()),
+        }
+    }
+
+    // Checked by `OrderFlowGenerator::generate`
+    fn sample(&self, rng: &mut StdRng) -> f64 {
+        match self {
+            SizeDistribution::LogNormal { mu, sigma } => {
+                LogNormal::new(*mu, *sigma).expect("Invalid size sigma").sample(rng)
+            },
+            SizeDistribution::Empirical(sizes) => {
+                sizes[rng.gen_range(0..sizes.len())]
+            },
+        }
+    }
+
+}
+
+// Reads the sizes in a CSV column; lines that don't parse (e.g. the header) are skipped
+pub fn load_sizes_csv(path: &str, column: usize) -> Result<Vec<f64>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut sizes = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(Ok(size)) = line.split(',').nth(column).map(|field| field.trim().parse::<f64>()) {
+            sizes.push(size);
+        }
+    }
+    if sizes.is_empty() {
+        return Err(eyre::eyre!(format!("No sizes in column {column} of {path}")));
+    }
+    Ok(sizes)
+}
+
+#[derive(Debug, Clone)]
+pub struct PairFlow {
+    pub sell_asset: Asset,
+    pub buy_asset: Asset,
+    pub weight: f64, // share of the arrivals relative to the other pairs
+    pub sizes: SizeDistribution,
+}
+
+// Poisson order arrivals over a weighted mix of pairs
+pub struct OrderFlowGenerator {
+    arrival_rate: f64, // orders per second
+    pairs: Vec<PairFlow>,
+    chain: EVM,
+    schedule: Arc<dyn EscalationSchedule>,
+    rng: StdRng,
+}
+
+impl OrderFlowGenerator {
+
+    pub fn new(
+        arrival_rate: f64,
+        pairs: Vec<PairFlow>,
+        chain: EVM,
+        schedule: Arc<dyn EscalationSchedule>,
+        seed: u64,
+    ) -> Self {
+        Self { arrival_rate, pairs, chain, schedule, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    // Orders with their creation time, in creation order
+    pub fn generate(&mut self, start_ms: u64, duration_ms: u64) -> Result<Vec<(u64, UserOrder)>> {
+        let inter_arrival = Exp::new(self.arrival_rate / 1000.)?;
+        let pair_index = WeightedIndex::new(self.pairs.iter().map(|pair| pair.weight))?;
+        for pair in self.pairs.iter() {
+            pair.sizes.check()?;
+        }
+
+        let mut orders = Vec::new();
+        let mut elapsed_ms = inter_arrival.sample(&mut self.rng);
+        while elapsed_ms < duration_ms as f64 {
+            let pair = &self.pairs[pair_index.sample(&mut self.rng)];
+            let sell_amount = pair.sizes.sample(&mut self.rng);
+            let order = UserOrder::new(
+                self.chain,
+                &pair.sell_asset,
+                &pair.buy_asset,
+                sell_amount,
+                self.schedule.clone(),
+            );
+            orders.push((start_ms + elapsed_ms as u64, order));
+            elapsed_ms += inter_arrival.sample(&mut self.rng);
+        }
+        Ok(orders)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::supported_assets;
+    use crate::simulation::{LinearSchedule, FeeUnit};
+
+    fn make_generator(seed: u64) -> OrderFlowGenerator {
+        let pairs = vec![
+            PairFlow {
+                sell_asset: supported_assets::WETH.clone(),
+                buy_asset: supported_assets::USDT.clone(),
+                weight: 3.,
+                sizes: SizeDistribution::log_normal(5., 0.5),
+            },
+            PairFlow {
+                sell_asset: supported_assets::ARB.clone(),
+                buy_asset: supported_assets::USDT.clone(),
+                weight: 1.,
+                sizes: SizeDistribution::Empirical(vec![100., 1000.]),
+            },
+        ];
+        let schedule = Arc::new(LinearSchedule::new(0., 1., FeeUnit::Bps));
+        OrderFlowGenerator::new(2., pairs, EVM::Arbitrum, schedule, seed)
+    }
+
+    #[test]
+    fn test_order_flow() {
+        let orders = make_generator(1).generate(1000, 1_000_000).unwrap();
+        // ~2 orders per second over 1000 seconds
+        assert!((orders.len() as f64 / 2000. - 1.).abs() < 0.1);
+        assert!(orders.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(orders.iter().all(|(created_ms, _)| *created_ms >= 1000 && *created_ms < 1_001_000));
+
+        let arb_orders = orders.iter()
+            .filter(|(_, order)| order.sell_asset.id == "arb")
+            .collect::<Vec<_>>();
+        assert!((arb_orders.len() as f64 / orders.len() as f64 - 0.25).abs() < 0.05);
+        assert!(arb_orders.iter().all(|(_, order)| [100., 1000.].contains(&order.amount)));
+
+        let again = make_generator(1).generate(1000, 1_000_000).unwrap();
+        assert_eq!(again.len(), orders.len());
+        assert_eq!(again[10].1.amount, orders[10].1.amount);
+    }
+
+    #[test]
+    fn test_fit_log_normal() {
+        let sizes = make_generator(2).generate(0, 1_000_000).unwrap()
+            .into_iter()
+            .filter(|(_, order)| order.sell_asset.id == "eth")
+            .map(|(_, order)| order.amount)
+            .collect::<Vec<_>>();
+        match SizeDistribution::fit_log_normal(&sizes).unwrap() {
+            SizeDistribution::LogNormal { mu, sigma } => {
+                assert!((mu.exp() / 5. - 1.).abs() < 0.05);
+                assert!((sigma / 0.5 - 1.).abs() < 0.05);
+            },
+            SizeDistribution::Empirical(_) => panic!("Expected a log-normal fit"),
+        }
+        assert!(SizeDistribution::fit_log_normal(&[1.]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        let mut generator = make_generator(3);
+        generator.pairs[1].sizes = SizeDistribution::Empirical(Vec::new());
+        assert!(generator.generate(0, 1_000_000).is_err());
+        generator.pairs[1].sizes = SizeDistribution::LogNormal { mu: 1., sigma: -1. };
+        assert!(generator.generate(0, 1_000_000).is_err());
+        generator.pairs[1].sizes = SizeDistribution::log_normal(0., 1.);
+        assert!(generator.generate(0, 1_000_000).is_err());
+    }
+
+}
@@ -0,0 +1,211 @@
+use eyre::Result;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution as _, Normal, Poisson};
+
+
+// Jump-diffusion in log price: dlnS = drift*dt + volatility*dW + J*dN
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathParams {
+    pub drift: f64, // per second
+    pub volatility: f64, // per sqrt(second)
+    pub jump_intensity: f64, // expected jumps per second
+    pub jump_mean: f64, // mean log jump
+    pub jump_std: f64,
+}
+
+impl PathParams {
+
+    // Returns larger than `jump_threshold` standard deviations are treated as jumps
+    pub fn calibrate(mid_prices: &[(u64, f64)], jump_threshold: f64) -> Result<Self> {
+        let returns = mid_prices.windows(2)
+            .filter(|w| w[1].0 > w[0].0)
+            .map(|w| ((w[1].0 - w[0].0) as f64 / 1000., (w[1].1 / w[0].1).ln()))
+            .collect::<Vec<(f64, f64)>>();
+        if returns.len() < 2 {
+            return Err(eyre::eyre!("Not enough mid prices to calibrate a price path"));
+        }
+        let total_time = returns.iter().map(|(dt, _)| dt).sum::<f64>();
+        let raw_volatility = (returns.iter().map(|(_, r)| r * r).sum::<f64>() / total_time).sqrt();
+
+        let (jumps, diffusion): (Vec<_>, Vec<_>) = returns.iter()
+            .partition(|(dt, r)| r.abs() > jump_threshold * raw_volatility * dt.sqrt());
+
+        let diffusion_time = diffusion.iter().map(|(dt, _)| dt).sum::<f64>();
+        if diffusion_time == 0. {
+            return Err(eyre::eyre!(format!("Every return is a jump at threshold {jump_threshold}")));
+        }
+        let drift = diffusion.iter().map(|(_, r)| r).sum::<f64>() / diffusion_time;
+        let variance = diffusion.iter()
+            .map(|(dt, r)| (r - drift * dt).powi(2))
+            .sum::<f64>() / diffusion_time;
+
+        let jump_sizes = jumps.iter().map(|(_, r)| *r).collect::<Vec<_>>();
+        let jump_mean = if jump_sizes.is_empty() {
+            0.
+        } else {
+            jump_sizes.iter().sum::<f64>() / jump_sizes.len() as f64
+        };
+        let jump_std = if jump_sizes.len() < 2 {
+            0.
+        } else {
+            let jump_variance = jump_sizes.iter()
+                .map(|j| (j - jump_mean).powi(2))
+                .sum::<f64>() / (jump_sizes.len() - 1) as f64;
+            jump_variance.sqrt()
+        };
+
+        let params = Self {
+            drift,
+            volatility: variance.sqrt(),
+            jump_intensity: jump_sizes.len() as f64 / total_time,
+            jump_mean,
+            jump_std,
+        };
+        params.check()?;
+        Ok(params)
+    }
+
+    fn check(&self) -> Result<()> {
+        let values = [self.drift, self.volatility, self.jump_intensity, self.jump_mean, self.jump_std];
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(eyre::eyre!(format!("Non-finite price path parameters {self:?}")));
+        }
+        if self.volatility < 0. || self.jump_intensity < 0. || self.jump_std < 0. {
+            return Err(eyre::eyre!(format!("Negative price path parameters {self:?}")));
+        }
+        Ok(())
+    }
+
+    pub fn stressed(&self, volatility_multiplier: f64, jump_multiplier: f64) -> Self {
+        Self {
+            volatility: self.volatility * volatility_multiplier,
+            jump_intensity: self.jump_intensity * jump_multiplier,
+            jump_std: self.jump_std * jump_multiplier,
+            ..self.clone()
+        }
+    }
+
+}
+
+pub struct PricePathGenerator {
+    params: PathParams,
+    rng: StdRng,
+}
+
+impl PricePathGenerator {
+
+    pub fn new(params: PathParams, seed: u64) -> Self {
+        Self { params, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    pub fn generate(&mut self, start_price: f64, step_ms: u64, steps: usize) -> Result<Vec<f64>> {
+        self.params.check()?;
+        let dt = step_ms as f64 / 1000.;
+        let diffusion = Normal::new(self.params.drift * dt, self.params.volatility * dt.sqrt())?;
+        let jump = Normal::new(self.params.jump_mean, self.params.jump_std)?;
+        let jump_count = Poisson::new(self.params.jump_intensity * dt).ok();
+
+        let mut price = start_price;
+        let mut path = Vec::with_capacity(steps + 1);
+        path.push(price);
+        for _ in 0..steps {
+            let mut log_return = diffusion.sample(&mut self.rng);
+            let jumps = jump_count.map(|n| n.sample(&mut self.rng) as u64).unwrap_or(0);
+            for _ in 0..jumps {
+                log_return += jump.sample(&mut self.rng);
+            }
+            price *= log_return.exp();
+            path.push(price);
+        }
+        Ok(path)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_mid_prices(path: &[f64], step_ms: u64) -> Vec<(u64, f64)> {
+        path.iter()
+            .enumerate()
+            .map(|(i, price)| (i as u64 * step_ms, *price))
+            .collect()
+    }
+
+    #[test]
+    fn test_seeded_paths_are_reproducible() {
+        let params = PathParams {
+            drift: 0.,
+            volatility: 0.001,
+            jump_intensity: 0.01,
+            jump_mean: 0.,
+            jump_std: 0.01,
+        };
+        let path_a = PricePathGenerator::new(params.clone(), 7).generate(2000., 1000, 100).unwrap();
+        let path_b = PricePathGenerator::new(params.clone(), 7).generate(2000., 1000, 100).unwrap();
+        let path_c = PricePathGenerator::new(params, 8).generate(2000., 1000, 100).unwrap();
+        assert_eq!(path_a.len(), 101);
+        assert_eq!(path_a, path_b);
+        assert_ne!(path_a, path_c);
+    }
+
+    #[test]
+    fn test_calibrate_diffusion() {
+        let params = PathParams {
+            drift: 0.,
+            volatility: 0.0005,
+            jump_intensity: 0.,
+            jump_mean: 0.,
+            jump_std: 0.,
+        };
+        let step_ms = 2000;
+        let path = PricePathGenerator::new(params, 1).generate(2000., step_ms, 20_000).unwrap();
+        let calibrated = PathParams::calibrate(&to_mid_prices(&path, step_ms), 5.).unwrap();
+        assert!((calibrated.volatility / 0.0005 - 1.).abs() < 0.05);
+        assert!(calibrated.jump_intensity < 0.0001);
+    }
+
+    #[test]
+    fn test_calibrate_jumps() {
+        let params = PathParams {
+            drift: 0.,
+            volatility: 0.0005,
+            jump_intensity: 0.01,
+            jump_mean: -0.01,
+            jump_std: 0.002,
+        };
+        let step_ms = 1000;
+        let path = PricePathGenerator::new(params, 3).generate(2000., step_ms, 50_000).unwrap();
+        let calibrated = PathParams::calibrate(&to_mid_prices(&path, step_ms), 5.).unwrap();
+        assert!((calibrated.volatility / 0.0005 - 1.).abs() < 0.05);
+        assert!((calibrated.jump_intensity / 0.01 - 1.).abs() < 0.2);
+        assert!((calibrated.jump_mean / -0.01 - 1.).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_calibrate_needs_prices() {
+        assert!(PathParams::calibrate(&[(0, 2000.), (1000, 2001.)], 5.).is_err());
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let mid_prices = [(0, 2000.), (1000, 2001.), (2000, 1999.), (3000, 2002.)];
+        assert!(PathParams::calibrate(&mid_prices, 5.).is_ok());
+        // every return is a jump at a tenth of a standard deviation
+        assert!(PathParams::calibrate(&mid_prices, 0.1).is_err());
+
+        let params = PathParams {
+            drift: 0.,
+            volatility: 0.001,
+            jump_intensity: 0.01,
+            jump_mean: 0.,
+            jump_std: 0.01,
+        };
+        assert!(PricePathGenerator::new(params.stressed(-1., 1.), 1).generate(2000., 1000, 10).is_err());
+        assert!(PricePathGenerator::new(params.stressed(1., -1.), 1).generate(2000., 1000, 10).is_err());
+        let params = PathParams { drift: f64::NAN, ..params };
+        assert!(PricePathGenerator::new(params, 1).generate(2000., 1000, 10).is_err());
+    }
+
+}
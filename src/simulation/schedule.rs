@@ -0,0 +1,250 @@
+use std::fmt::Debug;
+use eyre::Result;
+
+use super::BPS;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fee {
+    Bps(f64), // of the order notional
+    Absolute(f64), // in the order's quoted asset
+}
+
+impl Fee {
+
+    pub fn amount(&self, notional: f64) -> f64 {
+        match self {
+            Fee::Bps(fee_bps) => notional * fee_bps / BPS,
+            Fee::Absolute(fee_amount) => *fee_amount,
+        }
+    }
+
+    pub fn bps(&self, notional: f64) -> f64 {
+        match self {
+            Fee::Bps(fee_bps) => *fee_bps,
+            Fee::Absolute(fee_amount) => fee_amount / notional * BPS,
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeUnit {
+    Bps,
+    Absolute,
+}
+
+impl FeeUnit {
+
+    fn fee(&self, value: f64) -> Fee {
+        match self {
+            FeeUnit::Bps => Fee::Bps(value),
+            FeeUnit::Absolute => Fee::Absolute(value),
+        }
+    }
+
+}
+
+// `elapsed` is counted in escalation periods - simulation steps or blocks,
+// depending on what drives the simulation
+pub trait EscalationSchedule: Debug + Send + Sync {
+
+    fn fee_at(&self, elapsed: u64) -> Fee;
+
+    fn name(&self) -> String;
+
+}
+
+// fee = min(start + rate * elapsed, cap)
+#[derive(Debug, Clone)]
+pub struct LinearSchedule {
+    pub start: f64,
+    pub rate: f64,
+    pub cap: Option<f64>,
+    pub unit: FeeUnit,
+}
+
+impl LinearSchedule {
+
+    pub fn new(start: f64, rate: f64, unit: FeeUnit) -> Self {
+        Self { start, rate, cap: None, unit }
+    }
+
+    pub fn with_cap(self, cap: f64) -> Self {
+        Self { cap: Some(cap), ..self }
+    }
+
+}
+
+impl EscalationSchedule for LinearSchedule {
+
+    fn fee_at(&self, elapsed: u64) -> Fee {
+        let fee = self.start + self.rate * elapsed as f64;
+        self.unit.fee(self.cap.map(|cap| fee.min(cap)).unwrap_or(fee))
+    }
+
+    fn name(&self) -> String {
+        match self.cap {
+            Some(cap) => format!("linear({}+{}/period, cap {})", self.start, self.rate, cap),
+            None => format!("linear({}+{}/period)", self.start, self.rate),
+        }
+    }
+
+}
+
+// fee = start * (1 + growth)^elapsed
+#[derive(Debug, Clone)]
+pub struct ExponentialSchedule {
+    pub start: f64,
+    pub growth: f64,
+    pub unit: FeeUnit,
+}
+
+impl ExponentialSchedule {
+
+    pub fn new(start: f64, growth: f64, unit: FeeUnit) -> Self {
+        Self { start, growth, unit }
+    }
+
+}
+
+impl EscalationSchedule for ExponentialSchedule {
+
+    fn fee_at(&self, elapsed: u64) -> Fee {
+        self.unit.fee(self.start * (1. + self.growth).powf(elapsed as f64))
+    }
+
+    fn name(&self) -> String {
+        format!("exponential({}*{}^period)", self.start, 1. + self.growth)
+    }
+
+}
+
+// Fee jumps to the level of the latest step reached; steps are (elapsed, fee)
+#[derive(Debug, Clone)]
+pub struct StepSchedule {
+    steps: Vec<(u64, f64)>,
+    unit: FeeUnit,
+}
+
+impl StepSchedule {
+
+    pub fn new(mut steps: Vec<(u64, f64)>, unit: FeeUnit) -> Result<Self> {
+        if steps.is_empty() {
+            return Err(eyre::eyre!("Step schedule needs at least one step"));
+        }
+        steps.sort_by_key(|(elapsed, _)| *elapsed);
+        Ok(Self { steps, unit })
+    }
+
+    pub fn steps(&self) -> &[(u64, f64)] {
+        &self.steps
+    }
+
+    pub fn unit(&self) -> FeeUnit {
+        self.unit
+    }
+
+}
+
+impl EscalationSchedule for StepSchedule {
+
+    fn fee_at(&self, elapsed: u64) -> Fee {
+        let steps = self.steps();
+        let fee = steps.iter()
+            .take_while(|(step_start, _)| *step_start <= elapsed)
+            .last()
+            .or(steps.first())
+            .map_or(0., |(_, fee)| *fee);
+        self.unit().fee(fee)
+    }
+
+    fn name(&self) -> String {
+        format!("step({} levels)", self.steps().len())
+    }
+
+}
+
+// Fee moves linearly from start to cap over `duration` periods, then stays at cap
+#[derive(Debug, Clone)]
+pub struct DutchAuctionSchedule {
+    pub start: f64,
+    pub cap: f64,
+    pub duration: u64,
+    pub unit: FeeUnit,
+}
+
+impl DutchAuctionSchedule {
+
+    pub fn new(start: f64, cap: f64, duration: u64, unit: FeeUnit) -> Self {
+        Self { start, cap, duration, unit }
+    }
+
+}
+
+impl EscalationSchedule for DutchAuctionSchedule {
+
+    fn fee_at(&self, elapsed: u64) -> Fee {
+        if elapsed >= self.duration {
+            return self.unit.fee(self.cap);
+        }
+        let progress = elapsed as f64 / self.duration as f64;
+        self.unit.fee(self.start + (self.cap - self.start) * progress)
+    }
+
+    fn name(&self) -> String {
+        format!("dutch({}->{} over {})", self.start, self.cap, self.duration)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_amount() {
+        assert_eq!(Fee::Bps(10.).amount(2000.), 2.);
+        assert_eq!(Fee::Absolute(2.).amount(2000.), 2.);
+        assert_eq!(Fee::Absolute(2.).bps(2000.), 10.);
+    }
+
+    #[test]
+    fn test_linear() {
+        let schedule = LinearSchedule::new(-5., 2.5, FeeUnit::Bps);
+        assert_eq!(schedule.fee_at(0), Fee::Bps(-5.));
+        assert_eq!(schedule.fee_at(4), Fee::Bps(5.));
+
+        let capped = schedule.with_cap(2.5);
+        assert_eq!(capped.fee_at(3), Fee::Bps(2.5));
+        assert_eq!(capped.fee_at(100), Fee::Bps(2.5));
+    }
+
+    #[test]
+    fn test_exponential() {
+        let schedule = ExponentialSchedule::new(1., 1., FeeUnit::Absolute);
+        assert_eq!(schedule.fee_at(0), Fee::Absolute(1.));
+        assert_eq!(schedule.fee_at(3), Fee::Absolute(8.));
+    }
+
+    #[test]
+    fn test_step() {
+        let schedule = StepSchedule::new(vec![(10, 5.), (0, 1.), (20, 10.)], FeeUnit::Bps).unwrap();
+        assert_eq!(schedule.fee_at(0), Fee::Bps(1.));
+        assert_eq!(schedule.fee_at(9), Fee::Bps(1.));
+        assert_eq!(schedule.fee_at(10), Fee::Bps(5.));
+        assert_eq!(schedule.fee_at(100), Fee::Bps(10.));
+        assert_eq!(schedule.steps(), &[(0, 1.), (10, 5.), (20, 10.)]);
+        assert!(StepSchedule::new(Vec::new(), FeeUnit::Bps).is_err());
+    }
+
+    #[test]
+    fn test_dutch_auction_capped() {
+        let schedule = DutchAuctionSchedule::new(0., 20., 10, FeeUnit::Bps);
+        assert_eq!(schedule.fee_at(0), Fee::Bps(0.));
+        assert_eq!(schedule.fee_at(5), Fee::Bps(10.));
+        assert_eq!(schedule.fee_at(10), Fee::Bps(20.));
+        assert_eq!(schedule.fee_at(50), Fee::Bps(20.));
+    }
+
+}
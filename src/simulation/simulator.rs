@@ -0,0 +1,155 @@
+use eyre::Result;
+use futures::future::join_all;
+
+use super::{
+    UserOrder,
+    FeeEscalator,
+    Fill,
+    LiveSolverAgent,
+    SolverQuote,
+    BlockClock,
+    Benchmark,
+    OrderOutcome,
+    race,
+};
+use crate::quoters::Quoter;
+
+
+#[derive(Debug, Clone)]
+pub enum SimulationResult {
+    Filled { solver: String, bidders: usize, fill: Fill },
+    Expired { steps: u32 },
+    Censored { steps: u32 }, // recorded data ran out after `steps` blocks, before the order filled or expired
+}
+
+// Steps through the escalation live on the block clock of each order's settlement
+// chain; at every block each solver quotes its hedge venue and the fastest one that
+// can profitably pay the user wins the order
+pub struct Simulator {
+    max_blocks: u32,
+}
+
+impl Simulator {
+
+    pub fn new(max_blocks: u32) -> Self {
+        Self { max_blocks }
+    }
+
+    // Orders must be for the same trade and only differ in their escalation
+    // schedule or chain, so all of them are compared against the same quotes.
+    // Every benchmark venue is quoted at creation and the fee is taken from the
+    // reference venue's quote
+    pub async fn run(
+        &self,
+        orders: &[UserOrder],
+        reference_venue: &str,
+        benchmark_quoters: &[(&str, &(dyn Quoter + Sync))],
+        solvers: &[LiveSolverAgent<'_>],
+    ) -> Result<Vec<OrderOutcome>> {
+        let order = orders.first()
+            .ok_or(eyre::eyre!("No orders to simulate"))?;
+        if !orders.iter().all(|o| o.is_same_trade(order)) {
+            return Err(eyre::eyre!("Simulated orders must be for the same trade"));
+        }
+
+        let benchmarks = join_all(benchmark_quoters.iter().map(|(_, quoter)| order.quote_on(*quoter)))
+            .await
+            .into_iter()
+            .zip(benchmark_quoters)
+            .filter_map(|(amount, (venue, _))| match amount {
+                Ok(amount) => Some(Benchmark { venue: venue.to_string(), kind: order.kind, amount }),
+                Err(e) => {
+                    println!("Error ({venue}): {e}");
+                    None
+                },
+            })
+            .collect::<Vec<_>>();
+        let reference_amount = benchmarks.iter()
+            .find(|benchmark| benchmark.venue == reference_venue)
+            .map(|benchmark| benchmark.amount)
+            .ok_or(eyre::eyre!(format!("No {reference_venue} quote at order creation")))?;
+        let escalators = orders.iter()
+            .map(|o| FeeEscalator::new(o, reference_amount))
+            .collect::<Vec<_>>();
+        let mut results: Vec<Option<SimulationResult>> = vec![None; orders.len()];
+        let mut first_blocks: Vec<Option<u64>> = vec![None; orders.len()];
+
+        let start = std::time::Instant::now();
+        let start_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as u64;
+        let chains = orders.iter().map(|o| o.chain).collect::<Vec<_>>();
+        for block in BlockClock::new(&chains, start_ms) {
+            // steps count blocks since the order's first one, including skipped ones
+            let mut pending = Vec::new();
+            for i in 0..orders.len() {
+                if results[i].is_some() || orders[i].chain != block.chain {
+                    continue;
+                }
+                let step = (block.number - *first_blocks[i].get_or_insert(block.number)) as u32;
+                if step >= self.max_blocks {
+                    results[i] = Some(SimulationResult::Expired { steps: self.max_blocks });
+                } else {
+                    pending.push((i, step));
+                }
+            }
+            if results.iter().all(|result| result.is_some()) {
+                break;
+            }
+            if pending.is_empty() {
+                continue;
+            }
+            let block_offset = std::time::Duration::from_millis(block.timestamp_ms - start_ms);
+            tokio::time::sleep_until((start + block_offset).into()).await;
+
+            // when quoting takes longer than a block, the blocks that passed
+            // meanwhile are skipped rather than priced with later market data
+            let elapsed_ms = start.elapsed().as_millis() as u64;
+            if start_ms + elapsed_ms >= block.timestamp_ms + block.chain.block_time_ms() {
+                continue;
+            }
+            let quotes = join_all(solvers.iter().map(|solver| solver.quote(order)))
+                .await
+                .into_iter()
+                .zip(solvers)
+                .map(|(quote, solver)| match quote {
+                    Ok(quote) => Some(quote),
+                    Err(e) => {
+                        println!("Error ({}): {}", solver.name, e);
+                        None
+                    },
+                })
+                .collect::<Vec<Option<SolverQuote>>>();
+
+            for (i, step) in pending {
+                let escalator = &escalators[i];
+                let user_amount = escalator.user_amount_at(step);
+                if let Some((winner, bidders)) = race(solvers, &quotes, user_amount) {
+                    let solver = &solvers[winner];
+                    let quote = quotes[winner].as_ref().unwrap();
+                    results[i] = escalator.try_fill(step, elapsed_ms + solver.latency_ms, quote)
+                        .map(|fill| SimulationResult::Filled {
+                            solver: solver.name.clone(),
+                            bidders,
+                            fill,
+                        });
+                }
+                if results[i].is_none() && step + 1 >= self.max_blocks {
+                    results[i] = Some(SimulationResult::Expired { steps: self.max_blocks });
+                }
+            }
+            if results.iter().all(|result| result.is_some()) {
+                break;
+            }
+        }
+
+        let outcomes = results.into_iter()
+            .map(|result| OrderOutcome {
+                result: result.unwrap_or(SimulationResult::Expired { steps: self.max_blocks }),
+                benchmarks: benchmarks.clone(),
+            })
+            .collect();
+        Ok(outcomes)
+    }
+
+}
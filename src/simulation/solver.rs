@@ -0,0 +1,177 @@
+use eyre::Result;
+
+use super::{UserOrder, OrderKind, BPS};
+use crate::asset::Asset;
+use crate::quoters::Quoter;
+
+
+// Amounts are the order's quoted amount: proceeds of sells and cost of buys
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolverQuote {
+    pub kind: OrderKind,
+    pub hedge_amount: f64, // on the venue
+    pub venue_fee: f64,
+    pub gas_cost: f64,
+    pub risk_premium: f64,
+    pub funding_cost: f64, // negative when the hedge earns funding
+}
+
+impl SolverQuote {
+
+    // Best amount the solver can give the user without making a loss
+    pub fn net_amount(&self) -> f64 {
+        self.kind.charge(self.hedge_amount, self.venue_fee + self.gas_cost + self.risk_premium + self.funding_cost)
+    }
+
+    pub fn profit(&self, user_amount: f64) -> f64 {
+        self.kind.surplus(self.net_amount(), user_amount)
+    }
+
+    pub fn is_profitable(&self, user_amount: f64) -> bool {
+        self.profit(user_amount) >= 0.
+    }
+
+    // Lowest fee (relative to the reference amount) a rational solver fills at
+    pub fn breakeven_fee_bps(&self, reference_amount: f64) -> f64 {
+        -self.kind.surplus(self.net_amount(), reference_amount) / reference_amount * BPS
+    }
+
+}
+
+// All costs are expressed in the order's quoted asset
+#[derive(Debug, Clone)]
+pub struct SolverModel {
+    pub venue_fee_bps: f64, // on top of the fees already in the venue's quotes
+    pub gas_units: u64, // gas used by the on-chain fill
+    pub gas_price_gwei: f64,
+    pub gas_asset: Asset,
+    pub risk_premium_bps: f64,
+    pub holding_ms: u64, // until the hedge is closed, perpetuals charge funding meanwhile
+}
+
+impl SolverModel {
+
+    pub fn new(
+        venue_fee_bps: f64,
+        gas_units: u64,
+        gas_price_gwei: f64,
+        gas_asset: &Asset,
+        risk_premium_bps: f64,
+    ) -> Self {
+        Self {
+            venue_fee_bps,
+            gas_units,
+            gas_price_gwei,
+            gas_asset: gas_asset.clone(),
+            risk_premium_bps,
+            holding_ms: 0,
+        }
+    }
+
+    pub fn with_holding_ms(mut self, holding_ms: u64) -> Self {
+        self.holding_ms = holding_ms;
+        self
+    }
+
+    pub fn gas_cost_in_gas_asset(&self) -> f64 {
+        self.gas_units as f64 * self.gas_price_gwei * 1e-9
+    }
+
+    pub fn make_quote(&self, kind: OrderKind, hedge_amount: f64, gas_cost: f64) -> SolverQuote {
+        SolverQuote {
+            kind,
+            hedge_amount,
+            venue_fee: hedge_amount * self.venue_fee_bps / BPS,
+            gas_cost,
+            risk_premium: hedge_amount * self.risk_premium_bps / BPS,
+            funding_cost: 0.,
+        }
+    }
+
+    pub async fn quote(
+        &self,
+        venue: &(dyn Quoter + Sync),
+        order: &UserOrder,
+    ) -> Result<SolverQuote> {
+        let hedge_amount = order.quote_on(venue).await?;
+        let gas_cost = self.gas_cost_in(order.quoted_asset(), venue).await?;
+        // the hedge sells what the user sells
+        let holding_cost = venue.get_holding_cost(&order.sell_asset, &order.buy_asset, self.holding_ms).await?;
+        Ok(SolverQuote {
+            funding_cost: hedge_amount * holding_cost,
+            ..self.make_quote(order.kind, hedge_amount, gas_cost)
+        })
+    }
+
+    // Gas is valued in the asset at the venue's price. It is not traded, so it
+    // is not held to the venue's minimum order size
+    pub async fn gas_cost_in(
+        &self,
+        asset: &Asset,
+        venue: &(dyn Quoter + Sync),
+    ) -> Result<f64> {
+        let gas_cost = self.gas_cost_in_gas_asset();
+        if gas_cost == 0. || asset.id == self.gas_asset.id {
+            return Ok(gas_cost);
+        }
+        venue.get_value(&self.gas_asset, asset, gas_cost).await
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::supported_assets;
+
+    #[test]
+    fn test_gas_cost() {
+        let model = SolverModel::new(0., 500_000, 0.1, &supported_assets::ETH, 0.);
+        assert_eq!(model.gas_cost_in_gas_asset(), 0.00005);
+    }
+
+    #[test]
+    fn test_quote_costs() {
+        let model = SolverModel::new(7.5, 0, 0., &supported_assets::ETH, 2.5);
+        let quote = model.make_quote(OrderKind::Sell, 20_000., 1.);
+        assert_eq!(quote.venue_fee, 15.);
+        assert_eq!(quote.risk_premium, 5.);
+        assert_eq!(quote.net_amount(), 19_979.);
+        assert!(quote.is_profitable(19_979.));
+        assert!(!quote.is_profitable(19_980.));
+    }
+
+    #[test]
+    fn test_buy_quote_costs() {
+        // buying costs 20_000 on the venue, so the user must pay more
+        let model = SolverModel::new(7.5, 0, 0., &supported_assets::ETH, 2.5);
+        let quote = model.make_quote(OrderKind::Buy, 20_000., 1.);
+        assert_eq!(quote.net_amount(), 20_021.);
+        assert!(quote.is_profitable(20_021.));
+        assert!(!quote.is_profitable(20_020.));
+        assert_eq!(quote.profit(20_031.), 10.);
+    }
+
+    #[test]
+    fn test_funding_cost() {
+        let model = SolverModel::new(0., 0, 0., &supported_assets::ETH, 0.).with_holding_ms(60_000);
+        // a short perp hedge receiving funding can pay the user more
+        let quote = SolverQuote { funding_cost: -2., ..model.make_quote(OrderKind::Sell, 20_000., 0.) };
+        assert_eq!(quote.net_amount(), 20_002.);
+        let quote = SolverQuote { funding_cost: 2., ..model.make_quote(OrderKind::Buy, 20_000., 0.) };
+        assert_eq!(quote.net_amount(), 20_002.);
+    }
+
+    #[test]
+    fn test_breakeven_fee() {
+        let model = SolverModel::new(10., 0, 0., &supported_assets::ETH, 0.);
+        let quote = model.make_quote(OrderKind::Sell, 10_000., 0.);
+        let breakeven_fee_bps = quote.breakeven_fee_bps(10_000.);
+        assert!((breakeven_fee_bps - 10.).abs() < 1e-9);
+
+        let quote = model.make_quote(OrderKind::Buy, 10_000., 0.);
+        let breakeven_fee_bps = quote.breakeven_fee_bps(10_000.);
+        assert!((breakeven_fee_bps - 10.).abs() < 1e-9);
+    }
+
+}
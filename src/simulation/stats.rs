@@ -0,0 +1,85 @@
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub p10: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+
+    // Non-finite samples are dropped
+    pub fn from_samples(samples: &[f64]) -> Option<Self> {
+        let mut sorted = samples.iter()
+            .copied()
+            .filter(|sample| sample.is_finite())
+            .collect::<Vec<_>>();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        Some(Self {
+            count,
+            mean: sorted.iter().sum::<f64>() / count as f64,
+            min: sorted[0],
+            p10: Self::percentile(&sorted, 0.1),
+            p50: Self::percentile(&sorted, 0.5),
+            p90: Self::percentile(&sorted, 0.9),
+            p99: Self::percentile(&sorted, 0.99),
+            max: sorted[count - 1],
+        })
+    }
+
+    // Nearest-rank percentile of sorted samples
+    fn percentile(sorted: &[f64], p: f64) -> f64 {
+        let rank = (p * sorted.len() as f64).ceil() as usize;
+        sorted[rank.saturating_sub(1)]
+    }
+
+}
+
+impl std::fmt::Display for Distribution {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "n={} mean={:.2} min={:.2} p10={:.2} p50={:.2} p90={:.2} p99={:.2} max={:.2}",
+            self.count, self.mean, self.min, self.p10, self.p50, self.p90, self.p99, self.max
+        )
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution() {
+        let samples = (1..=100).rev().map(|x| x as f64).collect::<Vec<_>>();
+        let dist = Distribution::from_samples(&samples).unwrap();
+        assert_eq!(dist.count, 100);
+        assert_eq!(dist.mean, 50.5);
+        assert_eq!(dist.min, 1.);
+        assert_eq!(dist.p10, 10.);
+        assert_eq!(dist.p50, 50.);
+        assert_eq!(dist.p99, 99.);
+        assert_eq!(dist.max, 100.);
+        assert!(Distribution::from_samples(&[]).is_none());
+    }
+
+    #[test]
+    fn test_distribution_drops_non_finite() {
+        let dist = Distribution::from_samples(&[3., f64::NAN, 1., f64::INFINITY, 2.]).unwrap();
+        assert_eq!(dist.count, 3);
+        assert_eq!(dist.mean, 2.);
+        assert_eq!(dist.max, 3.);
+        assert!(Distribution::from_samples(&[f64::NAN]).is_none());
+    }
+
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use super::{MarketSnapshot, DexQuote, BPS};
+use crate::asset::{Asset, Domain};
+use crate::quoters::binance::{BinanceOrderBook, BinanceSnapshot, Market};
+
+
+// Symmetric book around the mid, with level size growing away from the touch
+#[derive(Debug, Clone)]
+pub struct BookShape {
+    pub half_spread_bps: f64,
+    pub level_step_bps: f64,
+    pub levels: usize,
+    pub level_qty: f64,
+    pub qty_growth: f64, // per level
+}
+
+impl BookShape {
+
+    pub fn make_book(&self, mid_price: f64, tick_size: f64, timestamp_ms: u64) -> BinanceOrderBook {
+        let make_side = |direction: f64| {
+            (0..self.levels)
+                .map(|i| {
+                    let offset_bps = self.half_spread_bps + self.level_step_bps * i as f64;
+                    let price = mid_price * (1. + direction * offset_bps / BPS);
+                    let qty = self.level_qty * (1. + self.qty_growth).powi(i as i32);
+                    (price, qty)
+                })
+                .collect::<Vec<_>>()
+        };
+        BinanceOrderBook::from_levels(
+            self.levels as u32,
+            tick_size,
+            timestamp_ms,
+            make_side(-1.),
+            make_side(1.),
+        )
+    }
+
+}
+
+// DEX quote at a fixed cost to the mid, lagging the CEX by a number of steps
+#[derive(Debug, Clone)]
+pub struct SyntheticDex {
+    pub venue: String,
+    pub cost_bps: f64,
+    pub lag_steps: usize,
+}
+
+pub struct SyntheticMarket {
+    pub market: Market,
+    pub sell_asset: Asset,
+    pub buy_asset: Asset,
+    pub quote_sizes: Vec<f64>, // sell amounts of the recorded DEX quotes
+    pub book: BookShape,
+    pub dexes: Vec<SyntheticDex>,
+}
+
+impl SyntheticMarket {
+
+    pub fn snapshots(
+        &self,
+        mid_prices: &[f64],
+        start_ms: u64,
+        step_ms: u64,
+    ) -> Vec<MarketSnapshot> {
+        let sells_base = self.sell_asset.get_domain_id(Domain::Binance)
+            .map(|id| id == self.market.base())
+            .unwrap_or(true);
+        mid_prices.iter()
+            .enumerate()
+            .map(|(i, mid_price)| {
+                let timestamp_ms = start_ms + i as u64 * step_ms;
+                let mut books = HashMap::new();
+                books.insert(self.market.ticker(), self.book.make_book(*mid_price, self.market.tick_size(), timestamp_ms));
+                let dex_quotes = self.dexes.iter()
+                    .flat_map(|dex| self.quote_sizes.iter().map(move |quote_size| (dex, *quote_size)))
+                    .map(|(dex, quote_size)| {
+                        let dex_mid = mid_prices[i.saturating_sub(dex.lag_steps)];
+                        let gross_amount_out = if sells_base {
+                            quote_size * dex_mid
+                        } else {
+                            quote_size / dex_mid
+                        };
+                        DexQuote {
+                            venue: dex.venue.clone(),
+                            sell_asset: self.sell_asset.id.clone(),
+                            buy_asset: self.buy_asset.id.clone(),
+                            sell_amount: quote_size,
+                            amount_out: gross_amount_out * (1. - dex.cost_bps / BPS),
+                        }
+                    })
+                    .collect();
+                MarketSnapshot {
+                    timestamp_ms,
+                    binance: BinanceSnapshot { timestamp_ms, books },
+                    dex_quotes,
+                }
+            })
+            .collect()
+    }
+
+}
+
+pub fn mid_prices(snapshots: &[MarketSnapshot], market: &Market) -> Vec<(u64, f64)> {
+    let ticker = market.ticker();
+    snapshots.iter()
+        .filter_map(|snapshot| {
+            let mid_price = snapshot.binance.books.get(&ticker)?.mid_price()?;
+            Some((snapshot.timestamp_ms, mid_price))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::supported_assets;
+    use crate::quoters::binance::suppported_markets;
+
+    fn make_market() -> SyntheticMarket {
+        SyntheticMarket {
+            market: suppported_markets::ETHUSDT,
+            sell_asset: supported_assets::WETH.clone(),
+            buy_asset: supported_assets::USDT.clone(),
+            quote_sizes: vec![10.],
+            book: BookShape {
+                half_spread_bps: 1.,
+                level_step_bps: 1.,
+                levels: 5,
+                level_qty: 1.,
+                qty_growth: 1.,
+            },
+            dexes: vec![SyntheticDex {
+                venue: String::from("univ3"),
+                cost_bps: 5.,
+                lag_steps: 1,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_synthetic_snapshots() {
+        let market = make_market();
+        let snapshots = market.snapshots(&[2000., 2010.], 1000, 2000);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].timestamp_ms, 3000);
+
+        let book = &snapshots[1].binance.books[&suppported_markets::ETHUSDT.ticker()];
+        assert!((book.mid_price().unwrap() - 2010.).abs() < 1e-9);
+
+        // the DEX still quotes the previous mid
+        let dex_quote = &snapshots[1].dex_quotes[0];
+        assert!((dex_quote.amount_out - 20_000. * (1. - 5. / BPS)).abs() < 1e-9);
+
+        let mids = mid_prices(&snapshots, &suppported_markets::ETHUSDT);
+        assert_eq!(mids.len(), 2);
+        assert_eq!(mids[0].0, 1000);
+    }
+
+}